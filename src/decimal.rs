@@ -0,0 +1,494 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign};
+use std::str::FromStr;
+
+// 乘除法结果最多保留的小数位数
+const MAX_SCALE: u32 = 18;
+
+// 金额使用的十进制数: mantissa / 10^scale
+// 加减法是精确的, 保留源文件里写的小数位数, 只在展示的时候才四舍五入
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Decimal {
+    mantissa: i128,
+    scale: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseDecimalError {
+    pub message: String,
+}
+
+impl fmt::Display for ParseDecimalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Decimal {
+    pub const ZERO: Decimal = Decimal {
+        mantissa: 0,
+        scale: 0,
+    };
+
+    pub const fn new(mantissa: i128, scale: u32) -> Self {
+        Self { mantissa, scale }
+    }
+
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.mantissa == 0
+    }
+
+    pub fn is_positive(&self) -> bool {
+        self.mantissa > 0
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.mantissa < 0
+    }
+
+    pub fn abs(&self) -> Self {
+        Self::new(self.mantissa.abs(), self.scale)
+    }
+
+    // 四舍五入到 scale 位小数, 0.5 远离 0 进位
+    pub fn round(&self, scale: u32) -> Self {
+        if scale >= self.scale {
+            return *self;
+        }
+
+        let factor = pow10(self.scale - scale);
+        let quotient = self.mantissa / factor;
+        let remainder = self.mantissa % factor;
+        let carry = if remainder.abs() * 2 >= factor {
+            self.mantissa.signum()
+        } else {
+            0
+        };
+
+        Self::new(quotient + carry, scale)
+    }
+
+    // 去掉小数末尾的 0
+    pub fn normalize(&self) -> Self {
+        let mut result = *self;
+        while result.scale > 0 && result.mantissa % 10 == 0 {
+            result.mantissa /= 10;
+            result.scale -= 1;
+        }
+
+        result
+    }
+
+    // 乘以 10^exp, 小数位数超过 MAX_SCALE 或者溢出时返回 None
+    pub fn shift(&self, exp: i32) -> Option<Self> {
+        if exp < 0 {
+            let scale = self.scale + exp.unsigned_abs();
+            return (scale <= MAX_SCALE).then_some(Self::new(self.mantissa, scale));
+        }
+
+        let exp = exp as u32;
+        if exp <= self.scale {
+            return Some(Self::new(self.mantissa, self.scale - exp));
+        }
+        let factor = 10i128.checked_pow(exp - self.scale)?;
+        Some(Self::new(self.mantissa.checked_mul(factor)?, 0))
+    }
+
+    // f64 只保留 10 位小数, 用于份额 x 价格这类本来就不精确的计算结果
+    pub fn from_f64(value: f64) -> Self {
+        if !value.is_finite() {
+            return Self::ZERO;
+        }
+
+        format!("{:.10}", value)
+            .parse::<Decimal>()
+            .map(|d| d.normalize())
+            .unwrap_or_default()
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.mantissa as f64 / 10f64.powi(self.scale as i32)
+    }
+
+    // 溢出时返回 None, 和标准库整数的 checked_* 一样
+    pub fn checked_add(self, other: Decimal) -> Option<Decimal> {
+        let (a, b, scale) = self.aligned(other)?;
+        Some(Decimal::new(a.checked_add(b)?, scale))
+    }
+
+    pub fn checked_sub(self, other: Decimal) -> Option<Decimal> {
+        let (a, b, scale) = self.aligned(other)?;
+        Some(Decimal::new(a.checked_sub(b)?, scale))
+    }
+
+    // 小数位数超过 MAX_SCALE 时四舍五入; 溢出时先减少操作数的小数位数再计算
+    pub fn checked_mul(self, other: Decimal) -> Option<Decimal> {
+        let (mut a, mut b) = (self.normalize(), other.normalize());
+        loop {
+            if let Some(mantissa) = a.mantissa.checked_mul(b.mantissa) {
+                let product = Decimal::new(mantissa, a.scale + b.scale);
+                return Some(product.round(MAX_SCALE.min(product.scale)));
+            }
+            if a.scale == 0 && b.scale == 0 {
+                return None;
+            }
+            a = a.round(a.scale.saturating_sub(1));
+            b = b.round(b.scale.saturating_sub(1));
+        }
+    }
+
+    // 长除法, 结果最多保留 MAX_SCALE 位小数, 四舍五入之后去掉末尾的 0
+    // 除得尽或者再多一位就会溢出的时候提前停下, 所以商的整数部分很大时小数位数会少一些
+    // 除数为 0 或者商的整数部分溢出时返回 None
+    pub fn checked_div(self, other: Decimal) -> Option<Decimal> {
+        let (a, b, _) = self.aligned(other)?;
+        if b == 0 {
+            return None;
+        }
+
+        let negative = (a < 0) != (b < 0);
+        let (a, b) = (a.unsigned_abs(), b.unsigned_abs());
+        let limit = i128::MAX.unsigned_abs();
+        let mut mantissa = a / b;
+        let mut remainder = a % b;
+        let mut scale = 0;
+        while remainder != 0 && scale < MAX_SCALE {
+            let Some(shifted) = remainder.checked_mul(10) else {
+                break;
+            };
+            let next = mantissa
+                .checked_mul(10)
+                .and_then(|m| m.checked_add(shifted / b))
+                .filter(|m| *m <= limit);
+            let Some(next) = next else {
+                break;
+            };
+            mantissa = next;
+            remainder = shifted % b;
+            scale += 1;
+        }
+        // 剩下的余数不少于除数的一半时进位
+        if remainder >= b - remainder {
+            mantissa += 1;
+        }
+
+        let mantissa = i128::try_from(mantissa).ok()?;
+        let quotient = Decimal::new(if negative { -mantissa } else { mantissa }, scale);
+        Some(quotient.normalize())
+    }
+
+    fn rescale(&self, scale: u32) -> Option<i128> {
+        self.mantissa
+            .checked_mul(10i128.checked_pow(scale - self.scale)?)
+    }
+
+    fn aligned(self, other: Self) -> Option<(i128, i128, u32)> {
+        let scale = self.scale.max(other.scale);
+        Some((self.rescale(scale)?, other.rescale(scale)?, scale))
+    }
+}
+
+fn pow10(exp: u32) -> i128 {
+    10i128.pow(exp)
+}
+
+impl FromStr for Decimal {
+    type Err = ParseDecimalError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let error = || ParseDecimalError {
+            message: format!("Invalid number: {}", text),
+        };
+
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, text.strip_prefix('+').unwrap_or(text)),
+        };
+        let (integer, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        if integer.is_empty()
+            || !integer.chars().all(|c| c.is_ascii_digit())
+            || !fraction.chars().all(|c| c.is_ascii_digit())
+            || (digits.contains('.') && fraction.is_empty())
+            || fraction.len() > MAX_SCALE as usize
+        {
+            return Err(error());
+        }
+
+        let mantissa: i128 = format!("{}{}", integer, fraction)
+            .parse()
+            .map_err(|_| error())?;
+        Ok(Self::new(
+            if negative { -mantissa } else { mantissa },
+            fraction.len() as u32,
+        ))
+    }
+}
+
+impl fmt::Display for Decimal {
+    // 支持 {:.2} 这样的精度, 没有指定时按照自身的小数位数输出
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = match f.precision() {
+            Some(precision) => {
+                let precision = precision as u32;
+                let rounded = self.round(precision);
+                match rounded.rescale(precision) {
+                    Some(mantissa) => Self::new(mantissa, precision),
+                    None => rounded,
+                }
+            }
+            None => *self,
+        };
+
+        let digits = value.mantissa.unsigned_abs().to_string();
+        let scale = value.scale as usize;
+        let digits = format!("{:0>width$}", digits, width = scale + 1);
+        let (integer, fraction) = digits.split_at(digits.len() - scale);
+        let text = if scale == 0 {
+            integer.to_string()
+        } else {
+            format!("{}.{}", integer, fraction)
+        };
+        f.pad_integral(value.mantissa >= 0, "", &text)
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Decimal {}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match self.aligned(*other) {
+            Some((a, b, _)) => a.cmp(&b),
+            // 对齐小数位数时溢出的一方绝对值更大, 大小由它的符号决定
+            None if self.scale < other.scale => self.mantissa.cmp(&0),
+            None => 0.cmp(&other.mantissa),
+        }
+    }
+}
+
+// 方便和字面量比较, 比如 assert_eq!(summary.value, 5000.0)
+impl PartialEq<f64> for Decimal {
+    fn eq(&self, other: &f64) -> bool {
+        *self == Decimal::from_f64(*other)
+    }
+}
+
+impl From<i64> for Decimal {
+    fn from(value: i64) -> Self {
+        Self::new(value as i128, 0)
+    }
+}
+
+impl From<i32> for Decimal {
+    fn from(value: i32) -> Self {
+        Self::new(value as i128, 0)
+    }
+}
+
+impl Add for Decimal {
+    type Output = Decimal;
+
+    fn add(self, other: Decimal) -> Decimal {
+        self.checked_add(other).expect("Decimal addition overflow")
+    }
+}
+
+impl Sub for Decimal {
+    type Output = Decimal;
+
+    fn sub(self, other: Decimal) -> Decimal {
+        self.checked_sub(other)
+            .expect("Decimal subtraction overflow")
+    }
+}
+
+impl Neg for Decimal {
+    type Output = Decimal;
+
+    fn neg(self) -> Decimal {
+        Decimal::new(-self.mantissa, self.scale)
+    }
+}
+
+impl Mul for Decimal {
+    type Output = Decimal;
+
+    fn mul(self, other: Decimal) -> Decimal {
+        self.checked_mul(other)
+            .expect("Decimal multiplication overflow")
+    }
+}
+
+impl Div for Decimal {
+    type Output = Decimal;
+
+    // 除数为 0 时 panic
+    fn div(self, other: Decimal) -> Decimal {
+        if other.is_zero() {
+            panic!("Decimal division by zero");
+        }
+        self.checked_div(other).expect("Decimal division overflow")
+    }
+}
+
+impl AddAssign for Decimal {
+    fn add_assign(&mut self, other: Decimal) {
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for Decimal {
+    fn sub_assign(&mut self, other: Decimal) {
+        *self = *self - other;
+    }
+}
+
+impl Sum for Decimal {
+    fn sum<I: Iterator<Item = Decimal>>(iter: I) -> Decimal {
+        iter.fold(Decimal::ZERO, |a, b| a + b)
+    }
+}
+
+impl<'a> Sum<&'a Decimal> for Decimal {
+    fn sum<I: Iterator<Item = &'a Decimal>>(iter: I) -> Decimal {
+        iter.fold(Decimal::ZERO, |a, b| a + *b)
+    }
+}
+
+// JSON 里用字符串表示, 避免精度丢失; 读取时也接受数字
+#[cfg(feature = "serde")]
+impl serde::Serialize for Decimal {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Decimal {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct DecimalVisitor;
+
+        impl serde::de::Visitor<'_> for DecimalVisitor {
+            type Value = Decimal;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "a decimal string or number")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<Decimal, E> {
+                value.parse().map_err(E::custom)
+            }
+
+            fn visit_i64<E: serde::de::Error>(self, value: i64) -> Result<Decimal, E> {
+                Ok(Decimal::from(value))
+            }
+
+            fn visit_u64<E: serde::de::Error>(self, value: u64) -> Result<Decimal, E> {
+                Ok(Decimal::new(value as i128, 0))
+            }
+
+            fn visit_f64<E: serde::de::Error>(self, value: f64) -> Result<Decimal, E> {
+                Ok(Decimal::from_f64(value))
+            }
+        }
+
+        deserializer.deserialize_any(DecimalVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn d(text: &str) -> Decimal {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn test_sum_has_no_drift() {
+        let total: Decimal = (0..1000).map(|_| d("3000.10")).sum();
+        assert_eq!(total.to_string(), "3000100.00");
+
+        let float: f64 = (0..1000).map(|_| 3000.10).sum();
+        assert_ne!(float, 3000100.0);
+    }
+
+    #[test]
+    fn test_parse_and_display() {
+        assert_eq!(d("-0.05").to_string(), "-0.05");
+        assert_eq!(d("+12").to_string(), "12");
+        assert_eq!(format!("{:.2}", d("2.005")), "2.01");
+        assert_eq!(format!("{:.2}", d("-2.005")), "-2.01");
+        assert_eq!(format!("{:.1}", d("3")), "3.0");
+        assert_eq!(format!("{:>6}", d("1.5")), "   1.5");
+        assert!("1.".parse::<Decimal>().is_err());
+        assert!("1e5".parse::<Decimal>().is_err());
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(d("1.10") + d("2.2"), d("3.3"));
+        assert_eq!(d("1") - d("1.5"), d("-0.5"));
+        assert_eq!(d("1.5") * d("2.5"), d("3.75"));
+        assert_eq!((d("10") / d("3")).round(4), d("3.3333"));
+        assert_eq!(Decimal::from_f64(0.1 + 0.2), d("0.3"));
+        assert!(d("1.00") == 1.0);
+        assert!(d("-1") < Decimal::ZERO);
+        assert_eq!(d("-7") / d("2"), d("-3.5"));
+        assert_eq!(d("1000000") * (d("1") / d("3")), d("333333.333333333333"));
+    }
+
+    #[test]
+    fn test_checked_arithmetic() {
+        let big = d("1").shift(36).unwrap();
+        assert_eq!(big.checked_mul(big), None);
+        assert_eq!(big.shift(2).unwrap().checked_div(d("0.5")), None);
+        assert_eq!(big.checked_add(d("0.000001")), None);
+        assert_eq!(big.checked_sub(d("0.000001")), None);
+        assert_eq!(d("1").checked_div(Decimal::ZERO), None);
+        assert_eq!(d("1.5").checked_add(d("2")), Some(d("3.5")));
+        assert_eq!(d("7").checked_div(d("2")), Some(d("3.5")));
+
+        // 商很大的时候只保留放得下的小数位数
+        assert_eq!(
+            d("1").shift(20).unwrap().checked_div(d("0.5")),
+            d("2").shift(20)
+        );
+        assert_eq!(
+            big.checked_div(d("3")).unwrap().to_string(),
+            "333333333333333333333333333333333333.33"
+        );
+        assert_eq!(d("2").checked_div(d("3")), Some(d("0.666666666666666667")));
+
+        // 对齐小数位数溢出的时候仍然可以比较大小
+        assert!(big > d("0.000001"));
+        assert!(-big < d("-0.000001"));
+        assert!(d("0.000001") < big);
+    }
+
+    #[test]
+    fn test_shift() {
+        assert_eq!(d("9").shift(-2), Some(d("0.09")));
+        assert_eq!(d("1.5").shift(6), Some(d("1500000")));
+        assert_eq!(d("1.25").shift(1).unwrap().to_string(), "12.5");
+        assert_eq!(d("1").shift(-19), None);
+        assert_eq!(d("1").shift(40), None);
+    }
+}
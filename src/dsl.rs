@@ -1,5 +1,7 @@
 pub mod ast;
+pub mod formatter;
 pub mod lexer;
+pub mod loader;
 pub mod parser;
 pub mod token;
 
@@ -1,30 +1,106 @@
+use crate::decimal::Decimal;
+use std::collections::BTreeMap;
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "snake_case"))]
+// 账本里绝大多数语句都是 Record, 不值得为了其他语句把它装箱
+#[allow(clippy::large_enum_variant)]
 pub enum Statement {
     Record(Record),
     Plan(Plan),
     Define(Define),
     Portfolio(Portfolio),
+    Import(Import),
+    Include(Include),
+    Let(Let),
 }
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Record {
     pub date: String,
     pub action: Action,
     pub details: Details,
     pub note: Option<String>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Metadata::is_empty")
+    )]
+    pub metadata: Metadata,
+}
+
+// TAG 和 META 附加的结构化信息, 标签不带前面的 '#'
+#[derive(Debug, PartialEq, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Metadata {
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub tags: Vec<String>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "BTreeMap::is_empty")
+    )]
+    pub meta: BTreeMap<String, String>,
+}
+
+impl Metadata {
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.meta.is_empty()
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        let tag = tag.strip_prefix('#').unwrap_or(tag);
+        self.tags.iter().any(|t| t == tag)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&String> {
+        self.meta.get(key)
+    }
+
+    // 合并另一份元信息, 重复的标签只保留一个, 相同的 key 以 other 为准
+    pub fn merge(&mut self, other: &Metadata) {
+        for tag in other.tags.iter() {
+            if !self.tags.contains(tag) {
+                self.tags.push(tag.clone());
+            }
+        }
+        for (key, value) in other.meta.iter() {
+            self.meta.insert(key.clone(), value.clone());
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum Action {
     Trade,
     Mark,
+    Fee,
+    Tax,
+    Split,
+    Merge,
+    Transfer,
+    Deposit,
+    Withdraw,
+    Price,
 }
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "snake_case"))]
 pub enum Details {
     Trade(TradeDetails),
     Mark(MarkDetails),
+    Fee(FeeDetails),
+    Split(SplitDetails),
+    Merge(MergeDetails),
+    Transfer(TransferDetails),
+    Cash(CashDetails),
+    Price(PriceDetails),
 }
 
 impl Details {
@@ -32,16 +108,38 @@ impl Details {
         match self {
             Details::Trade(trade) => &trade.symbol,
             Details::Mark(mark) => &mark.symbol,
+            Details::Fee(fee) => &fee.symbol,
+            Details::Split(split) => &split.symbol,
+            Details::Merge(merge) => &merge.symbol,
+            Details::Transfer(transfer) => &transfer.symbol,
+            Details::Cash(cash) => &cash.symbol,
+            Details::Price(price) => &price.symbol,
+        }
+    }
+
+    // 同时影响两个资产的记录, 返回另一个资产
+    pub fn get_target(&self) -> Option<&Symbol> {
+        match self {
+            Details::Merge(merge) => Some(&merge.target),
+            Details::Transfer(transfer) => Some(&transfer.target),
+            Details::Trade(trade) => trade.account.as_ref(),
+            _ => None,
         }
     }
 }
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TradeDetails {
     pub symbol: Symbol,
     pub signed_amount: SignedAmount,
     pub unit: String,
-    pub price: Option<f64>,
+    pub price: Option<Decimal>,
+    // 交易的手续费和税费, 不计入交易金额
+    pub fee: Option<Charge>,
+    pub tax: Option<Charge>,
+    // 资金来源或者去向的现金账户
+    pub account: Option<Symbol>,
 }
 
 impl TradeDetails {
@@ -54,20 +152,127 @@ impl TradeDetails {
     }
 }
 
+// 手续费或者税费
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Charge {
+    pub amount: Decimal,
+    pub unit: String,
+}
+
+impl Charge {
+    pub fn new(amount: Decimal, unit: String) -> Self {
+        Self { amount, unit }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MarkDetails {
     pub symbol: Symbol,
-    pub value: f64,
+    pub value: Decimal,
+    pub unit: String,
+}
+
+// 单独记录的费用, FEE 或者 TAX 由 Record.action 区分
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FeeDetails {
+    pub symbol: Symbol,
+    pub charge: Charge,
+}
+
+// 拆分或者合并份额, ratio 是每一份旧份额对应的新份额数
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SplitDetails {
+    pub symbol: Symbol,
+    pub ratio: Decimal,
+}
+
+// 把 symbol 的持仓全部转换成 target, 改名时 ratio 为 1
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MergeDetails {
+    pub symbol: Symbol,
+    pub target: Symbol,
+    pub ratio: Decimal,
+}
+
+// 资产之间的内部转换, 不算作新的投入或者转出
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TransferDetails {
+    pub symbol: Symbol,
+    pub target: Symbol,
+    pub amount: Decimal,
+    pub unit: String,
+    // 转入目标资产的成交价格
+    pub price: Option<Decimal>,
+}
+
+// 现金账户的存入和取出, DEPOSIT 或者 WITHDRAW 由 Record.action 区分
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CashDetails {
+    pub symbol: Symbol,
+    pub amount: Decimal,
+    pub unit: String,
+}
+
+// 单位价格, 持仓价值按照 份额 x 最新价格 计算
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PriceDetails {
+    pub symbol: Symbol,
+    pub price: Decimal,
     pub unit: String,
 }
 
+// 从外部文件导入数据, 相对路径由引擎按照 base_dir 解析, dsl::loader 会换成绝对路径
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Import {
+    pub kind: ImportKind,
+    pub path: String,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ImportKind {
+    Prices,
+}
+
+// 引入另一个账本文件, 由 loader 展开, 路径相对于当前文件
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Include {
+    pub path: String,
+}
+
+// 命名常量, 引用的地方在解析时已经替换成了数值
+// 从定义的位置开始可见, INCLUDE 进来的文件也能看到, 同一个名字不能重复定义
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Let {
+    pub name: String,
+    pub value: Decimal,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Plan {
     pub name: String,
     pub rules: Vec<PlanRule>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(tag = "type", content = "value", rename_all = "snake_case")
+)]
 pub enum PlanRule {
     Schedule(Schedule),
     StartDate(String),
@@ -75,14 +280,17 @@ pub enum PlanRule {
 }
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Schedule {
     pub frequency: Frequency,
-    pub amount: f64,
+    pub amount: Decimal,
     pub unit: String,
     pub target: Symbol,
 }
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum Frequency {
     Daily,
     Weekly,
@@ -91,30 +299,157 @@ pub enum Frequency {
     Yearly,
 }
 
+// 大类资产
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum AssetClass {
+    Equity,
+    Bond,
+    Cash,
+    Commodity,
+}
+
+impl AssetClass {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_uppercase().as_str() {
+            "EQUITY" => Some(AssetClass::Equity),
+            "BOND" => Some(AssetClass::Bond),
+            "CASH" => Some(AssetClass::Cash),
+            "COMMODITY" => Some(AssetClass::Commodity),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for AssetClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            AssetClass::Equity => "EQUITY",
+            AssetClass::Bond => "BOND",
+            AssetClass::Cash => "CASH",
+            AssetClass::Commodity => "COMMODITY",
+        };
+        write!(f, "{}", name)
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Define {
     pub symbol: Symbol,
     pub alias: Option<String>,
     pub target_return: Option<f64>,
+    pub class: Option<AssetClass>,
+    // 自定义的分类, 比如 "宽基", "行业"
+    pub category: Option<String>,
+    // 用来比较收益的基准, 比如指数
+    pub benchmark: Option<Symbol>,
+    pub currency: Option<String>,
+    // 风险等级, 1 到 5
+    pub risk: Option<u8>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Metadata::is_empty")
+    )]
+    pub metadata: Metadata,
 }
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Portfolio {
     pub name: String,
-    pub assets: Vec<Symbol>,
+    // ASSETS 里的成员, 保持书写的顺序
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub members: Vec<PortfolioMember>,
     pub target_return: Option<f64>,
+    pub rebalance: Option<RebalancePolicy>,
 }
 
+impl Portfolio {
+    // 直接列出的资产
+    pub fn symbols(&self) -> impl Iterator<Item = &Symbol> {
+        self.members.iter().filter_map(|member| match &member.kind {
+            MemberKind::Symbol(symbol) => Some(symbol),
+            MemberKind::Portfolio(_) => None,
+        })
+    }
+
+    // 包含的其他组合的名字
+    pub fn portfolios(&self) -> impl Iterator<Item = &String> {
+        self.members.iter().filter_map(|member| match &member.kind {
+            MemberKind::Symbol(_) => None,
+            MemberKind::Portfolio(name) => Some(name),
+        })
+    }
+
+    pub fn has_weights(&self) -> bool {
+        has_weights(&self.members)
+    }
+}
+
+// 每个成员都设置了目标权重, 解析器和引擎都用它判断组合有没有目标权重
+pub fn has_weights(members: &[PortfolioMember]) -> bool {
+    !members.is_empty() && members.iter().all(|m| m.weight.is_some())
+}
+
+// 组合的一个成员和它的目标权重
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PortfolioMember {
+    pub kind: MemberKind,
+    // 目标权重, 所有成员合计为 1; 没有设置权重的时候为 None
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub weight: Option<Decimal>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(tag = "type", content = "value", rename_all = "snake_case")
+)]
+pub enum MemberKind {
+    Symbol(Symbol),
+    // 其他组合的名字
+    Portfolio(String),
+}
+
+// 组合的再平衡策略
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum RebalancePolicy {
+    // 每个周期开始的时候再平衡, 比如 REBALANCE QUARTERLY
+    Calendar(Frequency),
+    // 任意一个资产的权重偏离目标超过这个值的时候再平衡, 比如 REBALANCE DRIFT 5% 是 0.05
+    Drift(Decimal),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Symbol {
     pub namespace: String,
     pub name: String,
 }
 
+// 现金账户使用的命名空间
+pub const CASH_NAMESPACE: &str = "CASH";
+
 impl Symbol {
     pub fn new(namespace: String, name: String) -> Self {
         Self { namespace, name }
     }
+
+    pub fn is_cash(&self) -> bool {
+        self.namespace == CASH_NAMESPACE
+    }
 }
 
 impl std::fmt::Display for Symbol {
@@ -124,41 +459,67 @@ impl std::fmt::Display for Symbol {
 }
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SignedAmount {
     pub sign: Sign,
-    pub value: f64,
+    pub value: Decimal,
 }
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum Sign {
     Positive,
     Negative,
 }
 
 impl SignedAmount {
-    pub fn new(sign: Sign, value: f64) -> Self {
+    pub fn new(sign: Sign, value: Decimal) -> Self {
         Self { sign, value }
     }
 
-    pub fn positive(value: f64) -> Self {
+    pub fn positive(value: Decimal) -> Self {
         Self::new(Sign::Positive, value)
     }
 
-    pub fn negative(value: f64) -> Self {
+    pub fn negative(value: Decimal) -> Self {
         Self::new(Sign::Negative, value)
     }
 
-    pub fn to_f64(&self) -> f64 {
+    pub fn to_decimal(&self) -> Decimal {
         match self.sign {
             Sign::Positive => self.value,
             Sign::Negative => -self.value,
         }
     }
+
+    pub fn to_f64(&self) -> f64 {
+        self.to_decimal().to_f64()
+    }
 }
 
+// 语句来自哪个文件的哪一行, 用于错误提示
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Origin {
+    pub file: Option<std::path::PathBuf>,
+    pub line: usize,
+}
+
+impl std::fmt::Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{}:{}", file.display(), self.line),
+            None => write!(f, "line {}", self.line),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Program {
-    pub statements: Vec<Statement>,
+    // 每条语句和它的来源位置, 没有位置信息的时候为 None
+    pub statements: Vec<(Statement, Option<Origin>)>,
 }
 
 impl Program {
@@ -169,7 +530,33 @@ impl Program {
     }
 
     pub fn add_statement(&mut self, statement: Statement) {
-        self.statements.push(statement);
+        self.statements.push((statement, None));
+    }
+
+    pub fn add_statement_with_origin(&mut self, statement: Statement, origin: Origin) {
+        self.statements.push((statement, Some(origin)));
+    }
+
+    pub fn statement(&self, index: usize) -> Option<&Statement> {
+        self.statements.get(index).map(|(statement, _)| statement)
+    }
+
+    pub fn origin(&self, index: usize) -> Option<&Origin> {
+        self.statements
+            .get(index)
+            .and_then(|(_, origin)| origin.as_ref())
+    }
+}
+
+// 位置信息不参与比较, 同样的内容从不同的文件读进来也是相等的
+impl PartialEq for Program {
+    fn eq(&self, other: &Self) -> bool {
+        self.statements.len() == other.statements.len()
+            && self
+                .statements
+                .iter()
+                .zip(other.statements.iter())
+                .all(|((a, _), (b, _))| a == b)
     }
 }
 
@@ -191,10 +578,10 @@ mod tests {
 
     #[test]
     fn test_signed_amount() {
-        let positive = SignedAmount::positive(100.0);
+        let positive = SignedAmount::positive(Decimal::from(100));
         assert_eq!(positive.to_f64(), 100.0);
 
-        let negative = SignedAmount::negative(50.0);
+        let negative = SignedAmount::negative(Decimal::from(50));
         assert_eq!(negative.to_f64(), -50.0);
     }
 
@@ -207,11 +594,15 @@ mod tests {
             action: Action::Trade,
             details: Details::Trade(TradeDetails {
                 symbol: Symbol::new("ETF".to_string(), "510300".to_string()),
-                signed_amount: SignedAmount::positive(5000.0),
+                signed_amount: SignedAmount::positive(Decimal::from(5000)),
                 unit: "CNY".to_string(),
-                price: Some(4.56),
+                price: Some(Decimal::from_f64(4.56)),
+                fee: Some(Charge::new(Decimal::from(5), "CNY".to_string())),
+                tax: None,
+                account: None,
             }),
             note: Some("Test trade".to_string()),
+            metadata: Metadata::default(),
         };
 
         program.add_statement(Statement::Record(record));
@@ -225,7 +616,7 @@ mod tests {
             rules: vec![
                 PlanRule::Schedule(Schedule {
                     frequency: Frequency::Monthly,
-                    amount: 3000.0,
+                    amount: Decimal::from(3000),
                     unit: "CNY".to_string(),
                     target: Symbol::new("ETF".to_string(), "510300".to_string()),
                 }),
@@ -244,6 +635,12 @@ mod tests {
             symbol: Symbol::new("ETF".to_string(), "510300".to_string()),
             alias: Some("沪深300ETF".to_string()),
             target_return: Some(0.09),
+            class: Some(AssetClass::Equity),
+            category: None,
+            benchmark: None,
+            currency: None,
+            risk: None,
+            metadata: Metadata::default(),
         };
 
         assert_eq!(define.symbol.namespace, "ETF");
@@ -256,15 +653,31 @@ mod tests {
     fn test_portfolio_creation() {
         let portfolio = Portfolio {
             name: "ETF Portfolio".to_string(),
-            assets: vec![
-                Symbol::new("ETF".to_string(), "510300".to_string()),
-                Symbol::new("ETF".to_string(), "159915".to_string()),
+            members: vec![
+                PortfolioMember {
+                    kind: MemberKind::Symbol(Symbol::new("ETF".to_string(), "510300".to_string())),
+                    weight: None,
+                },
+                PortfolioMember {
+                    kind: MemberKind::Portfolio("债券".to_string()),
+                    weight: None,
+                },
             ],
             target_return: Some(0.09),
+            rebalance: None,
         };
 
-        assert_eq!(portfolio.assets.len(), 2);
+        assert_eq!(portfolio.symbols().count(), 1);
+        assert_eq!(portfolio.portfolios().collect::<Vec<_>>(), vec!["债券"]);
+        assert!(!portfolio.has_weights());
         assert_eq!(portfolio.name, "ETF Portfolio");
+
+        // 只有部分成员有权重的时候不算有目标权重
+        let mut mixed = portfolio.clone();
+        mixed.members[0].weight = Some("0.5".parse().unwrap());
+        assert!(!mixed.has_weights());
+        mixed.members[1].weight = Some("0.5".parse().unwrap());
+        assert!(mixed.has_weights());
         assert_eq!(portfolio.target_return, Some(0.09));
     }
 }
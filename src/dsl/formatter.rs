@@ -0,0 +1,341 @@
+use super::ast::*;
+use super::token::Token;
+
+// 把 AST 重新输出成 DSL 文本, 输出的结果可以再次被 Parser 解析
+
+pub fn format_program(program: &Program) -> String {
+    let mut output = String::new();
+    for (statement, _) in program.statements.iter() {
+        output.push_str(&format_statement(statement));
+        output.push('\n');
+    }
+
+    output
+}
+
+pub fn format_statement(statement: &Statement) -> String {
+    match statement {
+        Statement::Record(record) => format_record(record),
+        Statement::Plan(plan) => format_plan(plan),
+        Statement::Define(define) => format_define(define),
+        Statement::Portfolio(portfolio) => format_portfolio(portfolio),
+        Statement::Import(import) => format_import(import),
+        Statement::Include(include) => format!("INCLUDE {}", format_string(&include.path)),
+        Statement::Let(constant) => format!("LET {} = {}", constant.name, constant.value),
+    }
+}
+
+pub fn format_record(record: &Record) -> String {
+    let mut line = format!(
+        "{} {} {}",
+        record.date,
+        format_action(&record.action),
+        format_details(&record.details)
+    );
+
+    for meta in format_metadata(&record.metadata) {
+        line.push_str(&format!("\n  {}", meta));
+    }
+    if let Some(note) = &record.note {
+        line.push_str(&format!("\n  NOTE {}", format_string(note)));
+    }
+
+    line
+}
+
+pub fn format_action(action: &Action) -> &'static str {
+    match action {
+        Action::Trade => "TRADE",
+        Action::Mark => "MARK",
+        Action::Fee => "FEE",
+        Action::Tax => "TAX",
+        Action::Split => "SPLIT",
+        Action::Merge => "MERGE",
+        Action::Transfer => "TRANSFER",
+        Action::Deposit => "DEPOSIT",
+        Action::Withdraw => "WITHDRAW",
+        Action::Price => "PRICE",
+    }
+}
+
+fn format_details(details: &Details) -> String {
+    match details {
+        Details::Trade(trade) => {
+            let sign = match trade.signed_amount.sign {
+                Sign::Positive => "+",
+                Sign::Negative => "-",
+            };
+            let mut text = format!(
+                "{} {}{} {}",
+                trade.symbol, sign, trade.signed_amount.value, trade.unit
+            );
+            if let Some(price) = trade.price {
+                text.push_str(&format!(" @ {}", price));
+            }
+            if let Some(account) = &trade.account {
+                text.push_str(&format!(" VIA {}", account));
+            }
+            if let Some(fee) = &trade.fee {
+                text.push_str(&format!(" FEE {}", format_charge(fee)));
+            }
+            if let Some(tax) = &trade.tax {
+                text.push_str(&format!(" TAX {}", format_charge(tax)));
+            }
+            text
+        }
+        Details::Mark(mark) => format!("{} VALUE {} {}", mark.symbol, mark.value, mark.unit),
+        Details::Fee(fee) => format!("{} {}", fee.symbol, format_charge(&fee.charge)),
+        Details::Split(split) => format!("{} {}", split.symbol, split.ratio),
+        Details::Merge(merge) => {
+            format!("{} INTO {} {}", merge.symbol, merge.target, merge.ratio)
+        }
+        Details::Transfer(transfer) => {
+            let mut text = format!(
+                "{} -> {} {} {}",
+                transfer.symbol, transfer.target, transfer.amount, transfer.unit
+            );
+            if let Some(price) = transfer.price {
+                text.push_str(&format!(" @ {}", price));
+            }
+            text
+        }
+        Details::Cash(cash) => format!("{} {} {}", cash.symbol, cash.amount, cash.unit),
+        Details::Price(price) => format!("{} {} {}", price.symbol, price.price, price.unit),
+    }
+}
+
+fn format_charge(charge: &Charge) -> String {
+    format!("{} {}", charge.amount, charge.unit)
+}
+
+fn format_plan(plan: &Plan) -> String {
+    let mut lines = vec![format!("PLAN {}", format_string(&plan.name))];
+    for rule in plan.rules.iter() {
+        let line = match rule {
+            PlanRule::Schedule(schedule) => format!(
+                "SCHEDULE {} {} {} INTO {}",
+                format_frequency(&schedule.frequency),
+                schedule.amount,
+                schedule.unit,
+                schedule.target
+            ),
+            PlanRule::StartDate(date) => format!("START {}", date),
+            PlanRule::EndDate(date) => format!("END_DATE {}", date),
+        };
+        lines.push(format!("  {}", line));
+    }
+    lines.push("END".to_string());
+
+    lines.join("\n")
+}
+
+pub fn format_frequency(frequency: &Frequency) -> &'static str {
+    match frequency {
+        Frequency::Daily => "DAILY",
+        Frequency::Weekly => "WEEKLY",
+        Frequency::Monthly => "MONTHLY",
+        Frequency::Quarterly => "QUARTERLY",
+        Frequency::Yearly => "YEARLY",
+    }
+}
+
+fn format_define(define: &Define) -> String {
+    let mut lines = vec![format!("DEFINE {}", define.symbol)];
+    if let Some(alias) = &define.alias {
+        lines.push(format!("  ALIAS {}", format_string(alias)));
+    }
+    if let Some(target_return) = define.target_return {
+        lines.push(format!("  TARGET RETURN {}", target_return));
+    }
+    if let Some(class) = define.class {
+        lines.push(format!("  CLASS {}", class));
+    }
+    if let Some(category) = &define.category {
+        lines.push(format!("  CATEGORY {}", format_string(category)));
+    }
+    if let Some(benchmark) = &define.benchmark {
+        lines.push(format!("  BENCHMARK {}", benchmark));
+    }
+    if let Some(currency) = &define.currency {
+        lines.push(format!("  CURRENCY {}", currency));
+    }
+    if let Some(risk) = define.risk {
+        lines.push(format!("  RISK {}", risk));
+    }
+    for meta in format_metadata(&define.metadata) {
+        lines.push(format!("  {}", meta));
+    }
+    lines.push("END".to_string());
+
+    lines.join("\n")
+}
+
+// 每个 META 单独一行, 所有标签写在一行 TAG 里
+fn format_metadata(metadata: &Metadata) -> Vec<String> {
+    let mut lines = Vec::new();
+    if !metadata.tags.is_empty() {
+        let tags: Vec<String> = metadata.tags.iter().map(|t| format!("#{}", t)).collect();
+        lines.push(format!("TAG {}", tags.join(" ")));
+    }
+    for (key, value) in metadata.meta.iter() {
+        lines.push(format!(
+            "META {} {}",
+            format_meta_key(key),
+            format_string(value)
+        ));
+    }
+
+    lines
+}
+
+// 不是合法标识符或者和关键字重名的 key 需要加引号
+fn format_meta_key(key: &str) -> String {
+    let mut chars = key.chars();
+    let is_identifier = chars
+        .next()
+        .is_some_and(|ch| ch.is_ascii_alphabetic() || ch == '_')
+        && chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_');
+    // END_ 开头的词会被当成 END_DATE 这类复合关键字
+    let compound = key.to_uppercase().starts_with("END_");
+    if is_identifier && !compound && Token::from_keyword(key).is_none() {
+        key.to_string()
+    } else {
+        format_string(key)
+    }
+}
+
+fn format_portfolio(portfolio: &Portfolio) -> String {
+    let mut lines = vec![format!("PORTFOLIO {}", format_string(&portfolio.name))];
+    if !portfolio.members.is_empty() {
+        let assets: Vec<String> = portfolio
+            .members
+            .iter()
+            .map(|member| {
+                let name = match &member.kind {
+                    MemberKind::Symbol(symbol) => symbol.to_string(),
+                    MemberKind::Portfolio(name) => format_string(name),
+                };
+                match member.weight {
+                    Some(weight) => format!("{} {}%", name, weight.shift(2).unwrap_or(weight)),
+                    None => name,
+                }
+            })
+            .collect();
+        lines.push(format!("  ASSETS {}", assets.join(", ")));
+    }
+    if let Some(target_return) = portfolio.target_return {
+        lines.push(format!("  TARGET RETURN {}", target_return));
+    }
+    match &portfolio.rebalance {
+        Some(RebalancePolicy::Calendar(frequency)) => {
+            lines.push(format!("  REBALANCE {}", format_frequency(frequency)));
+        }
+        Some(RebalancePolicy::Drift(threshold)) => {
+            lines.push(format!(
+                "  REBALANCE DRIFT {}%",
+                threshold.shift(2).unwrap_or(*threshold)
+            ));
+        }
+        None => {}
+    }
+    lines.push("END".to_string());
+
+    lines.join("\n")
+}
+
+fn format_import(import: &Import) -> String {
+    let kind = match import.kind {
+        ImportKind::Prices => "PRICES",
+    };
+
+    format!("IMPORT {} {}", kind, format_string(&import.path))
+}
+
+fn format_string(value: &str) -> String {
+    let mut text = String::from('"');
+    for ch in value.chars() {
+        match ch {
+            '"' => text.push_str("\\\""),
+            '\\' => text.push_str("\\\\"),
+            '\n' => text.push_str("\\n"),
+            '\t' => text.push_str("\\t"),
+            '\r' => text.push_str("\\r"),
+            ch => text.push(ch),
+        }
+    }
+    text.push('"');
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsl::{Lexer, Parser};
+
+    fn parse_input(input: &str) -> Program {
+        let tokens = Lexer::new(input).tokenize().unwrap();
+        Parser::new(tokens).parse().unwrap()
+    }
+
+    #[test]
+    fn test_format_round_trip() {
+        let input = r#"
+        DEFINE ETF:510300
+            ALIAS "沪深300 \"ETF\""
+            TARGET RETURN 0.09
+            CLASS equity
+            CATEGORY "宽基"
+            BENCHMARK INDEX:000300
+            CURRENCY CNY
+            RISK 4
+            TAG #core
+            META "value" "x"
+        END
+
+        PORTFOLIO "ETF 长期投资"
+            ASSETS ETF:510300 60%, ETF:159915 0.4
+            REBALANCE DRIFT 5%
+        END
+
+        PORTFOLIO "家庭资产"
+            ASSETS "ETF 长期投资" 70%, CASH:BANK 30%
+        END
+
+        LET BUDGET = 3000
+        PLAN "2024年定投计划"
+            SCHEDULE MONTHLY BUDGET CNY INTO ETF:510300
+            START 2024-01-01
+        END
+
+        IMPORT PRICES "prices.csv"
+        INCLUDE "2023.cash"
+
+        2024-01-01 DEPOSIT CASH:BROKER 10000 CNY
+        2024-01-02 TRADE ETF:510300 +5000 CNY @ 4.56 VIA CASH:BROKER FEE 5 CNY TAX 1 CNY
+            NOTE "新年第一笔定投"
+            TAG #定投 #bonus
+            META broker "华泰"
+        2024-03-01 TRADE ETF:510300 -2000 CNY
+        2024-03-31 MARK ETF:510300 VALUE 7200 CNY
+        2024-04-01 TRANSFER ETF:510300 -> ETF:159915 1000 CNY @ 2.5
+        2024-05-01 SPLIT ETF:510300 2
+        2024-06-01 MERGE ETF:159915 INTO ETF:159949 0.25
+        2024-06-30 FEE ETF:510300 3 CNY
+        2024-06-30 PRICE ETF:510300 4.61 CNY
+        "#;
+
+        let program = parse_input(input);
+        let text = format_program(&program);
+
+        assert!(text.contains("2024-01-02 TRADE ETF:510300 +5000 CNY @ 4.56 VIA CASH:BROKER"));
+        assert!(text.contains("LET BUDGET = 3000\n"));
+        assert!(text.contains("  ASSETS ETF:510300 60%, ETF:159915 40%\n"));
+        assert!(text.contains("  REBALANCE DRIFT 5%\n"));
+        // 子组合和资产保持原来的顺序, 权重跟着各自的成员
+        assert!(text.contains("  ASSETS \"ETF 长期投资\" 70%, CASH:BANK 30%\n"));
+        assert!(text.contains("  TAG #定投 #bonus\n  META broker \"华泰\"\n  NOTE"));
+        assert!(text.contains("  META \"value\" \"x\"\n"));
+        assert_eq!(parse_input(&text), program);
+    }
+}
@@ -1,4 +1,5 @@
-use super::token::Token;
+use super::token::{Span, Token};
+use crate::decimal::Decimal;
 
 #[derive(Debug)]
 pub struct LexError {
@@ -11,6 +12,8 @@ pub struct Lexer {
     position: usize,
     current_line: usize,
     current_column: usize,
+    // 紧跟在 TAG 后面的 '#' 是标签而不是注释
+    in_tags: bool,
 }
 
 impl Lexer {
@@ -20,11 +23,18 @@ impl Lexer {
             position: 0,
             current_line: 1,
             current_column: 1,
+            in_tags: false,
         }
     }
 
     pub fn tokenize(&mut self) -> Result<Vec<Token>, LexError> {
+        self.tokenize_with_spans().map(|(tokens, _)| tokens)
+    }
+
+    // 同时返回每个 token 的位置, spans 和 tokens 一一对应
+    pub fn tokenize_with_spans(&mut self) -> Result<(Vec<Token>, Vec<Span>), LexError> {
         let mut tokens = Vec::new();
+        let mut spans = Vec::new();
         
         while !self.is_at_end() {
             self.skip_whitespace();
@@ -33,14 +43,25 @@ impl Lexer {
                 break;
             }
             
+            let span = self.current_span();
             let token = self.scan_token()?;
+            self.in_tags = matches!(token, Token::Tag | Token::HashTag(_));
             if !matches!(token, Token::Newline) {
                 tokens.push(token);
+                spans.push(span);
             }
         }
         
+        spans.push(self.current_span());
         tokens.push(Token::Eof);
-        Ok(tokens)
+        Ok((tokens, spans))
+    }
+
+    fn current_span(&self) -> Span {
+        Span {
+            line: self.current_line,
+            column: self.current_column,
+        }
     }
 
     fn is_at_end(&self) -> bool {
@@ -55,12 +76,8 @@ impl Lexer {
         }
     }
 
-    fn peek_next(&self) -> char {
-        if self.position + 1 >= self.input.len() {
-            '\0'
-        } else {
-            self.input[self.position + 1]
-        }
+    fn peek_at(&self, offset: usize) -> char {
+        self.input.get(self.position + offset).copied().unwrap_or('\0')
     }
 
     fn advance(&mut self) -> char {
@@ -97,8 +114,15 @@ impl Lexer {
         match ch {
             '\n' => Ok(Token::Newline),
             '+' => Ok(Token::Plus),
+            '-' if self.peek() == '>' => {
+                self.advance(); // consume '>'
+                Ok(Token::Arrow)
+            }
             '-' => Ok(Token::Minus),
+            '*' => Ok(Token::Star),
+            '/' => Ok(Token::Slash),
             '@' => Ok(Token::At),
+            '=' => Ok(Token::Equals),
             ':' => Ok(Token::Colon),
             ',' => Ok(Token::Comma),
             '(' => Ok(Token::LeftParen),
@@ -106,6 +130,7 @@ impl Lexer {
             '{' => Ok(Token::LeftBrace),
             '}' => Ok(Token::RightBrace),
             '"' => self.scan_string(),
+            '#' if self.in_tags && is_tag_char(self.peek()) => self.scan_hashtag(),
             '#' => self.scan_comment(),
             '0'..='9' => {
                 self.position -= 1; // Back up
@@ -176,35 +201,14 @@ impl Lexer {
         Ok(Token::Comment(comment))
     }
 
-    fn scan_number(&mut self) -> Result<Token, LexError> {
-        let mut has_dot = false;
-        let start_pos = self.position;
-        let is_negative = self.peek() == '-';
+    fn scan_hashtag(&mut self) -> Result<Token, LexError> {
+        let mut tag = String::new();
         
-        if is_negative {
-            self.advance();
+        while !self.is_at_end() && is_tag_char(self.peek()) {
+            tag.push(self.advance());
         }
         
-        while !self.is_at_end() {
-            match self.peek() {
-                '0'..='9' => { self.advance(); }
-                '.' if !has_dot => {
-                    has_dot = true;
-                    self.advance();
-                }
-                _ => break,
-            }
-        }
-        
-        let number_str: String = self.input[start_pos..self.position].iter().collect();
-        
-        match number_str.parse::<f64>() {
-            Ok(num) => Ok(Token::Number(num)),
-            Err(_) => Err(LexError {
-                message: format!("Invalid number: {}", number_str),
-                position: start_pos,
-            }),
-        }
+        Ok(Token::HashTag(tag))
     }
 
     fn scan_number_or_date(&mut self) -> Result<Token, LexError> {
@@ -243,22 +247,135 @@ impl Lexer {
             }
         }
         
-        // Not a date, check if it's a number
+        // Not a date, go back to the end of the digits and scan the rest of the number
+        // 比如 5000-1200 里的 '-' 是减号
+        if self.input[start_pos..self.position].contains(&'-') {
+            self.current_column -= self.position - (start_pos + 4);
+            self.position = start_pos + 4;
+        }
+        self.scan_number(start_pos)
+    }
+
+    // 数字支持 1_000 / 1,000,000 分组, 1.5e6 科学计数法, 以及 9% 这样的百分数
+    fn scan_number(&mut self, start_pos: usize) -> Result<Token, LexError> {
+        let mut digits: String = self.input[start_pos..self.position].iter().collect();
+        
+        self.scan_digit_groups(&mut digits, start_pos)?;
+        
         if self.peek() == '.' {
-            self.advance();
-            while !self.is_at_end() && self.peek().is_ascii_digit() {
-                self.advance();
+            self.advance(); // consume '.'
+            digits.push('.');
+            if !self.peek().is_ascii_digit() {
+                return Err(self.number_error(start_pos, "expected digits after '.'"));
             }
+            self.scan_digits(&mut digits, start_pos)?;
         }
         
-        let number_str: String = self.input[start_pos..self.position].iter().collect();
+        if self.peek() == '.' || self.peek() == ',' && self.peek_at(1).is_ascii_digit() {
+            return Err(self.number_error(start_pos, "unexpected separator"));
+        }
         
-        match number_str.parse::<f64>() {
-            Ok(num) => Ok(Token::Number(num)),
-            Err(_) => Err(LexError {
-                message: format!("Invalid number or date: {}", number_str),
-                position: start_pos,
-            }),
+        let mut exponent: i32 = 0;
+        let exponent_digits = match self.peek_at(1) {
+            '+' | '-' => 2,
+            _ => 1,
+        };
+        if matches!(self.peek(), 'e' | 'E') && self.peek_at(exponent_digits).is_ascii_digit() {
+            self.advance(); // consume 'e'
+            let negative = self.peek() == '-';
+            if exponent_digits == 2 {
+                self.advance(); // consume sign
+            }
+            
+            let mut text = String::new();
+            while self.peek().is_ascii_digit() {
+                text.push(self.advance());
+            }
+            exponent = text
+                .parse()
+                .ok()
+                .filter(|e: &i32| *e <= 36)
+                .ok_or_else(|| self.number_error(start_pos, "exponent is too large"))?;
+            if negative {
+                exponent = -exponent;
+            }
+        }
+        
+        // 百分数换算成小数, 9% 就是 0.09
+        if self.peek() == '%' {
+            self.advance(); // consume '%'
+            exponent -= 2;
+        }
+        
+        if self.peek().is_ascii_alphanumeric() || self.peek() == '_' {
+            return Err(self.number_error(start_pos, "unexpected character after number"));
+        }
+        
+        digits
+            .parse::<Decimal>()
+            .ok()
+            .and_then(|number| number.shift(exponent))
+            .map(Token::Number)
+            .ok_or_else(|| self.number_error(start_pos, "out of range"))
+    }
+
+    // 整数部分: 连续的数字, 可以用 '_' 或者 ',' 分组, ',' 分组时每组必须是 3 位
+    fn scan_digit_groups(&mut self, digits: &mut String, start_pos: usize) -> Result<(), LexError> {
+        if self.peek() == '_' {
+            self.scan_digits(digits, start_pos)?;
+        }
+        
+        // ',' 后面紧跟数字才是分组, 否则是列表里的逗号
+        if self.peek() == ',' && self.peek_at(1).is_ascii_digit() {
+            if digits.len() > 3 || digits.contains('_') {
+                return Err(self.number_error(start_pos, "digit groups separated by ',' must have 3 digits"));
+            }
+            
+            while self.peek() == ',' && self.peek_at(1).is_ascii_digit() {
+                self.advance(); // consume ','
+                let mut count = 0;
+                while self.peek().is_ascii_digit() {
+                    digits.push(self.advance());
+                    count += 1;
+                }
+                if count != 3 {
+                    return Err(self.number_error(start_pos, "digit groups separated by ',' must have 3 digits"));
+                }
+            }
+        }
+        
+        Ok(())
+    }
+
+    // 连续的数字, '_' 只能出现在两个数字之间
+    fn scan_digits(&mut self, digits: &mut String, start_pos: usize) -> Result<(), LexError> {
+        loop {
+            match self.peek() {
+                ch if ch.is_ascii_digit() => {
+                    digits.push(self.advance());
+                }
+                '_' => {
+                    let after_digit = digits.ends_with(|c: char| c.is_ascii_digit());
+                    self.advance(); // consume '_'
+                    if !after_digit || !self.peek().is_ascii_digit() {
+                        return Err(self.number_error(start_pos, "'_' must be placed between digits"));
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn number_error(&mut self, start_pos: usize, reason: &str) -> LexError {
+        // 把剩下的数字字符也带上, 错误信息更容易定位
+        while self.peek().is_ascii_alphanumeric() || matches!(self.peek(), '.' | '_' | '%') {
+            self.advance();
+        }
+        let text: String = self.input[start_pos..self.position].iter().collect();
+        
+        LexError {
+            message: format!("Invalid number '{}': {}", text, reason),
+            position: start_pos,
         }
     }
 
@@ -325,18 +442,29 @@ impl Lexer {
     }
 }
 
+// 标签可以包含中文
+fn is_tag_char(ch: char) -> bool {
+    ch.is_alphanumeric() || matches!(ch, '_' | '-' | '/')
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn number(text: &str) -> Token {
+        Token::Number(text.parse().unwrap())
+    }
+
     #[test]
     fn test_tokenize_simple_tokens() {
-        let mut lexer = Lexer::new("+ - @ : , ( ) { }");
+        let mut lexer = Lexer::new("+ - * / @ : , ( ) { }");
         let tokens = lexer.tokenize().unwrap();
         
         assert_eq!(tokens, vec![
             Token::Plus,
             Token::Minus,
+            Token::Star,
+            Token::Slash,
             Token::At,
             Token::Colon,
             Token::Comma,
@@ -348,20 +476,99 @@ mod tests {
         ]);
     }
 
+    #[test]
+    fn test_tokenize_arrow() {
+        let mut lexer = Lexer::new("ETF:510300 -> ETF:159915 -5");
+        let tokens = lexer.tokenize().unwrap();
+        
+        assert_eq!(tokens, vec![
+            Token::Symbol("ETF".to_string(), "510300".to_string()),
+            Token::Arrow,
+            Token::Symbol("ETF".to_string(), "159915".to_string()),
+            Token::Minus,
+            number("5"),
+            Token::Eof,
+        ]);
+    }
+
+    #[test]
+    fn test_tokenize_with_spans() {
+        let mut lexer = Lexer::new("PLAN \"定投\"\n  END");
+        let (tokens, spans) = lexer.tokenize_with_spans().unwrap();
+        
+        assert_eq!(tokens.len(), spans.len());
+        assert_eq!(spans[0], Span { line: 1, column: 1 });
+        assert_eq!(spans[1], Span { line: 1, column: 6 });
+        assert_eq!(spans[2], Span { line: 2, column: 3 });
+    }
+
     #[test]
     fn test_tokenize_numbers() {
         let mut lexer = Lexer::new("123 45.67 -89.01");
         let tokens = lexer.tokenize().unwrap();
         
         assert_eq!(tokens, vec![
-            Token::Number(123.0),
-            Token::Number(45.67),
+            number("123"),
+            number("45.67"),
             Token::Minus,
-            Token::Number(89.01),
+            number("89.01"),
+            Token::Eof,
+        ]);
+    }
+
+    #[test]
+    fn test_tokenize_number_formats() {
+        let mut lexer = Lexer::new("1_000_000 1,000,000.50 9% 1.5e6 2.5E-3 12.5%");
+        let tokens = lexer.tokenize().unwrap();
+        
+        assert_eq!(tokens, vec![
+            number("1000000"),
+            number("1000000.50"),
+            number("0.09"),
+            number("1500000"),
+            number("0.0025"),
+            number("0.125"),
             Token::Eof,
         ]);
     }
 
+    #[test]
+    fn test_comma_after_number_is_separator() {
+        let mut lexer = Lexer::new("100, ETF:510300,1000");
+        let tokens = lexer.tokenize().unwrap();
+        
+        assert_eq!(tokens, vec![
+            number("100"),
+            Token::Comma,
+            Token::Symbol("ETF".to_string(), "510300".to_string()),
+            Token::Comma,
+            number("1000"),
+            Token::Eof,
+        ]);
+    }
+
+    #[test]
+    fn test_invalid_numbers() {
+        for (input, reason) in [
+            ("1.2.3", "unexpected separator"),
+            ("1.", "expected digits after '.'"),
+            ("1__000", "'_' must be placed between digits"),
+            ("1000_", "'_' must be placed between digits"),
+            ("1,00", "must have 3 digits"),
+            ("1000,000", "must have 3 digits"),
+            ("1_000,000", "must have 3 digits"),
+            ("12abc", "unexpected character after number"),
+            ("1e99", "exponent is too large"),
+        ] {
+            let error = Lexer::new(input).tokenize().unwrap_err();
+            assert!(error.message.contains(reason), "{}: {}", input, error.message);
+            assert_eq!(error.position, 0);
+        }
+        
+        let error = Lexer::new("TRADE 1.2.3 CNY").tokenize().unwrap_err();
+        assert_eq!(error.message, "Invalid number '1.2.3': unexpected separator");
+    }
+
     #[test]
     fn test_tokenize_date() {
         let mut lexer = Lexer::new("2024-01-15");
@@ -426,6 +633,21 @@ mod tests {
         ]);
     }
 
+    #[test]
+    fn test_tokenize_hashtags() {
+        let mut lexer = Lexer::new("TAG #rebalance #红利 # comment\n#not_a_tag");
+        let tokens = lexer.tokenize().unwrap();
+        
+        assert_eq!(tokens, vec![
+            Token::Tag,
+            Token::HashTag("rebalance".to_string()),
+            Token::HashTag("红利".to_string()),
+            Token::Comment(" comment".to_string()),
+            Token::Comment("not_a_tag".to_string()),
+            Token::Eof,
+        ]);
+    }
+
     #[test]
     fn test_tokenize_complete_statement() {
         let mut lexer = Lexer::new("2024-01-01 TRADE ETF:510300 +5000 CNY @ 4.56");
@@ -436,10 +658,36 @@ mod tests {
             Token::Trade,
             Token::Symbol("ETF".to_string(), "510300".to_string()),
             Token::Plus,
-            Token::Number(5000.0),
+            number("5000"),
             Token::Identifier("CNY".to_string()),
             Token::At,
-            Token::Number(4.56),
+            number("4.56"),
+            Token::Eof,
+        ]);
+    }
+
+    #[test]
+    fn test_subtraction_after_four_digits() {
+        let mut lexer = Lexer::new("5000-1200 CNY");
+        let (tokens, spans) = lexer.tokenize_with_spans().unwrap();
+        
+        assert_eq!(tokens, vec![
+            number("5000"),
+            Token::Minus,
+            number("1200"),
+            Token::Identifier("CNY".to_string()),
+            Token::Eof,
+        ]);
+        assert_eq!(spans[1].column, 5);
+        
+        // 像日期但是不完整的时候也退回到减号
+        let tokens = Lexer::new("2024-01-1").tokenize().unwrap();
+        assert_eq!(tokens, vec![
+            number("2024"),
+            Token::Minus,
+            number("1"),
+            Token::Minus,
+            number("1"),
             Token::Eof,
         ]);
     }
@@ -451,7 +699,7 @@ mod tests {
         
         assert_eq!(tokens, vec![
             Token::Minus,
-            Token::Number(42.0),
+            number("42"),
             Token::Eof,
         ]);
     }
@@ -468,10 +716,10 @@ mod tests {
             Token::Trade,
             Token::Symbol("ETF".to_string(), "510300".to_string()),
             Token::Minus,
-            Token::Number(1000.0),
+            number("1000"),
             Token::Identifier("CNY".to_string()),
             Token::At,
-            Token::Number(4.56),
+            number("4.56"),
             Token::Eof,
         ]);
     }
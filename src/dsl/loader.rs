@@ -0,0 +1,317 @@
+use super::ast::{Origin, Program, Statement};
+use super::{Lexer, Parser};
+use crate::decimal::Decimal;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub struct LoadError {
+    pub message: String,
+    pub file: PathBuf,
+    // 出错的行号, 0 表示和具体的行无关
+    pub line: usize,
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.line > 0 {
+            write!(f, "{}:{}: {}", self.file.display(), self.line, self.message)
+        } else {
+            write!(f, "{}: {}", self.file.display(), self.message)
+        }
+    }
+}
+
+// 读取账本文件并展开所有的 INCLUDE, 合并成一个 Program
+// 每条语句都会记录来源文件和行号; IMPORT 的路径和 INCLUDE 一样相对于所在的文件解析, 转换成绝对路径
+// 同一个文件只能读入一次, 否则里面的记录会算两遍
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Program, LoadError> {
+    let mut program = Program::new();
+    let mut stack = Vec::new();
+    let mut loaded = HashSet::new();
+    load_into(
+        path.as_ref(),
+        &mut program,
+        &mut stack,
+        &mut loaded,
+        &HashMap::new(),
+    )?;
+
+    Ok(program)
+}
+
+fn load_into(
+    path: &Path,
+    program: &mut Program,
+    stack: &mut Vec<PathBuf>,
+    // 已经读入的文件, 包括 stack 里的
+    loaded: &mut HashSet<PathBuf>,
+    // INCLUDE 之前已经定义的常量
+    constants: &HashMap<String, Decimal>,
+) -> Result<(), LoadError> {
+    let error = |message: String, line: usize| LoadError {
+        message,
+        file: path.to_path_buf(),
+        line,
+    };
+
+    let canonical = path
+        .canonicalize()
+        .map_err(|e| error(format!("Failed to open: {}", e), 0))?;
+    if stack.contains(&canonical) {
+        let chain: Vec<String> = stack
+            .iter()
+            .chain(std::iter::once(&canonical))
+            .map(|p| p.display().to_string())
+            .collect();
+        return Err(error(
+            format!("Circular INCLUDE: {}", chain.join(" -> ")),
+            0,
+        ));
+    }
+    if !loaded.insert(canonical.clone()) {
+        return Err(error(
+            format!("File is included more than once: {}", canonical.display()),
+            0,
+        ));
+    }
+
+    let source =
+        std::fs::read_to_string(path).map_err(|e| error(format!("Failed to read: {}", e), 0))?;
+
+    let (tokens, spans) = Lexer::new(&source)
+        .tokenize_with_spans()
+        .map_err(|e| error(e.message, line_of_offset(&source, e.position)))?;
+
+    let mut parser = Parser::with_spans(tokens, spans);
+    for (name, value) in constants.iter() {
+        parser.define_constant(name, *value);
+    }
+    let parsed = match parser.parse() {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            let line = parser.span_of(&e).map_or(0, |span| span.line);
+            return Err(error(e.message, line));
+        }
+    };
+
+    // 用规范化的路径, IMPORT 的路径就不依赖当前目录和 Engine::with_base_dir
+    let base_dir = canonical.parent().unwrap_or(Path::new("")).to_path_buf();
+    stack.push(canonical);
+
+    let mut constants = constants.clone();
+
+    for (statement, origin) in parsed.statements {
+        let origin = Origin {
+            file: Some(path.to_path_buf()),
+            line: origin.map_or(0, |origin| origin.line),
+        };
+
+        match statement {
+            Statement::Include(include) => {
+                load_into(
+                    &base_dir.join(&include.path),
+                    program,
+                    stack,
+                    loaded,
+                    &constants,
+                )?;
+            }
+            Statement::Let(constant) => {
+                constants.insert(constant.name.clone(), constant.value);
+                program.add_statement_with_origin(Statement::Let(constant), origin);
+            }
+            Statement::Import(mut import) => {
+                import.path = base_dir.join(&import.path).display().to_string();
+                program.add_statement_with_origin(Statement::Import(import), origin);
+            }
+            statement => program.add_statement_with_origin(statement, origin),
+        }
+    }
+
+    stack.pop();
+    Ok(())
+}
+
+fn line_of_offset(source: &str, offset: usize) -> usize {
+    source.chars().take(offset).filter(|ch| *ch == '\n').count() + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsl::ast::Details;
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("cashly-{}-{}", name, std::process::id()));
+            std::fs::create_dir_all(dir.join("years")).unwrap();
+            Self(dir)
+        }
+
+        fn write(&self, name: &str, content: &str) -> PathBuf {
+            let path = self.0.join(name);
+            std::fs::write(&path, content).unwrap();
+            path
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_load_includes() {
+        let dir = TempDir::new("loader");
+        dir.write(
+            "years/2024.cash",
+            "2024-01-02 TRADE ETF:510300 +5000 CNY\n\nIMPORT PRICES \"prices.csv\"\n",
+        );
+        let main = dir.write(
+            "main.cash",
+            "DEFINE ETF:510300\n  ALIAS \"沪深300ETF\"\nEND\nINCLUDE \"years/2024.cash\"\n",
+        );
+
+        let program = load(&main).unwrap();
+        assert_eq!(program.statements.len(), 3);
+        assert!(program.statements.iter().all(|(_, origin)| origin.is_some()));
+        assert_eq!(program.origin(0).unwrap().line, 1);
+        assert_eq!(
+            program.origin(2).unwrap().to_string(),
+            format!("{}:3", dir.0.join("years/2024.cash").display())
+        );
+
+        match &program.statements[2].0 {
+            Statement::Import(import) => {
+                assert_eq!(
+                    PathBuf::from(&import.path),
+                    dir.0.join("years").join("prices.csv")
+                );
+            }
+            _ => panic!("Expected import statement"),
+        }
+    }
+
+    #[test]
+    fn test_load_detects_cycle() {
+        let dir = TempDir::new("loader-cycle");
+        dir.write("b.cash", "INCLUDE \"a.cash\"\n");
+        let a = dir.write("a.cash", "INCLUDE \"b.cash\"\n");
+
+        let err = load(&a).unwrap_err();
+        assert!(err.message.starts_with("Circular INCLUDE"));
+    }
+
+    #[test]
+    fn test_load_rejects_repeated_include() {
+        let dir = TempDir::new("loader-diamond");
+        dir.write("accounts.cash", "DEFINE ETF:510300\n  ALIAS \"沪深300ETF\"\nEND\n");
+        dir.write("years/2023.cash", "INCLUDE \"../accounts.cash\"\n");
+        dir.write("years/2024.cash", "INCLUDE \"../accounts.cash\"\n");
+        let main = dir.write(
+            "main.cash",
+            "INCLUDE \"years/2023.cash\"\nINCLUDE \"years/2024.cash\"\n",
+        );
+
+        let err = load(&main).unwrap_err();
+        assert_eq!(
+            err.message,
+            format!(
+                "File is included more than once: {}",
+                dir.0.join("accounts.cash").canonicalize().unwrap().display()
+            )
+        );
+    }
+
+    #[test]
+    fn test_load_import_ignores_engine_base_dir() {
+        // 用相对于当前目录的路径读入
+        let dir = TempDir(PathBuf::from(format!(
+            "target/cashly-loader-import-{}",
+            std::process::id()
+        )));
+        std::fs::create_dir_all(dir.0.join("years")).unwrap();
+        dir.write("years/prices.csv", "date,symbol,close\n2024-01-31,ETF:510300,5\n");
+        dir.write(
+            "years/2024.cash",
+            "IMPORT PRICES \"prices.csv\"\n2024-01-02 TRADE ETF:510300 +4000 CNY @ 4\n",
+        );
+        let main = dir.write("main.cash", "INCLUDE \"years/2024.cash\"\n");
+
+        // IMPORT 的路径已经相对于所在的文件解析过, 不会再拼上一次 base_dir
+        let program = load(&main).unwrap();
+        let report = crate::Engine::new()
+            .with_base_dir(&dir.0)
+            .evaluate(program)
+            .unwrap();
+        assert_eq!(report.asset_summary("ETF:510300").unwrap().value, 5000.0);
+    }
+
+    #[test]
+    fn test_load_reports_parse_error_line() {
+        let dir = TempDir::new("loader-error");
+        let path = dir.write(
+            "bad.cash",
+            "2024-01-02 TRADE ETF:510300 +5000 CNY\n2024-01-03 BUY\n",
+        );
+
+        let err = load(&path).unwrap_err();
+        assert_eq!(err.line, 2);
+        assert_eq!(err.file, path);
+    }
+
+    #[test]
+    fn test_engine_error_reports_origin() {
+        let dir = TempDir::new("loader-engine");
+        let path = dir.write(
+            "main.cash",
+            "2024-01-02 TRADE ETF:510300 +5000 CNY\n2024-01-03 DEPOSIT ETF:510300 100 CNY\n",
+        );
+
+        let program = load(&path).unwrap();
+        let err = crate::Engine::new().evaluate(program).err().unwrap();
+        assert_eq!(err.origin.as_ref().unwrap().line, 2);
+        assert_eq!(
+            err.to_string(),
+            format!("{}:2: {}", path.display(), err.message)
+        );
+    }
+
+    #[test]
+    fn test_load_inherits_constants() {
+        let dir = TempDir::new("loader-let");
+        dir.write(
+            "years/2024.cash",
+            "LET LOCAL = 1\n2024-01-02 TRADE ETF:510300 +BUDGET CNY\n",
+        );
+        dir.write("years/2025.cash", "LET BUDGET = 1\n");
+        let main = dir.write(
+            "main.cash",
+            "LET BUDGET = 4000\nINCLUDE \"years/2024.cash\"\n",
+        );
+
+        let program = load(&main).unwrap();
+        assert_eq!(program.statements.len(), 3);
+        match &program.statements[2].0 {
+            Statement::Record(record) => {
+                assert!(
+                    matches!(&record.details, Details::Trade(t) if t.signed_amount.to_f64() == 4000.0)
+                );
+            }
+            _ => panic!("Expected record statement"),
+        }
+
+        // 被 INCLUDE 的文件不能重新定义外面的常量
+        let main = dir.write(
+            "main.cash",
+            "LET BUDGET = 4000\nINCLUDE \"years/2025.cash\"\n",
+        );
+        let err = load(&main).unwrap_err();
+        assert_eq!(err.message, "Constant 'BUDGET' is already defined");
+        assert_eq!(err.line, 1);
+    }
+}
@@ -1,5 +1,10 @@
 use super::ast::*;
-use super::token::Token;
+use super::token::{Span, Token};
+use crate::decimal::Decimal;
+use std::collections::HashMap;
+
+// 目标权重的合计和 100% 允许相差 0.1%, 四舍五入写的权重也能加起来
+const WEIGHT_TOLERANCE: Decimal = Decimal::new(1, 3);
 
 #[derive(Debug)]
 pub struct ParseError {
@@ -9,12 +14,41 @@ pub struct ParseError {
 
 pub struct Parser {
     tokens: Vec<Token>,
+    // 和 tokens 一一对应, 可以为空
+    spans: Vec<Span>,
     current: usize,
+    // 表达式里可以引用的常量
+    constants: HashMap<String, Decimal>,
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, current: 0 }
+        Self {
+            tokens,
+            spans: Vec::new(),
+            current: 0,
+            constants: HashMap::new(),
+        }
+    }
+
+    // 带上 token 的位置, 解析出来的 Program 会记录每条语句的行号
+    pub fn with_spans(tokens: Vec<Token>, spans: Vec<Span>) -> Self {
+        Self {
+            tokens,
+            spans,
+            current: 0,
+            constants: HashMap::new(),
+        }
+    }
+
+    // 预先定义常量, 数值的位置上可以直接写常量名
+    pub fn define_constant(&mut self, name: &str, value: Decimal) {
+        self.constants.insert(name.to_string(), value);
+    }
+
+    // 错误位置对应的源码位置
+    pub fn span_of(&self, error: &ParseError) -> Option<Span> {
+        self.spans.get(error.position).copied()
     }
 
     pub fn parse(&mut self) -> Result<Program, ParseError> {
@@ -28,9 +62,17 @@ impl Parser {
                 break;
             }
 
-            match self.parse_statement() {
-                Ok(statement) => program.add_statement(statement),
-                Err(e) => return Err(e),
+            let start = self.current;
+            let statement = self.parse_statement()?;
+            match self.spans.get(start) {
+                Some(span) => program.add_statement_with_origin(
+                    statement,
+                    Origin {
+                        file: None,
+                        line: span.line,
+                    },
+                ),
+                None => program.add_statement(statement),
             }
         }
 
@@ -45,9 +87,13 @@ impl Parser {
 
         match self.peek() {
             Token::Date(_) => self.parse_record().map(Statement::Record),
+            Token::Price => self.parse_price_record().map(Statement::Record),
             Token::Plan => self.parse_plan().map(Statement::Plan),
             Token::Define => self.parse_define().map(Statement::Define),
             Token::Portfolio => self.parse_portfolio().map(Statement::Portfolio),
+            Token::Import => self.parse_import().map(Statement::Import),
+            Token::Include => self.parse_include().map(Statement::Include),
+            Token::Let => self.parse_let().map(Statement::Let),
             Token::Eof => Err(ParseError {
                 message: "Unexpected end of input".to_string(),
                 position: self.current,
@@ -72,22 +118,119 @@ impl Parser {
 
         let action = self.parse_action()?;
         let details = self.parse_details(&action)?;
-        let note = self.parse_optional_note()?;
 
-        Ok(Record {
+        let mut record = Record {
             date,
             action,
             details,
-            note,
-        })
+            note: None,
+            metadata: Metadata::default(),
+        };
+        self.parse_record_clauses(&mut record)?;
+
+        Ok(record)
+    }
+
+    // PRICE <date> <symbol> <number> <unit>, 日期写在关键字后面
+    fn parse_price_record(&mut self) -> Result<Record, ParseError> {
+        self.consume(&Token::Price, "Expected PRICE")?;
+        let date = self.parse_date()?;
+        let details = self.parse_price_details()?;
+
+        let mut record = Record {
+            date,
+            action: Action::Price,
+            details: Details::Price(details),
+            note: None,
+            metadata: Metadata::default(),
+        };
+        self.parse_record_clauses(&mut record)?;
+
+        Ok(record)
+    }
+
+    // 记录后面可选的 NOTE, TAG 和 META, 顺序不限
+    fn parse_record_clauses(&mut self, record: &mut Record) -> Result<(), ParseError> {
+        loop {
+            if self.check(&Token::Note) {
+                if record.note.is_some() {
+                    return Err(ParseError {
+                        message: "Duplicate NOTE".to_string(),
+                        position: self.current,
+                    });
+                }
+                self.advance(); // consume NOTE
+                record.note = Some(self.parse_string()?);
+            } else if !self.parse_metadata(&mut record.metadata)? {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    // TAG #a #b 或者 META key "value", 不是这两种的时候返回 false
+    fn parse_metadata(&mut self, metadata: &mut Metadata) -> Result<bool, ParseError> {
+        match self.peek() {
+            Token::Tag => {
+                self.advance(); // consume TAG
+                if !matches!(self.peek(), Token::HashTag(_)) {
+                    return Err(ParseError {
+                        message: format!("Expected #tag after TAG, found {:?}", self.peek()),
+                        position: self.current,
+                    });
+                }
+                while let Token::HashTag(tag) = self.peek().clone() {
+                    self.advance();
+                    if !metadata.tags.contains(&tag) {
+                        metadata.tags.push(tag);
+                    }
+                }
+            }
+            Token::Meta => {
+                self.advance(); // consume META
+                let position = self.current;
+                let key = match self.advance() {
+                    Token::Identifier(key) | Token::String(key) => key,
+                    token => {
+                        return Err(ParseError {
+                            message: format!("Expected META key, found {:?}", token),
+                            position,
+                        });
+                    }
+                };
+                let value = self.parse_string()?;
+                if metadata.meta.contains_key(&key) {
+                    return Err(ParseError {
+                        message: format!("Duplicate META key '{}'", key),
+                        position,
+                    });
+                }
+                metadata.meta.insert(key, value);
+            }
+            _ => return Ok(false),
+        }
+
+        Ok(true)
     }
 
     fn parse_action(&mut self) -> Result<Action, ParseError> {
         match self.advance() {
             Token::Trade => Ok(Action::Trade),
             Token::Mark => Ok(Action::Mark),
+            Token::Fee => Ok(Action::Fee),
+            Token::Tax => Ok(Action::Tax),
+            Token::Split => Ok(Action::Split),
+            Token::Merge => Ok(Action::Merge),
+            Token::Transfer => Ok(Action::Transfer),
+            Token::Deposit => Ok(Action::Deposit),
+            Token::Withdraw => Ok(Action::Withdraw),
+            Token::Price => Ok(Action::Price),
             token => Err(ParseError {
-                message: format!("Expected TRADE or MARK, found {:?}", token),
+                message: format!(
+                    "Expected TRADE, MARK, FEE, TAX, SPLIT, MERGE, TRANSFER, DEPOSIT, WITHDRAW or PRICE, found {:?}",
+                    token
+                ),
                 position: self.current - 1,
             }),
         }
@@ -97,6 +240,12 @@ impl Parser {
         match action {
             Action::Trade => self.parse_trade_details().map(Details::Trade),
             Action::Mark => self.parse_mark_details().map(Details::Mark),
+            Action::Fee | Action::Tax => self.parse_fee_details().map(Details::Fee),
+            Action::Split => self.parse_split_details().map(Details::Split),
+            Action::Merge => self.parse_merge_details().map(Details::Merge),
+            Action::Transfer => self.parse_transfer_details().map(Details::Transfer),
+            Action::Deposit | Action::Withdraw => self.parse_cash_details().map(Details::Cash),
+            Action::Price => self.parse_price_details().map(Details::Price),
         }
     }
 
@@ -107,23 +256,168 @@ impl Parser {
 
         let price = if self.check(&Token::At) {
             self.advance(); // consume '@'
-            Some(self.parse_number()?)
+            Some(self.parse_amount()?)
         } else {
             None
         };
 
-        Ok(TradeDetails {
+        let mut details = TradeDetails {
             symbol,
             signed_amount,
             unit,
             price,
+            fee: None,
+            tax: None,
+            account: None,
+        };
+        self.parse_trade_clauses(&mut details)?;
+
+        Ok(details)
+    }
+
+    // FEE, TAX 和 VIA 子句都是可选的, 顺序不限
+    fn parse_trade_clauses(&mut self, details: &mut TradeDetails) -> Result<(), ParseError> {
+        loop {
+            if self.check(&Token::Fee) {
+                self.advance(); // consume FEE
+                details.fee = Some(self.parse_charge()?);
+            } else if self.check(&Token::Tax) {
+                self.advance(); // consume TAX
+                details.tax = Some(self.parse_charge()?);
+            } else if self.check(&Token::Via) {
+                self.advance(); // consume VIA
+                details.account = Some(self.parse_symbol()?);
+            } else {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn parse_charge(&mut self) -> Result<Charge, ParseError> {
+        let amount = self.parse_amount()?;
+        let unit = self.parse_identifier()?;
+
+        Ok(Charge::new(amount, unit))
+    }
+
+    fn parse_cash_details(&mut self) -> Result<CashDetails, ParseError> {
+        let symbol = self.parse_symbol()?;
+        let amount = self.parse_amount()?;
+        let unit = self.parse_identifier()?;
+
+        Ok(CashDetails {
+            symbol,
+            amount,
+            unit,
+        })
+    }
+
+    fn parse_price_details(&mut self) -> Result<PriceDetails, ParseError> {
+        let symbol = self.parse_symbol()?;
+        let price = self.parse_amount()?;
+        let unit = self.parse_identifier()?;
+
+        Ok(PriceDetails {
+            symbol,
+            price,
+            unit,
+        })
+    }
+
+    fn parse_fee_details(&mut self) -> Result<FeeDetails, ParseError> {
+        let symbol = self.parse_symbol()?;
+        let charge = self.parse_charge()?;
+
+        Ok(FeeDetails { symbol, charge })
+    }
+
+    fn parse_split_details(&mut self) -> Result<SplitDetails, ParseError> {
+        let symbol = self.parse_symbol()?;
+        let ratio = self.parse_ratio()?;
+
+        Ok(SplitDetails { symbol, ratio })
+    }
+
+    fn parse_merge_details(&mut self) -> Result<MergeDetails, ParseError> {
+        let symbol = self.parse_symbol()?;
+        self.consume(&Token::Into, "Expected INTO")?;
+        let target = self.parse_symbol()?;
+
+        // 没有比例的时候就是简单的改名
+        let ratio = if matches!(
+            self.peek(),
+            Token::Number(_) | Token::LeftParen | Token::Identifier(_)
+        ) {
+            self.parse_ratio()?
+        } else {
+            Decimal::from(1)
+        };
+
+        Ok(MergeDetails {
+            symbol,
+            target,
+            ratio,
+        })
+    }
+
+    fn parse_transfer_details(&mut self) -> Result<TransferDetails, ParseError> {
+        let symbol = self.parse_symbol()?;
+        self.consume(&Token::Arrow, "Expected '->'")?;
+        let target = self.parse_symbol()?;
+        let amount = self.parse_amount()?;
+        let unit = self.parse_identifier()?;
+
+        let price = if self.check(&Token::At) {
+            self.advance(); // consume '@'
+            Some(self.parse_amount()?)
+        } else {
+            None
+        };
+
+        Ok(TransferDetails {
+            symbol,
+            target,
+            amount,
+            unit,
+            price,
         })
     }
 
+    // 比例可以写成 2 或者 2:1
+    fn parse_ratio(&mut self) -> Result<Decimal, ParseError> {
+        let position = self.current;
+        let mut ratio = self.parse_expression()?;
+
+        if self.check(&Token::Colon) {
+            self.advance(); // consume ':'
+            let denominator = self.parse_expression()?;
+            if denominator.is_zero() {
+                return Err(ParseError {
+                    message: "Ratio denominator must not be zero".to_string(),
+                    position: self.current - 1,
+                });
+            }
+            ratio = ratio
+                .checked_div(denominator)
+                .ok_or_else(|| overflow(position))?;
+        }
+
+        if !ratio.is_positive() {
+            return Err(ParseError {
+                message: format!("Ratio must be positive, found {}", ratio),
+                position,
+            });
+        }
+
+        Ok(ratio)
+    }
+
     fn parse_mark_details(&mut self) -> Result<MarkDetails, ParseError> {
         let symbol = self.parse_symbol()?;
         self.consume(&Token::Value, "Expected VALUE")?;
-        let value = self.parse_number()?;
+        let value = self.parse_amount()?;
         let unit = self.parse_identifier()?;
 
         Ok(MarkDetails {
@@ -179,7 +473,7 @@ impl Parser {
 
     fn parse_schedule(&mut self) -> Result<Schedule, ParseError> {
         let frequency = self.parse_frequency()?;
-        let amount = self.parse_number()?;
+        let amount = self.parse_amount()?;
         let unit = self.parse_identifier()?;
         self.consume(&Token::Into, "Expected INTO")?;
         let target = self.parse_symbol()?;
@@ -208,94 +502,283 @@ impl Parser {
 
     fn parse_define(&mut self) -> Result<Define, ParseError> {
         self.consume(&Token::Define, "Expected DEFINE")?;
-        let symbol = self.parse_symbol()?;
-        let (alias, target_return) = self.parse_define_body()?;
+        let mut define = Define {
+            symbol: self.parse_symbol()?,
+            alias: None,
+            target_return: None,
+            class: None,
+            category: None,
+            benchmark: None,
+            currency: None,
+            risk: None,
+            metadata: Metadata::default(),
+        };
+        self.parse_define_body(&mut define)?;
         self.consume(&Token::End, "Expected END")?;
 
-        Ok(Define {
-            symbol,
-            alias,
-            target_return,
-        })
+        Ok(define)
     }
 
-    fn parse_define_body(&mut self) -> Result<(Option<String>, Option<f64>), ParseError> {
-        let mut alias = None;
-        let mut target_return = None;
-
+    fn parse_define_body(&mut self, define: &mut Define) -> Result<(), ParseError> {
         while !self.check(&Token::End) && !self.is_at_end() {
             match self.peek() {
                 Token::Alias => {
                     self.advance(); // consume ALIAS
-                    alias = Some(self.parse_string()?);
+                    define.alias = Some(self.parse_string()?);
                 }
                 Token::Target => {
                     self.advance(); // consume TARGET
                     self.consume(&Token::Return, "Expected RETURN after TARGET")?;
-                    target_return = Some(self.parse_number()?);
+                    define.target_return = Some(self.parse_number()?);
+                }
+                Token::Class => {
+                    self.advance(); // consume CLASS
+                    let position = self.current;
+                    let name = self.parse_identifier()?;
+                    define.class = Some(AssetClass::from_name(&name).ok_or(ParseError {
+                        message: format!(
+                            "Unknown asset class '{}', expected EQUITY, BOND, CASH or COMMODITY",
+                            name
+                        ),
+                        position,
+                    })?);
+                }
+                Token::Category => {
+                    self.advance(); // consume CATEGORY
+                    define.category = Some(self.parse_string()?);
+                }
+                Token::Benchmark => {
+                    self.advance(); // consume BENCHMARK
+                    define.benchmark = Some(self.parse_symbol()?);
+                }
+                Token::Currency => {
+                    self.advance(); // consume CURRENCY
+                    define.currency = Some(self.parse_identifier()?);
+                }
+                Token::Risk => {
+                    self.advance(); // consume RISK
+                    let position = self.current;
+                    let risk = self.parse_amount()?;
+                    if !(1..=5).any(|level| risk == Decimal::from(level)) {
+                        return Err(ParseError {
+                            message: format!("RISK must be an integer from 1 to 5, found {}", risk),
+                            position,
+                        });
+                    }
+                    define.risk = Some(risk.to_f64() as u8);
+                }
+                _ => {
+                    if !self.parse_metadata(&mut define.metadata)? {
+                        break;
+                    }
                 }
-                _ => break,
             }
         }
 
-        Ok((alias, target_return))
+        Ok(())
     }
 
     fn parse_portfolio(&mut self) -> Result<Portfolio, ParseError> {
         self.consume(&Token::Portfolio, "Expected PORTFOLIO")?;
-        let name = self.parse_string()?;
-        let (assets, target_return) = self.parse_portfolio_body()?;
+        let mut portfolio = Portfolio {
+            name: self.parse_string()?,
+            members: Vec::new(),
+            target_return: None,
+            rebalance: None,
+        };
+        let position = self.current;
+        self.parse_portfolio_body(&mut portfolio)?;
+        if portfolio.rebalance.is_some() && !portfolio.has_weights() {
+            return Err(ParseError {
+                message: format!(
+                    "REBALANCE requires target weights in portfolio \"{}\"",
+                    portfolio.name
+                ),
+                position,
+            });
+        }
         self.consume(&Token::End, "Expected END")?;
 
-        Ok(Portfolio {
-            name,
-            assets,
-            target_return,
-        })
+        Ok(portfolio)
     }
 
-    fn parse_portfolio_body(&mut self) -> Result<(Vec<Symbol>, Option<f64>), ParseError> {
-        let mut assets = Vec::new();
-        let mut target_return = None;
-
+    fn parse_portfolio_body(&mut self, portfolio: &mut Portfolio) -> Result<(), ParseError> {
         while !self.check(&Token::End) && !self.is_at_end() {
             match self.peek() {
                 Token::Assets => {
                     self.advance(); // consume ASSETS
-                    assets = self.parse_symbol_list()?;
+                    self.parse_weighted_assets(portfolio)?;
                 }
                 Token::Target => {
                     self.advance(); // consume TARGET
                     self.consume(&Token::Return, "Expected RETURN after TARGET")?;
-                    target_return = Some(self.parse_number()?);
+                    portfolio.target_return = Some(self.parse_number()?);
+                }
+                Token::Rebalance => {
+                    if portfolio.rebalance.is_some() {
+                        return Err(ParseError {
+                            message: "Duplicate REBALANCE".to_string(),
+                            position: self.current,
+                        });
+                    }
+                    self.advance(); // consume REBALANCE
+                    portfolio.rebalance = Some(self.parse_rebalance_policy()?);
                 }
                 _ => break,
             }
         }
 
-        Ok((assets, target_return))
+        Ok(())
     }
 
-    fn parse_symbol_list(&mut self) -> Result<Vec<Symbol>, ParseError> {
-        let mut symbols = Vec::new();
+    // REBALANCE QUARTERLY 或者 REBALANCE DRIFT 5%
+    fn parse_rebalance_policy(&mut self) -> Result<RebalancePolicy, ParseError> {
+        if self.peek().is_frequency() {
+            return Ok(RebalancePolicy::Calendar(self.parse_frequency()?));
+        }
+        self.consume(&Token::Drift, "Expected frequency or DRIFT after REBALANCE")?;
+
+        let position = self.current;
+        let threshold = self.parse_amount()?;
+        if threshold.is_zero() {
+            return Err(ParseError {
+                message: "REBALANCE DRIFT must be greater than 0".to_string(),
+                position,
+            });
+        }
+        if threshold > Decimal::from(1) {
+            return Err(ParseError {
+                message: format!(
+                    "REBALANCE DRIFT must not exceed 100%, found {}%",
+                    threshold.shift(2).unwrap_or(threshold)
+                ),
+                position,
+            });
+        }
+
+        Ok(RebalancePolicy::Drift(threshold))
+    }
 
-        symbols.push(self.parse_symbol()?);
+    // ASSETS ETF:510300 60%, "债券" 40%, 字符串是其他组合的名字
+    // 权重要么都写要么都不写, 合计必须是 100%, 同一个成员不能写两次
+    fn parse_weighted_assets(&mut self, portfolio: &mut Portfolio) -> Result<(), ParseError> {
+        let position = self.current;
+        let mut members = Vec::new();
+
+        loop {
+            let member_position = self.current;
+            let kind = match self.peek() {
+                Token::String(_) => MemberKind::Portfolio(self.parse_string()?),
+                _ => MemberKind::Symbol(self.parse_symbol()?),
+            };
+            if members.iter().any(|m: &PortfolioMember| m.kind == kind) {
+                let name = match &kind {
+                    MemberKind::Symbol(symbol) => symbol.to_string(),
+                    MemberKind::Portfolio(name) => format!("\"{}\"", name),
+                };
+                return Err(ParseError {
+                    message: format!(
+                        "Duplicate member {} in portfolio \"{}\"",
+                        name, portfolio.name
+                    ),
+                    position: member_position,
+                });
+            }
+            let mut weight = None;
+            if matches!(
+                self.peek(),
+                Token::Number(_) | Token::LeftParen | Token::Identifier(_) | Token::Minus
+            ) {
+                let weight_position = self.current;
+                let value = self.parse_expression()?;
+                if !value.is_positive() {
+                    return Err(ParseError {
+                        message: format!(
+                            "Target weight must be positive, found {}%",
+                            value.shift(2).unwrap_or(value)
+                        ),
+                        position: weight_position,
+                    });
+                }
+                weight = Some(value);
+            }
+            members.push(PortfolioMember { kind, weight });
 
-        while self.check(&Token::Comma) {
+            if !self.check(&Token::Comma) {
+                break;
+            }
             self.advance(); // consume ','
-            symbols.push(self.parse_symbol()?);
         }
 
-        Ok(symbols)
+        let weights: Vec<Decimal> = members.iter().filter_map(|m| m.weight).collect();
+        if !weights.is_empty() && weights.len() != members.len() {
+            return Err(ParseError {
+                message: format!(
+                    "Portfolio \"{}\" must set a target weight for every asset or none",
+                    portfolio.name
+                ),
+                position,
+            });
+        }
+        let total = weights
+            .iter()
+            .try_fold(Decimal::ZERO, |total, weight| total.checked_add(*weight))
+            .ok_or_else(|| overflow(position))?;
+        // 三个 33.33% 合计 99.99%, 在允许的误差以内
+        let difference = total.checked_sub(Decimal::from(1)).ok_or_else(|| overflow(position))?;
+        if !weights.is_empty() && difference.abs() > WEIGHT_TOLERANCE {
+            return Err(ParseError {
+                message: format!(
+                    "Target weights of portfolio \"{}\" add up to {}%, expected 100%",
+                    portfolio.name,
+                    total.shift(2).unwrap_or(total)
+                ),
+                position,
+            });
+        }
+
+        portfolio.members = members;
+        Ok(())
     }
 
-    fn parse_optional_note(&mut self) -> Result<Option<String>, ParseError> {
-        if self.check(&Token::Note) {
-            self.advance(); // consume NOTE
-            Ok(Some(self.parse_string()?))
-        } else {
-            Ok(None)
+    fn parse_import(&mut self) -> Result<Import, ParseError> {
+        self.consume(&Token::Import, "Expected IMPORT")?;
+        let kind = match self.advance() {
+            Token::Prices => ImportKind::Prices,
+            token => {
+                return Err(ParseError {
+                    message: format!("Expected PRICES, found {:?}", token),
+                    position: self.current - 1,
+                });
+            }
+        };
+        let path = self.parse_string()?;
+
+        Ok(Import { kind, path })
+    }
+
+    fn parse_include(&mut self) -> Result<Include, ParseError> {
+        self.consume(&Token::Include, "Expected INCLUDE")?;
+        let path = self.parse_string()?;
+
+        Ok(Include { path })
+    }
+
+    fn parse_let(&mut self) -> Result<Let, ParseError> {
+        self.consume(&Token::Let, "Expected LET")?;
+        let position = self.current;
+        let name = self.parse_identifier()?;
+        if self.constants.contains_key(&name) {
+            return Err(ParseError {
+                message: format!("Constant '{}' is already defined", name),
+                position,
+            });
         }
+        self.consume(&Token::Equals, "Expected '='")?;
+        let value = self.parse_expression()?;
+
+        self.constants.insert(name.clone(), value);
+        Ok(Let { name, value })
     }
 
     // Helper parsing methods
@@ -323,13 +806,96 @@ impl Parser {
             _ => Sign::Positive, // Default to positive if no sign
         };
 
-        let value = self.parse_number()?;
+        let value = self.parse_amount()?;
         Ok(SignedAmount::new(sign, value))
     }
 
+    // 目标收益率这类只用来比较的比例, 不参与金额和份额的计算
     fn parse_number(&mut self) -> Result<f64, ParseError> {
+        self.parse_amount().map(|n| n.to_f64())
+    }
+
+    // 金额, 价格和份额保留源文件里的精度, 可以写成表达式, 比如 (1000 * 4.56) 或者 BUDGET / 2
+    fn parse_amount(&mut self) -> Result<Decimal, ParseError> {
+        let position = self.current;
+        let value = self.parse_expression()?;
+        if value.is_negative() {
+            return Err(ParseError {
+                message: format!("Amount must not be negative, found {}", value),
+                position,
+            });
+        }
+
+        Ok(value)
+    }
+
+    // expression ::= term (("+" | "-") term)*
+    fn parse_expression(&mut self) -> Result<Decimal, ParseError> {
+        let mut value = self.parse_term()?;
+
+        loop {
+            let position = self.current;
+            let operation: fn(Decimal, Decimal) -> Option<Decimal> = match self.peek() {
+                Token::Plus => Decimal::checked_add,
+                Token::Minus => Decimal::checked_sub,
+                _ => return Ok(value),
+            };
+            self.advance();
+            let operand = self.parse_term()?;
+            value = operation(value, operand).ok_or_else(|| overflow(position))?;
+        }
+    }
+
+    // term ::= factor (("*" | "/") factor)*
+    fn parse_term(&mut self) -> Result<Decimal, ParseError> {
+        let mut value = self.parse_factor()?;
+
+        loop {
+            let position = self.current;
+            match self.peek() {
+                Token::Star => {
+                    self.advance();
+                    let operand = self.parse_factor()?;
+                    value = value
+                        .checked_mul(operand)
+                        .ok_or_else(|| overflow(position))?;
+                }
+                Token::Slash => {
+                    self.advance();
+                    let divisor_position = self.current;
+                    let divisor = self.parse_factor()?;
+                    if divisor.is_zero() {
+                        return Err(ParseError {
+                            message: "Division by zero".to_string(),
+                            position: divisor_position,
+                        });
+                    }
+                    value = value
+                        .checked_div(divisor)
+                        .ok_or_else(|| overflow(position))?;
+                }
+                _ => return Ok(value),
+            }
+        }
+    }
+
+    // factor ::= number | constant | "-" factor | "(" expression ")"
+    fn parse_factor(&mut self) -> Result<Decimal, ParseError> {
         match self.advance() {
             Token::Number(n) => Ok(n),
+            Token::Identifier(name) => match self.constants.get(&name) {
+                Some(value) => Ok(*value),
+                None => Err(ParseError {
+                    message: format!("Unknown constant '{}'", name),
+                    position: self.current - 1,
+                }),
+            },
+            Token::Minus => self.parse_factor().map(|value| -value),
+            Token::LeftParen => {
+                let value = self.parse_expression()?;
+                self.consume(&Token::RightParen, "Expected ')' to close expression")?;
+                Ok(value)
+            }
             token => Err(ParseError {
                 message: format!("Expected number, found {:?}", token),
                 position: self.current - 1,
@@ -404,29 +970,8 @@ impl Parser {
             return false;
         }
 
-        match (self.peek(), token_type) {
-            (Token::Plan, Token::Plan) => true,
-            (Token::Define, Token::Define) => true,
-            (Token::Portfolio, Token::Portfolio) => true,
-            (Token::End, Token::End) => true,
-            (Token::Trade, Token::Trade) => true,
-            (Token::Mark, Token::Mark) => true,
-            (Token::Schedule, Token::Schedule) => true,
-            (Token::Start, Token::Start) => true,
-            (Token::EndDate, Token::EndDate) => true,
-            (Token::Alias, Token::Alias) => true,
-            (Token::Target, Token::Target) => true,
-            (Token::Return, Token::Return) => true,
-            (Token::Assets, Token::Assets) => true,
-            (Token::Into, Token::Into) => true,
-            (Token::Value, Token::Value) => true,
-            (Token::Note, Token::Note) => true,
-            (Token::At, Token::At) => true,
-            (Token::Comma, Token::Comma) => true,
-            (Token::Plus, Token::Plus) => true,
-            (Token::Minus, Token::Minus) => true,
-            _ => false,
-        }
+        // 只比较 token 的种类, 不比较携带的值
+        std::mem::discriminant(self.peek()) == std::mem::discriminant(token_type)
     }
 
     fn consume(&mut self, expected: &Token, error_message: &str) -> Result<(), ParseError> {
@@ -442,6 +987,14 @@ impl Parser {
     }
 }
 
+// 运算结果超出 Decimal 的范围, 位置指向运算符
+fn overflow(position: usize) -> ParseError {
+    ParseError {
+        message: "Arithmetic overflow".to_string(),
+        position,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -461,7 +1014,7 @@ mod tests {
 
         assert_eq!(program.statements.len(), 1);
 
-        if let Statement::Record(record) = &program.statements[0] {
+        if let Statement::Record(record) = &program.statements[0].0 {
             assert_eq!(record.date, "2024-01-01");
             assert_eq!(record.action, Action::Trade);
 
@@ -470,7 +1023,7 @@ mod tests {
                 assert_eq!(details.symbol.name, "510300");
                 assert_eq!(details.signed_amount.to_f64(), 5000.0);
                 assert_eq!(details.unit, "CNY");
-                assert_eq!(details.price, Some(4.56));
+                assert_eq!(details.price, Some(Decimal::from_f64(4.56)));
             } else {
                 panic!("Expected trade details");
             }
@@ -486,7 +1039,7 @@ mod tests {
 
         assert_eq!(program.statements.len(), 1);
 
-        if let Statement::Record(record) = &program.statements[0] {
+        if let Statement::Record(record) = &program.statements[0].0 {
             assert_eq!(record.date, "2024-03-31");
             assert_eq!(record.action, Action::Mark);
 
@@ -517,7 +1070,7 @@ mod tests {
         let program = parse_input(input).unwrap();
         assert_eq!(program.statements.len(), 1);
 
-        if let Statement::Plan(plan) = &program.statements[0] {
+        if let Statement::Plan(plan) = &program.statements[0].0 {
             assert_eq!(plan.name, "Investment Plan 2024");
             assert_eq!(plan.rules.len(), 4);
 
@@ -547,7 +1100,7 @@ mod tests {
         let program = parse_input(input).unwrap();
         assert_eq!(program.statements.len(), 1);
 
-        if let Statement::Define(define) = &program.statements[0] {
+        if let Statement::Define(define) = &program.statements[0].0 {
             assert_eq!(define.symbol.namespace, "ETF");
             assert_eq!(define.symbol.name, "510300");
             assert_eq!(define.alias, Some("CSI 300 ETF".to_string()));
@@ -557,6 +1110,35 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_define_profile() {
+        let input = r#"
+        DEFINE ETF:510300
+            CLASS Equity
+            CATEGORY "宽基"
+            BENCHMARK INDEX:000300
+            CURRENCY CNY
+            RISK 4
+        END
+        "#;
+
+        let program = parse_input(input).unwrap();
+        let Statement::Define(define) = &program.statements[0].0 else {
+            panic!("Expected define statement");
+        };
+        assert_eq!(define.class, Some(AssetClass::Equity));
+        assert_eq!(define.category, Some("宽基".to_string()));
+        assert_eq!(define.benchmark.as_ref().unwrap().to_string(), "INDEX:000300");
+        assert_eq!(define.currency, Some("CNY".to_string()));
+        assert_eq!(define.risk, Some(4));
+
+        let error = parse_input("DEFINE ETF:510300 CLASS STOCK END").unwrap_err();
+        assert!(error.message.starts_with("Unknown asset class 'STOCK'"));
+        let error = parse_input("DEFINE ETF:510300 RISK 2.5 END").unwrap_err();
+        assert_eq!(error.message, "RISK must be an integer from 1 to 5, found 2.5");
+        assert!(parse_input("DEFINE ETF:510300 RISK 6 END").is_err());
+    }
+
     #[test]
     fn test_parse_portfolio() {
         let input = r#"
@@ -569,19 +1151,137 @@ mod tests {
         let program = parse_input(input).unwrap();
         assert_eq!(program.statements.len(), 1);
 
-        if let Statement::Portfolio(portfolio) = &program.statements[0] {
+        if let Statement::Portfolio(portfolio) = &program.statements[0].0 {
             assert_eq!(portfolio.name, "Long Term ETF Investment");
-            assert_eq!(portfolio.assets.len(), 2);
-            assert_eq!(portfolio.assets[0].namespace, "ETF");
-            assert_eq!(portfolio.assets[0].name, "510300");
-            assert_eq!(portfolio.assets[1].namespace, "ETF");
-            assert_eq!(portfolio.assets[1].name, "159915");
+            let assets: Vec<&Symbol> = portfolio.symbols().collect();
+            assert_eq!(assets.len(), 2);
+            assert_eq!(assets[0].namespace, "ETF");
+            assert_eq!(assets[0].name, "510300");
+            assert_eq!(assets[1].namespace, "ETF");
+            assert_eq!(assets[1].name, "159915");
             assert_eq!(portfolio.target_return, Some(0.09));
+            assert!(!portfolio.has_weights());
         } else {
             panic!("Expected portfolio statement");
         }
     }
 
+    #[test]
+    fn test_parse_portfolio_weights() {
+        let input = r#"
+        LET BOND = 0.4
+        PORTFOLIO "股债平衡"
+            ASSETS ETF:510300 60%, ETF:511010 BOND
+        END
+        "#;
+
+        let program = parse_input(input).unwrap();
+        let Statement::Portfolio(portfolio) = &program.statements[1].0 else {
+            panic!("Expected portfolio statement");
+        };
+        let weights: Vec<Option<Decimal>> = portfolio.members.iter().map(|m| m.weight).collect();
+        assert_eq!(
+            weights,
+            vec![Some("0.6".parse().unwrap()), Some("0.4".parse().unwrap())]
+        );
+
+        let error = parse_input(r#"PORTFOLIO "A" ASSETS ETF:510300 60%, ETF:511010 END"#).unwrap_err();
+        assert_eq!(error.message, "Portfolio \"A\" must set a target weight for every asset or none");
+        assert_eq!(error.position, 3);
+
+        let error = parse_input(r#"PORTFOLIO "A" ASSETS ETF:510300 60%, ETF:511010 30% END"#).unwrap_err();
+        assert_eq!(error.message, "Target weights of portfolio \"A\" add up to 90%, expected 100%");
+
+        let error = parse_input(r#"PORTFOLIO "A" ASSETS ETF:510300 110%, ETF:511010 -10% END"#).unwrap_err();
+        assert_eq!(error.message, "Target weight must be positive, found -10%");
+        assert_eq!(error.position, 7);
+
+        let error = parse_input(r#"PORTFOLIO "A" ASSETS ETF:510300 100%, ETF:511010 0% END"#).unwrap_err();
+        assert_eq!(error.message, "Target weight must be positive, found 0%");
+
+        // 合计允许有四舍五入的误差
+        let input = r#"PORTFOLIO "A" ASSETS ETF:510300 33.33%, ETF:511010 33.33%, ETF:159915 33.33% END"#;
+        assert!(parse_input(input).is_ok());
+        let input = r#"LET W = 1 / 3 PORTFOLIO "A" ASSETS ETF:510300 W, ETF:511010 W, ETF:159915 W END"#;
+        assert!(parse_input(input).is_ok());
+        let error = parse_input(r#"PORTFOLIO "A" ASSETS ETF:510300 60%, ETF:511010 40.2% END"#).unwrap_err();
+        assert_eq!(error.message, "Target weights of portfolio \"A\" add up to 100.2%, expected 100%");
+
+        let error = parse_input(r#"PORTFOLIO "A" ASSETS ETF:510300 50%, ETF:510300 50% END"#).unwrap_err();
+        assert_eq!(error.message, "Duplicate member ETF:510300 in portfolio \"A\"");
+        assert_eq!(error.position, 6);
+
+        let error = parse_input(r#"PORTFOLIO "A" ASSETS "B", ETF:510300, "B" END"#).unwrap_err();
+        assert_eq!(error.message, "Duplicate member \"B\" in portfolio \"A\"");
+    }
+
+    #[test]
+    fn test_parse_nested_portfolio() {
+        let input = r#"
+        PORTFOLIO "家庭资产"
+            ASSETS "ETF 长期投资" 70%, CASH:BANK 30%
+        END
+        "#;
+
+        let program = parse_input(input).unwrap();
+        let Statement::Portfolio(portfolio) = &program.statements[0].0 else {
+            panic!("Expected portfolio statement");
+        };
+        // 成员保持书写的顺序
+        assert_eq!(
+            portfolio.members,
+            vec![
+                PortfolioMember {
+                    kind: MemberKind::Portfolio("ETF 长期投资".to_string()),
+                    weight: Some("0.7".parse().unwrap()),
+                },
+                PortfolioMember {
+                    kind: MemberKind::Symbol(Symbol::new("CASH".to_string(), "BANK".to_string())),
+                    weight: Some("0.3".parse().unwrap()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_portfolio_rebalance() {
+        let input = r#"
+        PORTFOLIO "季度"
+            ASSETS ETF:510300 60%, ETF:511010 40%
+            REBALANCE QUARTERLY
+        END
+        PORTFOLIO "阈值"
+            ASSETS ETF:510300 60%, ETF:511010 40%
+            REBALANCE DRIFT 5%
+        END
+        "#;
+
+        let program = parse_input(input).unwrap();
+        let Statement::Portfolio(portfolio) = &program.statements[0].0 else {
+            panic!("Expected portfolio statement");
+        };
+        assert_eq!(portfolio.rebalance, Some(RebalancePolicy::Calendar(Frequency::Quarterly)));
+        let Statement::Portfolio(portfolio) = &program.statements[1].0 else {
+            panic!("Expected portfolio statement");
+        };
+        assert_eq!(portfolio.rebalance, Some(RebalancePolicy::Drift("0.05".parse().unwrap())));
+
+        let error = parse_input(r#"PORTFOLIO "A" ASSETS ETF:510300 REBALANCE MONTHLY END"#).unwrap_err();
+        assert_eq!(error.message, "REBALANCE requires target weights in portfolio \"A\"");
+
+        let error = parse_input(r#"PORTFOLIO "A" ASSETS ETF:510300 1 REBALANCE DRIFT 0 END"#).unwrap_err();
+        assert_eq!(error.message, "REBALANCE DRIFT must be greater than 0");
+
+        let error = parse_input(r#"PORTFOLIO "A" ASSETS ETF:510300 1 REBALANCE DRIFT 150% END"#).unwrap_err();
+        assert_eq!(error.message, "REBALANCE DRIFT must not exceed 100%, found 150%");
+
+        let error = parse_input(r#"PORTFOLIO "A" ASSETS ETF:510300 1 REBALANCE 5% END"#).unwrap_err();
+        assert!(error.message.starts_with("Expected frequency or DRIFT after REBALANCE"));
+
+        let input = r#"PORTFOLIO "A" ASSETS ETF:510300 1 REBALANCE YEARLY REBALANCE MONTHLY END"#;
+        assert_eq!(parse_input(input).unwrap_err().message, "Duplicate REBALANCE");
+    }
+
     #[test]
     fn test_parse_with_note() {
         let input = r#"2024-01-01 TRADE ETF:510300 +5000 CNY @ 4.56
@@ -590,7 +1290,7 @@ mod tests {
         let program = parse_input(input).unwrap();
         assert_eq!(program.statements.len(), 1);
 
-        if let Statement::Record(record) = &program.statements[0] {
+        if let Statement::Record(record) = &program.statements[0].0 {
             assert_eq!(
                 record.note,
                 Some("First investment of the year".to_string())
@@ -600,12 +1300,186 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_trade_with_fee_and_tax() {
+        let input = r#"2024-03-01 TRADE ETF:510300 -2000 CNY @ 4.65 FEE 5 CNY TAX 1.2 CNY"#;
+        let program = parse_input(input).unwrap();
+
+        if let Statement::Record(record) = &program.statements[0].0 {
+            if let Details::Trade(details) = &record.details {
+                assert_eq!(details.fee, Some(Charge::new(Decimal::from(5), "CNY".to_string())));
+                assert_eq!(details.tax, Some(Charge::new("1.2".parse().unwrap(), "CNY".to_string())));
+            } else {
+                panic!("Expected trade details");
+            }
+        } else {
+            panic!("Expected record statement");
+        }
+    }
+
+    #[test]
+    fn test_parse_fee_record() {
+        let input = r#"2024-06-30 FEE ETF:510300 12.5 CNY
+        NOTE "托管费""#;
+        let program = parse_input(input).unwrap();
+
+        if let Statement::Record(record) = &program.statements[0].0 {
+            assert_eq!(record.action, Action::Fee);
+            if let Details::Fee(details) = &record.details {
+                assert_eq!(details.symbol.to_string(), "ETF:510300");
+                assert_eq!(details.charge.amount, 12.5);
+                assert_eq!(details.charge.unit, "CNY");
+            } else {
+                panic!("Expected fee details");
+            }
+            assert_eq!(record.note, Some("托管费".to_string()));
+        } else {
+            panic!("Expected record statement");
+        }
+    }
+
+    #[test]
+    fn test_parse_split_and_merge() {
+        let input = r#"
+        2024-05-01 SPLIT ETF:510300 2:1
+        2024-06-01 SPLIT ETF:159915 0.5
+        2024-07-01 MERGE ETF:510300 INTO ETF:510310
+        2024-08-01 MERGE ETF:159915 INTO ETF:159949 1:4
+        "#;
+        let program = parse_input(input).unwrap();
+        assert_eq!(program.statements.len(), 4);
+
+        let details: Vec<&Details> = program
+            .statements
+            .iter()
+            .map(|(statement, _)| match statement {
+                Statement::Record(record) => &record.details,
+                _ => panic!("Expected record statement"),
+            })
+            .collect();
+
+        assert!(matches!(details[0], Details::Split(split) if split.ratio == 2.0));
+        assert!(matches!(details[1], Details::Split(split) if split.ratio == 0.5));
+
+        if let Details::Merge(merge) = details[2] {
+            assert_eq!(merge.symbol.to_string(), "ETF:510300");
+            assert_eq!(merge.target.to_string(), "ETF:510310");
+            assert_eq!(merge.ratio, 1.0);
+        } else {
+            panic!("Expected merge details");
+        }
+        assert!(matches!(details[3], Details::Merge(merge) if merge.ratio == 0.25));
+    }
+
+    #[test]
+    fn test_parse_split_rejects_zero_ratio() {
+        assert!(parse_input("2024-05-01 SPLIT ETF:510300 0").is_err());
+        assert!(parse_input("2024-05-01 SPLIT ETF:510300 2:0").is_err());
+    }
+
+    #[test]
+    fn test_parse_transfer() {
+        let input = r#"2024-04-01 TRANSFER ETF:510300 -> ETF:159915 3000 CNY @ 2.5"#;
+        let program = parse_input(input).unwrap();
+
+        if let Statement::Record(record) = &program.statements[0].0 {
+            assert_eq!(record.action, Action::Transfer);
+            if let Details::Transfer(details) = &record.details {
+                assert_eq!(details.symbol.to_string(), "ETF:510300");
+                assert_eq!(details.target.to_string(), "ETF:159915");
+                assert_eq!(details.amount, 3000.0);
+                assert_eq!(details.unit, "CNY");
+                assert_eq!(details.price, Some(Decimal::from_f64(2.5)));
+            } else {
+                panic!("Expected transfer details");
+            }
+        } else {
+            panic!("Expected record statement");
+        }
+
+        assert!(parse_input("2024-04-01 TRANSFER ETF:510300 ETF:159915 3000 CNY").is_err());
+    }
+
+    #[test]
+    fn test_parse_cash_records() {
+        let input = r#"
+        2024-01-01 DEPOSIT CASH:BROKER 10000 CNY
+        2024-01-02 TRADE ETF:510300 +5000 CNY @ 4.56 VIA CASH:BROKER FEE 5 CNY
+        2024-12-31 WITHDRAW CASH:BROKER 2000 CNY
+        "#;
+        let program = parse_input(input).unwrap();
+        assert_eq!(program.statements.len(), 3);
+
+        if let Statement::Record(record) = &program.statements[0].0 {
+            assert_eq!(record.action, Action::Deposit);
+            assert!(matches!(&record.details, Details::Cash(cash) if cash.amount == 10000.0));
+        } else {
+            panic!("Expected record statement");
+        }
+
+        if let Statement::Record(record) = &program.statements[1].0 {
+            if let Details::Trade(details) = &record.details {
+                let account = details.account.as_ref().unwrap();
+                assert!(account.is_cash());
+                assert_eq!(account.name, "BROKER");
+                assert_eq!(details.fee, Some(Charge::new(Decimal::from(5), "CNY".to_string())));
+            } else {
+                panic!("Expected trade details");
+            }
+        } else {
+            panic!("Expected record statement");
+        }
+
+        if let Statement::Record(record) = &program.statements[2].0 {
+            assert_eq!(record.action, Action::Withdraw);
+        } else {
+            panic!("Expected record statement");
+        }
+    }
+
+    #[test]
+    fn test_parse_price_statement() {
+        let input = r#"
+        PRICE 2024-01-31 ETF:510300 4.61 CNY
+        2024-02-29 PRICE ETF:510300 4.75 CNY
+        "#;
+        let program = parse_input(input).unwrap();
+        assert_eq!(program.statements.len(), 2);
+
+        for ((statement, _), (date, price)) in program
+            .statements
+            .iter()
+            .zip([("2024-01-31", 4.61), ("2024-02-29", 4.75)])
+        {
+            if let Statement::Record(record) = statement {
+                assert_eq!(record.date, date);
+                assert_eq!(record.action, Action::Price);
+                assert!(matches!(&record.details, Details::Price(p) if p.price == price));
+            } else {
+                panic!("Expected record statement");
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_import_prices() {
+        let program = parse_input(r#"IMPORT PRICES "prices/510300.csv""#).unwrap();
+
+        assert_eq!(
+            program.statements[0].0,
+            Statement::Import(Import {
+                kind: ImportKind::Prices,
+                path: "prices/510300.csv".to_string(),
+            })
+        );
+    }
+
     #[test]
     fn test_parse_negative_amount() {
         let input = r#"2024-03-01 TRADE ETF:510300 -2000 CNY @ 4.65"#;
         let program = parse_input(input).unwrap();
 
-        if let Statement::Record(record) = &program.statements[0] {
+        if let Statement::Record(record) = &program.statements[0].0 {
             if let Details::Trade(details) = &record.details {
                 assert_eq!(details.signed_amount.to_f64(), -2000.0);
             } else {
@@ -615,4 +1489,179 @@ mod tests {
             panic!("Expected record statement");
         }
     }
+
+    #[test]
+    fn test_parse_amount_expressions() {
+        let input = r#"
+        2024-01-02 TRADE ETF:510300 +(1000 * 4.56) CNY @ 4.5 + 0.06 FEE 1000 * 4.56 / 10000 CNY
+        2024-03-31 MARK ETF:510300 VALUE 3000 + 2000 - -100 CNY
+        2024-05-01 SPLIT ETF:510300 (1 + 1):1
+        2024-06-30 MARK ETF:510300 VALUE 5000-1200 CNY
+        "#;
+        let mut parser = Parser::new(Lexer::new(input).tokenize().unwrap());
+        let program = parser.parse().unwrap();
+
+        let Statement::Record(record) = &program.statements[0].0 else {
+            panic!("Expected record statement");
+        };
+        let Details::Trade(details) = &record.details else {
+            panic!("Expected trade details");
+        };
+        assert_eq!(details.signed_amount.to_decimal(), Decimal::from(4560));
+        assert_eq!(details.price, Some(Decimal::from_f64(4.56)));
+        assert_eq!(details.fee.as_ref().unwrap().amount.to_string(), "0.456");
+
+        let Statement::Record(record) = &program.statements[1].0 else {
+            panic!("Expected record statement");
+        };
+        assert!(matches!(&record.details, Details::Mark(mark) if mark.value == 5100.0));
+
+        let Statement::Record(record) = &program.statements[2].0 else {
+            panic!("Expected record statement");
+        };
+        assert!(matches!(&record.details, Details::Split(split) if split.ratio == 2.0));
+
+        // 四位数后面紧跟着 '-' 不是日期
+        let Statement::Record(record) = &program.statements[3].0 else {
+            panic!("Expected record statement");
+        };
+        assert!(matches!(&record.details, Details::Mark(mark) if mark.value == 3800.0));
+    }
+
+    #[test]
+    fn test_parse_expression_constants() {
+        let tokens = Lexer::new("2024-01-02 TRADE ETF:510300 +BUDGET / 2 CNY")
+            .tokenize()
+            .unwrap();
+        let mut parser = Parser::new(tokens);
+        parser.define_constant("BUDGET", Decimal::from(4000));
+        let program = parser.parse().unwrap();
+
+        let Statement::Record(record) = &program.statements[0].0 else {
+            panic!("Expected record statement");
+        };
+        assert!(
+            matches!(&record.details, Details::Trade(trade) if trade.signed_amount.to_f64() == 2000.0)
+        );
+    }
+
+    #[test]
+    fn test_parse_expression_errors() {
+        let input = "2024-01-02 TRADE ETF:510300 +1000 / (2 - 2) CNY";
+        let (tokens, spans) = Lexer::new(input).tokenize_with_spans().unwrap();
+        let mut parser = Parser::with_spans(tokens, spans);
+        let error = parser.parse().unwrap_err();
+        assert_eq!(error.message, "Division by zero");
+        assert_eq!(parser.span_of(&error), Some(Span { line: 1, column: 37 }));
+
+        let error = parse_input("2024-01-02 TRADE ETF:510300 +BUDGET CNY").unwrap_err();
+        assert_eq!(error.message, "Unknown constant 'BUDGET'");
+        assert_eq!(error.position, 4);
+
+        let error = parse_input("2024-01-02 TRADE ETF:510300 +(1000 CNY").unwrap_err();
+        assert!(error.message.starts_with("Expected ')' to close expression"));
+
+        let error = parse_input("2024-03-31 MARK ETF:510300 VALUE 100 - 200 CNY").unwrap_err();
+        assert_eq!(error.message, "Amount must not be negative, found -100");
+    }
+
+    #[test]
+    fn test_parse_expression_overflow() {
+        // 错误的位置指向溢出的运算符, 权重合计溢出的时候指向资产列表的开头
+        for (input, position) in [
+            ("LET A = 1e36 * 1e36", 4),
+            ("LET A = 1e36 * 100 / 0.01", 6),
+            ("LET A = 1e36 + 0.000001", 4),
+            ("LET A = 1 + 1e36 * 1e36", 6),
+            ("LET A = 0 - 1e36 - 0.000001", 6),
+            ("PORTFOLIO \"P\" ASSETS ETF:510300 1e36, ETF:511010 0.000001 END", 3),
+        ] {
+            let error = parse_input(input).unwrap_err();
+            assert_eq!(error.message, "Arithmetic overflow", "{}", input);
+            assert_eq!(error.position, position, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_parse_let_constants() {
+        let input = r#"
+        LET MONTHLY_BUDGET = 4000
+        LET HALF = MONTHLY_BUDGET / 2
+        PLAN "定投"
+          SCHEDULE MONTHLY HALF CNY INTO ETF:510300
+          SCHEDULE MONTHLY MONTHLY_BUDGET - HALF CNY INTO ETF:159915
+        END
+        "#;
+        let program = parse_input(input).unwrap();
+
+        assert_eq!(
+            program.statements[1].0,
+            Statement::Let(Let {
+                name: "HALF".to_string(),
+                value: Decimal::from(2000),
+            })
+        );
+        let Statement::Plan(plan) = &program.statements[2].0 else {
+            panic!("Expected plan statement");
+        };
+        for rule in plan.rules.iter() {
+            assert!(matches!(rule, PlanRule::Schedule(s) if s.amount == 2000.0));
+        }
+    }
+
+    #[test]
+    fn test_parse_let_errors() {
+        let error = parse_input("LET A = 1\nLET A = 2").unwrap_err();
+        assert_eq!(error.message, "Constant 'A' is already defined");
+        assert_eq!(error.position, 5);
+
+        // 常量必须先定义再使用
+        let error = parse_input("2024-01-02 TRADE ETF:510300 +A CNY\nLET A = 1").unwrap_err();
+        assert_eq!(error.message, "Unknown constant 'A'");
+
+        assert!(parse_input("LET A 1").is_err());
+    }
+
+    #[test]
+    fn test_parse_metadata() {
+        let input = r#"
+        DEFINE ETF:510300
+          ALIAS "沪深300ETF"
+          TAG #core
+          META "exchange" "SSE"
+        END
+        2024-01-02 TRADE ETF:510300 +5000 CNY @ 4.56
+          TAG #rebalance #bonus
+          META broker "华泰"
+          NOTE "季度再平衡"
+        "#;
+        let program = parse_input(input).unwrap();
+
+        let Statement::Define(define) = &program.statements[0].0 else {
+            panic!("Expected define statement");
+        };
+        assert!(define.metadata.has_tag("#core"));
+        assert_eq!(define.metadata.get("exchange"), Some(&"SSE".to_string()));
+
+        let Statement::Record(record) = &program.statements[1].0 else {
+            panic!("Expected record statement");
+        };
+        assert_eq!(record.metadata.tags, vec!["rebalance", "bonus"]);
+        assert_eq!(record.metadata.get("broker"), Some(&"华泰".to_string()));
+        assert_eq!(record.note, Some("季度再平衡".to_string()));
+    }
+
+    #[test]
+    fn test_parse_metadata_errors() {
+        let error = parse_input("2024-01-02 MARK ETF:510300 VALUE 1 CNY TAG").unwrap_err();
+        assert!(error.message.starts_with("Expected #tag after TAG"));
+
+        let input = r#"2024-01-02 MARK ETF:510300 VALUE 1 CNY META a "1" META a "2""#;
+        let error = parse_input(input).unwrap_err();
+        assert_eq!(error.message, "Duplicate META key 'a'");
+
+        let input = r#"2024-01-02 MARK ETF:510300 VALUE 1 CNY NOTE "a" NOTE "b""#;
+        let error = parse_input(input).unwrap_err();
+        assert_eq!(error.message, "Duplicate NOTE");
+    }
 }
@@ -1,13 +1,15 @@
+use crate::decimal::Decimal;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Token {
     // Literals
     Date(String),
-    Number(f64),
+    Number(Decimal),
     String(String),
     Identifier(String),
     Symbol(String, String), // namespace:name
     Comment(String),
+    HashTag(String),
     
     // Keywords
     Plan,
@@ -26,6 +28,28 @@ pub enum Token {
     Into,
     Value,
     Note,
+    Fee,
+    Tax,
+    Split,
+    Merge,
+    Transfer,
+    Deposit,
+    Withdraw,
+    Via,
+    Price,
+    Import,
+    Prices,
+    Include,
+    Let,
+    Tag,
+    Meta,
+    Class,
+    Category,
+    Benchmark,
+    Currency,
+    Risk,
+    Rebalance,
+    Drift,
     
     // Frequency keywords
     Daily,
@@ -37,7 +61,11 @@ pub enum Token {
     // Operators
     Plus,
     Minus,
+    Star,
+    Slash,
     At,
+    Arrow,
+    Equals,
     
     // Punctuation
     Colon,
@@ -72,6 +100,28 @@ impl Token {
             "INTO" => Some(Token::Into),
             "VALUE" => Some(Token::Value),
             "NOTE" => Some(Token::Note),
+            "FEE" => Some(Token::Fee),
+            "TAX" => Some(Token::Tax),
+            "SPLIT" => Some(Token::Split),
+            "MERGE" => Some(Token::Merge),
+            "TRANSFER" => Some(Token::Transfer),
+            "DEPOSIT" => Some(Token::Deposit),
+            "WITHDRAW" => Some(Token::Withdraw),
+            "VIA" => Some(Token::Via),
+            "PRICE" => Some(Token::Price),
+            "IMPORT" => Some(Token::Import),
+            "PRICES" => Some(Token::Prices),
+            "INCLUDE" => Some(Token::Include),
+            "LET" => Some(Token::Let),
+            "TAG" => Some(Token::Tag),
+            "META" => Some(Token::Meta),
+            "CLASS" => Some(Token::Class),
+            "CATEGORY" => Some(Token::Category),
+            "BENCHMARK" => Some(Token::Benchmark),
+            "CURRENCY" => Some(Token::Currency),
+            "RISK" => Some(Token::Risk),
+            "REBALANCE" => Some(Token::Rebalance),
+            "DRIFT" => Some(Token::Drift),
             "DAILY" => Some(Token::Daily),
             "WEEKLY" => Some(Token::Weekly),
             "MONTHLY" => Some(Token::Monthly),
@@ -85,3 +135,10 @@ impl Token {
         matches!(self, Token::Daily | Token::Weekly | Token::Monthly | Token::Quarterly | Token::Yearly)
     }
 }
+
+// token 在源码中的起始位置, 行和列都从 1 开始
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+}
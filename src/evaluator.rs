@@ -1,4 +1,18 @@
+pub mod allocation;
+pub(crate) mod calendar;
 pub mod engine;
+pub mod filter;
 mod output;
+pub mod plan;
+pub mod price;
+pub mod rebalance;
 
 pub use engine::Engine;
+
+// 测试里解析并执行一段 DSL, 出错时直接 panic
+#[cfg(test)]
+pub(crate) fn evaluate_str(input: &str) -> engine::AnalysisReport {
+    let tokens = crate::dsl::Lexer::new(input).tokenize().unwrap();
+    let program = crate::dsl::Parser::new(tokens).parse().unwrap();
+    Engine::new().evaluate(program).unwrap()
+}
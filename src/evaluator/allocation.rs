@@ -0,0 +1,214 @@
+use crate::decimal::Decimal;
+use crate::evaluator::engine::{AnalysisReport, Asset};
+
+// 资产配置按照哪个属性汇总
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AllocationKey {
+    Class,
+    Category,
+    Currency,
+    Risk,
+}
+
+impl AllocationKey {
+    fn label(&self, asset: &Asset) -> Option<String> {
+        match self {
+            AllocationKey::Class => asset.get_class().map(|class| class.to_string()),
+            AllocationKey::Category => asset.category.clone(),
+            AllocationKey::Currency => asset.currency.clone(),
+            AllocationKey::Risk => asset.risk.map(|risk| risk.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AllocationSlice {
+    // 没有设置对应属性的资产归到 None
+    pub label: Option<String>,
+    pub value: Decimal,
+    // 占总价值的比例
+    pub weight: f64,
+    pub symbols: Vec<String>,
+}
+
+// 按照最新的期末价值统计资产配置, 价值不为正的资产不参与统计
+// 结果按价值从大到小排列, 未分类的放在最后; 按币种汇总时每个币种的占比都是在自己的币种内计算的
+pub fn allocation(report: &AnalysisReport, key: AllocationKey) -> Vec<AllocationSlice> {
+    let mut slices: Vec<AllocationSlice> = Vec::new();
+    let mut assets: Vec<&Asset> = report.assets.iter().collect();
+    assets.sort_by(|a, b| a.get_symbol().cmp(b.get_symbol()));
+
+    for asset in assets {
+        let Some(summary) = report.asset_summary(asset.get_symbol()) else {
+            continue;
+        };
+        if !summary.value.is_positive() {
+            continue;
+        }
+
+        let label = key.label(asset);
+        match slices.iter_mut().find(|slice| slice.label == label) {
+            Some(slice) => {
+                slice.value += summary.value;
+                slice.symbols.push(asset.get_symbol().clone());
+            }
+            None => slices.push(AllocationSlice {
+                label,
+                value: summary.value,
+                weight: 0.0,
+                symbols: vec![asset.get_symbol().clone()],
+            }),
+        }
+    }
+
+    // 不同币种的价值没有汇率不能相加, 按币种汇总时占比在各自的币种内计算, 结果按币种排列
+    let by_currency = key == AllocationKey::Currency;
+    let total: Decimal = slices.iter().map(|slice| slice.value).sum();
+    for slice in slices.iter_mut() {
+        let total = if by_currency { slice.value } else { total };
+        slice.weight = slice.value.to_f64() / total.to_f64();
+    }
+    slices.sort_by(|a, b| {
+        let order = if by_currency {
+            a.label.cmp(&b.label)
+        } else {
+            b.value.cmp(&a.value)
+        };
+        a.label.is_none().cmp(&b.label.is_none()).then(order)
+    });
+
+    slices
+}
+
+// 资产和基准在同一段时间内的收益对比
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchmarkComparison {
+    pub symbol: String,
+    pub benchmark: String,
+    // 资产第一条记录的日期
+    pub start: String,
+    // 资产最后一条记录的日期
+    pub end: String,
+    // 累积收益 / (总投入 + 转入)
+    pub asset_return: f64,
+    // 这段时间内基准价格的涨跌幅
+    pub benchmark_return: f64,
+    // asset_return - benchmark_return
+    pub excess_return: f64,
+}
+
+// 资产没有设置 BENCHMARK, 没有投入, 或者基准缺少开始那天的价格时返回 None
+pub fn benchmark_comparison(report: &AnalysisReport, symbol: &str) -> Option<BenchmarkComparison> {
+    let asset = report.assets.iter().find(|a| a.get_symbol() == symbol)?;
+    let benchmark = asset.benchmark.as_ref()?;
+
+    let history = report.asset_history(symbol);
+    let (start, _) = history.first()?;
+    let (end, summary) = history.last()?;
+
+    let invested = summary.total_purchase + summary.total_transfer_in;
+    if !invested.is_positive() {
+        return None;
+    }
+    let asset_return = summary.profit.to_f64() / invested.to_f64();
+
+    let start_price = report.prices.price_at(benchmark, start)?.price;
+    let end_price = report.prices.price_at(benchmark, end)?.price;
+    if !start_price.is_positive() {
+        return None;
+    }
+    let benchmark_return = (end_price / start_price).to_f64() - 1.0;
+
+    Some(BenchmarkComparison {
+        symbol: symbol.to_string(),
+        benchmark: benchmark.clone(),
+        start: start.clone(),
+        end: end.clone(),
+        asset_return,
+        benchmark_return,
+        excess_return: asset_return - benchmark_return,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluator::evaluate_str;
+
+    const INPUT: &str = r#"
+        DEFINE ETF:510300
+          CLASS EQUITY
+          CATEGORY "宽基"
+          BENCHMARK INDEX:000300
+        END
+        DEFINE ETF:511010
+          CLASS BOND
+        END
+        PRICE 2024-01-01 INDEX:000300 3500 CNY
+        PRICE 2024-06-30 INDEX:000300 3675 CNY
+        2024-01-02 TRADE ETF:510300 +6000 CNY
+        2024-01-02 TRADE ETF:511010 +2000 CNY
+        2024-01-02 TRADE ETF:159915 +1000 CNY
+        2024-01-02 DEPOSIT CASH:BROKER 1000 CNY
+        2024-06-30 MARK ETF:510300 VALUE 6600 CNY
+    "#;
+
+    #[test]
+    fn test_allocation_by_class() {
+        let report = evaluate_str(INPUT);
+        let slices = allocation(&report, AllocationKey::Class);
+
+        let summary: Vec<(Option<&str>, f64)> = slices
+            .iter()
+            .map(|s| (s.label.as_deref(), s.value.to_f64()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (Some("EQUITY"), 6600.0),
+                (Some("BOND"), 2000.0),
+                (Some("CASH"), 1000.0),
+                (None, 1000.0),
+            ]
+        );
+        assert!((slices[0].weight - 6600.0 / 10600.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_allocation_by_currency() {
+        let report = evaluate_str(
+            r#"
+            DEFINE ETF:510300 CURRENCY CNY END
+            DEFINE ETF:511010 CURRENCY CNY END
+            DEFINE US:VOO CURRENCY USD END
+            2024-01-02 TRADE ETF:510300 +6000 CNY
+            2024-01-02 TRADE ETF:511010 +2000 CNY
+            2024-01-02 TRADE US:VOO +1000 USD
+            "#,
+        );
+
+        // 人民币和美元的价值不相加, 各自都是 100%
+        let slices = allocation(&report, AllocationKey::Currency);
+        let summary: Vec<(Option<&str>, f64, f64)> = slices
+            .iter()
+            .map(|s| (s.label.as_deref(), s.value.to_f64(), s.weight))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![(Some("CNY"), 8000.0, 1.0), (Some("USD"), 1000.0, 1.0)]
+        );
+    }
+
+    #[test]
+    fn test_benchmark_comparison() {
+        let report = evaluate_str(INPUT);
+        let comparison = benchmark_comparison(&report, "ETF:510300").unwrap();
+
+        assert_eq!(comparison.start, "2024-01-02");
+        assert_eq!(comparison.end, "2024-06-30");
+        assert!((comparison.asset_return - 0.1).abs() < 1e-9);
+        assert!((comparison.benchmark_return - 0.05).abs() < 1e-9);
+        assert!((comparison.excess_return - 0.05).abs() < 1e-9);
+        assert!(benchmark_comparison(&report, "ETF:511010").is_none());
+    }
+}
@@ -1,14 +1,37 @@
-use super::output::Output;
+use crate::decimal::Decimal;
 use crate::dsl::ast::Portfolio as PortfolioStatement;
-use crate::dsl::ast::{Define, Details, Program, Record, Statement};
+use crate::dsl::ast::{
+    Action, AssetClass, CASH_NAMESPACE, Define, Details, Import, ImportKind, MemberKind, Metadata,
+    Origin, Plan, PortfolioMember, Program, RebalancePolicy, Record, Statement, has_weights,
+};
 use crate::evaluator::output::RecordOutput;
-use std::collections::HashMap;
+use crate::evaluator::price::{PriceHistory, PricePoint};
+use crate::importer::{ImportError, prices};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+// 份额 x 价格 得到的价值保留的小数位数; 份额是金额除以价格得到的, 更多的位数没有意义
+pub(crate) const VALUE_SCALE: u32 = 10;
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Asset {
     pub symbol: String,
     pub alias: Option<String>,
     pub target_return: Option<f64>,
+    pub class: Option<AssetClass>,
+    pub category: Option<String>,
+    // 基准的标的, 比如 INDEX:000300
+    pub benchmark: Option<String>,
+    pub currency: Option<String>,
+    // 风险等级, 1 到 5
+    pub risk: Option<u8>,
+    // DEFINE 里的 TAG 和 META
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Metadata::is_empty")
+    )]
+    pub metadata: Metadata,
 }
 
 impl Asset {
@@ -17,6 +40,12 @@ impl Asset {
             symbol,
             alias,
             target_return,
+            class: None,
+            category: None,
+            benchmark: None,
+            currency: None,
+            risk: None,
+            metadata: Metadata::default(),
         }
     }
 
@@ -31,27 +60,80 @@ impl Asset {
     pub fn get_target_return(&self) -> &Option<f64> {
         &self.target_return
     }
+
+    // 没有写 CLASS 的现金账户归为现金类
+    pub fn get_class(&self) -> Option<AssetClass> {
+        self.class.or_else(|| {
+            self.symbol
+                .starts_with(&format!("{}:", CASH_NAMESPACE))
+                .then_some(AssetClass::Cash)
+        })
+    }
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Portfolio {
     pub name: String,
+    // 直接列出的资产
     pub assets: Vec<Asset>,
+    // ASSETS 里的资产和子组合以及它们的目标权重, 保持书写的顺序
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub members: Vec<PortfolioMember>,
     pub target_return: f64,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub rebalance: Option<RebalancePolicy>,
 }
 
-pub struct Plan {
-    pub name: String,
+impl Portfolio {
+    // 包含的子组合的名字
+    pub fn portfolios(&self) -> impl Iterator<Item = &String> {
+        self.members.iter().filter_map(|member| match &member.kind {
+            MemberKind::Symbol(_) => None,
+            MemberKind::Portfolio(name) => Some(name),
+        })
+    }
+
+    pub fn has_target_weights(&self) -> bool {
+        has_weights(&self.members)
+    }
+}
+
+// 同时出现在多个组合里的资产, 把这些组合的价值直接相加会重复计算
+#[derive(Debug, Clone, PartialEq)]
+pub struct SharedAsset {
+    pub symbol: String,
+    // 包含这个资产的组合, 子组合里的资产也算, 按照定义的顺序
+    // 只列出和其他不相互包含的组合重复的那些, 组合和它自己的子组合同时包含不算共享
+    pub owners: Vec<AssetOwner>,
+    // 最新的期末价值
+    pub value: Decimal,
+    // 占整个账本期末价值的比例
+    pub book_weight: f64,
 }
 
+// 包含共享资产的一个组合
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssetOwner {
+    pub portfolio: String,
+    // 资产是通过哪个子组合包含进来的, 在 ASSETS 里直接列出的时候为 None
+    pub via: Option<String>,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AnalysisReport {
     // 这里定义有哪些资产, 不包含资产的财务指标
     pub assets: Vec<Asset>,
     // 这里是定义了哪些组合
     pub portfolios: Vec<Portfolio>,
+    // 定投计划, 用来检查计划的执行情况
+    pub plans: Vec<Plan>,
 
     // 这里就是 DSL 执行完了之后生成的结果, 按照每天进行汇总
     pub daily_snapshot: HashMap<String, Vec<DailySnapshot>>,
+
+    // PRICE 语句和交易价格汇总出来的历史价格
+    pub prices: PriceHistory,
 }
 
 impl AnalysisReport {
@@ -59,21 +141,274 @@ impl AnalysisReport {
         Self {
             assets: Vec::new(),
             portfolios: Vec::new(),
+            plans: Vec::new(),
             daily_snapshot: HashMap::new(),
+            prices: PriceHistory::new(),
         }
     }
 }
 
+impl AnalysisReport {
+    // 资产最新的汇总指标, 也就是最后一条快照
+    pub fn asset_summary(&self, symbol: &str) -> Option<Summary> {
+        self.daily_snapshot
+            .get(symbol)
+            .and_then(|days| days.last())
+            .and_then(|day| day.snapshots.last())
+            .map(Summary::from_snapshot)
+    }
+
+    pub fn find_portfolio(&self, name: &str) -> Option<&Portfolio> {
+        self.portfolios.iter().find(|p| p.name == name)
+    }
+
+    // 组合里所有的资产, 子组合里的资产也会展开; 同一个资产出现多次的时候只保留第一次
+    pub fn portfolio_symbols(&self, portfolio: &Portfolio) -> Vec<String> {
+        let mut symbols = Vec::new();
+        self.collect_symbols(portfolio, &mut vec![portfolio.name.as_str()], &mut symbols);
+
+        let mut seen = HashSet::new();
+        symbols.retain(|symbol| seen.insert(symbol.clone()));
+        symbols
+    }
+
+    fn collect_symbols<'a>(
+        &'a self,
+        portfolio: &'a Portfolio,
+        path: &mut Vec<&'a str>,
+        symbols: &mut Vec<String>,
+    ) {
+        symbols.extend(portfolio.assets.iter().map(|a| a.get_symbol().clone()));
+        for name in portfolio.portfolios() {
+            // Engine 已经拒绝了循环引用, 这里防止反序列化得到的报告死循环
+            if path.contains(&name.as_str()) {
+                continue;
+            }
+            if let Some(child) = self.find_portfolio(name) {
+                path.push(name);
+                self.collect_symbols(child, path, symbols);
+                path.pop();
+            }
+        }
+    }
+
+    // 组合的汇总指标, 现金账户和普通资产一样计入组合价值, 子组合按照其中的资产汇总
+    pub fn portfolio_summary(&self, portfolio: &Portfolio) -> Summary {
+        let mut summary = Summary::default();
+        for symbol in self.portfolio_symbols(portfolio) {
+            if let Some(asset_summary) = self.asset_summary(&symbol) {
+                summary.add(&asset_summary);
+            }
+        }
+
+        summary
+    }
+
+    // 整个账本的汇总指标, 每个资产只计算一次, 不管它在几个组合里
+    pub fn book_summary(&self) -> Summary {
+        let mut summary = Summary::default();
+        for symbol in self.daily_snapshot.keys() {
+            if let Some(asset_summary) = self.asset_summary(symbol) {
+                summary.add(&asset_summary);
+            }
+        }
+
+        summary
+    }
+
+    // 被多个组合包含的资产, 按照代码排列
+    // 子组合包含在父组合里不算共享, 父组合的汇总已经去掉了重复
+    pub fn shared_assets(&self) -> Vec<SharedAsset> {
+        let expanded: Vec<(&Portfolio, Vec<String>, Vec<&str>)> = self
+            .portfolios
+            .iter()
+            .map(|p| (p, self.portfolio_symbols(p), self.descendants(p)))
+            .collect();
+        let mut symbols: Vec<&String> = expanded.iter().flat_map(|(_, s, _)| s).collect();
+        symbols.sort();
+        symbols.dedup();
+
+        // 一个组合是另一个的后代的时候两者相互包含
+        let nested = |a: &Portfolio, b: &Portfolio| {
+            expanded.iter().any(|(p, _, descendants)| {
+                (p.name == a.name && descendants.contains(&b.name.as_str()))
+                    || (p.name == b.name && descendants.contains(&a.name.as_str()))
+            })
+        };
+
+        let mut owners: Vec<(&String, Vec<AssetOwner>)> = Vec::new();
+        for symbol in symbols {
+            let containing: Vec<&Portfolio> = expanded
+                .iter()
+                .filter(|(_, symbols, _)| symbols.contains(symbol))
+                .map(|(p, _, _)| *p)
+                .collect();
+            let shared: Vec<AssetOwner> = containing
+                .iter()
+                .filter(|a| containing.iter().any(|b| a.name != b.name && !nested(a, b)))
+                .map(|portfolio| AssetOwner {
+                    portfolio: portfolio.name.clone(),
+                    via: self.child_containing(portfolio, symbol),
+                })
+                .collect();
+            if !shared.is_empty() {
+                owners.push((symbol, shared));
+            }
+        }
+
+        let total = self.book_summary().value;
+        owners
+            .into_iter()
+            .map(|(symbol, owners)| {
+                let value = self
+                    .asset_summary(symbol)
+                    .map_or(Decimal::ZERO, |summary| summary.value);
+                SharedAsset {
+                    symbol: symbol.clone(),
+                    owners,
+                    value,
+                    book_weight: if total.is_positive() {
+                        value.to_f64() / total.to_f64()
+                    } else {
+                        0.0
+                    },
+                }
+            })
+            .collect()
+    }
+
+    // 组合直接或者间接包含的所有子组合的名字
+    fn descendants<'a>(&'a self, portfolio: &'a Portfolio) -> Vec<&'a str> {
+        let mut names = vec![portfolio.name.as_str()];
+        let mut index = 0;
+        while index < names.len() {
+            if let Some(current) = self.find_portfolio(names[index]) {
+                for child in current.portfolios() {
+                    // 防止反序列化得到的报告里有循环引用
+                    if !names.contains(&child.as_str()) {
+                        names.push(child);
+                    }
+                }
+            }
+            index += 1;
+        }
+        names.remove(0);
+        names
+    }
+
+    // 资产不是直接列出的时候, 包含它的第一个子组合
+    fn child_containing(&self, portfolio: &Portfolio, symbol: &str) -> Option<String> {
+        if portfolio.assets.iter().any(|a| a.get_symbol() == symbol) {
+            return None;
+        }
+        portfolio
+            .portfolios()
+            .find(|name| {
+                self.find_portfolio(name)
+                    .is_some_and(|child| self.portfolio_symbols(child).iter().any(|s| s == symbol))
+            })
+            .cloned()
+    }
+
+    // 资产每天的汇总指标, 按日期排列
+    pub fn asset_history(&self, symbol: &str) -> Vec<(String, Summary)> {
+        self.daily_snapshot
+            .get(symbol)
+            .map(|days| {
+                days.iter()
+                    .filter_map(|day| {
+                        let shot = day.snapshots.last()?;
+                        Some((day.date.clone(), Summary::from_snapshot(shot)))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    // 组合每天的汇总指标, 没有记录的资产沿用之前最近一天的指标
+    pub fn portfolio_history(&self, portfolio: &Portfolio) -> Vec<(String, Summary)> {
+        let histories: Vec<Vec<(String, Summary)>> = self
+            .portfolio_symbols(portfolio)
+            .iter()
+            .map(|symbol| self.asset_history(symbol))
+            .collect();
+
+        let mut dates: Vec<&String> = histories
+            .iter()
+            .flat_map(|history| history.iter().map(|(date, _)| date))
+            .collect();
+        dates.sort();
+        dates.dedup();
+
+        dates
+            .into_iter()
+            .map(|date| {
+                let mut summary = Summary::default();
+                for history in histories.iter() {
+                    let index = history.partition_point(|(d, _)| d <= date);
+                    if index > 0 {
+                        summary.add(&history[index - 1].1);
+                    }
+                }
+                (date.clone(), summary)
+            })
+            .collect()
+    }
+}
+
+impl Default for AnalysisReport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Debug)]
-pub struct EngineError {}
+pub struct EngineError {
+    pub message: String,
+    // 出错的语句来自哪个文件的哪一行, 没有位置信息的时候为 None
+    pub origin: Option<Origin>,
+}
+
 impl EngineError {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(message: String) -> Self {
+        Self {
+            message,
+            origin: None,
+        }
+    }
+
+    // 金额计算溢出, 和 Parser 一样报错而不是 panic
+    fn overflow() -> Self {
+        Self::new("Arithmetic overflow".to_string())
+    }
+
+    // 补上出错语句的位置, 已经有位置的时候保持不变
+    fn at(mut self, origin: Option<&Origin>) -> Self {
+        if self.origin.is_none() {
+            self.origin = origin.cloned();
+        }
+        self
+    }
+}
+
+impl std::fmt::Display for EngineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.origin {
+            Some(origin) => write!(f, "{}: {}", origin, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl From<ImportError> for EngineError {
+    fn from(error: ImportError) -> Self {
+        Self::new(format!("Import failed: {}", error))
     }
 }
 
 // record 语句执行后的快照
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Snapshot {
     pub symbol: String,
     pub date: String,
@@ -83,32 +418,109 @@ pub struct Snapshot {
 
     // 执行的结果
     // 总投入
-    pub total_purchase: f64,
+    pub total_purchase: Decimal,
     // 总转出
-    pub total_sale: f64,
+    pub total_sale: Decimal,
+    // 从其他资产转入
+    pub total_transfer_in: Decimal,
+    // 转出到其他资产
+    pub total_transfer_out: Decimal,
     // 期末价值
-    pub value: f64,
-    // 累积收益, 正负均有可能
-    pub profit: f64,
+    pub value: Decimal,
+    // 持有份额
+    pub units: Decimal,
+    // 每份的平均持有成本
+    pub cost_per_unit: Option<Decimal>,
+    // 当天或者之前最近的单位价格
+    pub price: Option<Decimal>,
+    // 累积手续费
+    pub total_fee: Decimal,
+    // 累积税费
+    pub total_tax: Decimal,
+    // 累积收益(扣除费用后), 正负均有可能
+    pub profit: Decimal,
+    // 累积收益(扣除费用前)
+    pub gross_profit: Decimal,
 }
 
 impl Snapshot {
     fn from_output(output: &RecordOutput) -> Self {
         Self {
-            symbol: output.program.details.get_symbol().to_string(),
+            symbol: output.symbol.clone(),
             date: output.program.date.clone(),
             statement: output.program.clone(),
             total_purchase: output.total_purchase,
             total_sale: output.total_sale,
+            total_transfer_in: output.total_transfer_in,
+            total_transfer_out: output.total_transfer_out,
             value: output.value,
+            units: output.units,
+            cost_per_unit: output.cost_per_unit,
+            price: output.price,
+            total_fee: output.total_fee,
+            total_tax: output.total_tax,
             profit: output.profit,
+            gross_profit: output.gross_profit,
+        }
+    }
+}
+
+// 资产或者组合在某个时间点的汇总指标
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Summary {
+    // 总投入
+    pub total_purchase: Decimal,
+    // 总转出
+    pub total_sale: Decimal,
+    // 从其他资产转入
+    pub total_transfer_in: Decimal,
+    // 转出到其他资产
+    pub total_transfer_out: Decimal,
+    // 期末价值
+    pub value: Decimal,
+    // 累积手续费
+    pub total_fee: Decimal,
+    // 累积税费
+    pub total_tax: Decimal,
+    // 累积收益(扣除费用后), 正负均有可能
+    pub profit: Decimal,
+    // 累积收益(扣除费用前)
+    pub gross_profit: Decimal,
+}
+
+impl Summary {
+    fn from_snapshot(snapshot: &Snapshot) -> Self {
+        Self {
+            total_purchase: snapshot.total_purchase,
+            total_sale: snapshot.total_sale,
+            total_transfer_in: snapshot.total_transfer_in,
+            total_transfer_out: snapshot.total_transfer_out,
+            value: snapshot.value,
+            total_fee: snapshot.total_fee,
+            total_tax: snapshot.total_tax,
+            profit: snapshot.profit,
+            gross_profit: snapshot.gross_profit,
         }
     }
+
+    fn add(&mut self, other: &Summary) {
+        self.total_purchase += other.total_purchase;
+        self.total_sale += other.total_sale;
+        self.total_transfer_in += other.total_transfer_in;
+        self.total_transfer_out += other.total_transfer_out;
+        self.value += other.value;
+        self.total_fee += other.total_fee;
+        self.total_tax += other.total_tax;
+        self.profit += other.profit;
+        self.gross_profit += other.gross_profit;
+    }
 }
 
 // 资产的每日快照
 // 记录这一天所有的 DSL 脚本及其执行的结果
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DailySnapshot {
     pub symbol: String,
     pub date: String,
@@ -137,26 +549,45 @@ struct EngineState {
     // 集合output可以生成一个资产每天的最新快照
     // 每天的快照再进行聚合，就能计算出最终的资产价值
     snapshots: HashMap<String, RecordOutput>,
+
+    // 历史价格
+    prices: PriceHistory,
+
+    // 有过推算不出份额的金额变动的资产, 它们的份额不完整, PRICE 不会按照份额重新估值
+    partial_units: HashSet<String>,
 }
 
 #[derive(Debug, Clone)]
 struct AssetMetric {
     // 总投入
-    total_purchase: f64,
+    total_purchase: Decimal,
     // 总转出
-    total_sale: f64,
+    total_sale: Decimal,
+    // 从其他资产转入
+    total_transfer_in: Decimal,
+    // 转出到其他资产
+    total_transfer_out: Decimal,
     // 期末价值
-    value: f64,
-    // // 累积收益, 正负均有可能
-    // profit: f64,
+    value: Decimal,
+    // 持有份额
+    units: Decimal,
+    // 累积手续费
+    total_fee: Decimal,
+    // 累积税费
+    total_tax: Decimal,
 }
 
 impl AssetMetric {
     fn new_zero() -> Self {
         Self {
-            total_purchase: 0.0,
-            total_sale: 0.0,
-            value: 0.0,
+            total_purchase: Decimal::ZERO,
+            total_sale: Decimal::ZERO,
+            total_transfer_in: Decimal::ZERO,
+            total_transfer_out: Decimal::ZERO,
+            value: Decimal::ZERO,
+            units: Decimal::ZERO,
+            total_fee: Decimal::ZERO,
+            total_tax: Decimal::ZERO,
         }
     }
 
@@ -164,12 +595,65 @@ impl AssetMetric {
         Self {
             total_purchase: output.total_purchase,
             total_sale: output.total_sale,
+            total_transfer_in: output.total_transfer_in,
+            total_transfer_out: output.total_transfer_out,
             value: output.value,
+            units: output.units,
+            total_fee: output.total_fee,
+            total_tax: output.total_tax,
         }
     }
 
-    pub fn get_profit(&self) -> f64 {
-        self.value - self.total_purchase + self.total_sale
+    // 把另一个资产的指标并入当前资产, 份额按 ratio 换算
+    fn absorb(&mut self, other: &AssetMetric, ratio: Decimal) -> Result<(), EngineError> {
+        let add = |a: Decimal, b: Decimal| a.checked_add(b).ok_or_else(EngineError::overflow);
+        let units = other
+            .units
+            .checked_mul(ratio)
+            .ok_or_else(EngineError::overflow)?;
+
+        self.total_purchase = add(self.total_purchase, other.total_purchase)?;
+        self.total_sale = add(self.total_sale, other.total_sale)?;
+        self.total_transfer_in = add(self.total_transfer_in, other.total_transfer_in)?;
+        self.total_transfer_out = add(self.total_transfer_out, other.total_transfer_out)?;
+        self.value = add(self.value, other.value)?;
+        self.units = add(self.units, units)?;
+        self.total_fee = add(self.total_fee, other.total_fee)?;
+        self.total_tax = add(self.total_tax, other.total_tax)?;
+        Ok(())
+    }
+
+    // 净投入, 资产之间的转换也算在内
+    fn get_net_invested(&self) -> Result<Decimal, EngineError> {
+        self.total_purchase
+            .checked_sub(self.total_sale)
+            .and_then(|net| net.checked_add(self.total_transfer_in))
+            .and_then(|net| net.checked_sub(self.total_transfer_out))
+            .ok_or_else(EngineError::overflow)
+    }
+
+    // 每份的平均成本
+    pub fn get_cost_per_unit(&self) -> Result<Option<Decimal>, EngineError> {
+        if !self.units.is_positive() {
+            return Ok(None);
+        }
+        let cost = self.get_net_invested()?.checked_div(self.units);
+        cost.map(Some).ok_or_else(EngineError::overflow)
+    }
+
+    // 扣除费用前的收益
+    pub fn get_gross_profit(&self) -> Result<Decimal, EngineError> {
+        self.value
+            .checked_sub(self.get_net_invested()?)
+            .ok_or_else(EngineError::overflow)
+    }
+
+    // 扣除费用后的收益
+    pub fn get_profit(&self) -> Result<Decimal, EngineError> {
+        self.get_gross_profit()?
+            .checked_sub(self.total_fee)
+            .and_then(|profit| profit.checked_sub(self.total_tax))
+            .ok_or_else(EngineError::overflow)
     }
 }
 
@@ -181,6 +665,8 @@ impl EngineState {
             assets: HashMap::new(),
             record_outputs: Vec::new(),
             snapshots: HashMap::new(),
+            prices: PriceHistory::new(),
+            partial_units: HashSet::new(),
         }
     }
 
@@ -198,40 +684,161 @@ impl EngineState {
                 if let Some(target_return) = args.target_return {
                     asset.target_return = Some(target_return);
                 };
+                if let Some(class) = args.class {
+                    asset.class = Some(class);
+                }
+                if let Some(category) = args.category {
+                    asset.category = Some(category);
+                }
+                if let Some(benchmark) = args.benchmark {
+                    asset.benchmark = Some(benchmark);
+                }
+                if let Some(currency) = args.currency {
+                    asset.currency = Some(currency);
+                }
+                if let Some(risk) = args.risk {
+                    asset.risk = Some(risk);
+                }
+                asset.metadata.merge(&args.metadata);
 
                 let asset = asset.clone();
                 self.update_portfolio_assets(asset);
             }
             Entry::Vacant(entry) => {
-                let asset = Asset::new(args.symbol.to_string(), args.name, args.target_return);
+                let mut asset = Asset::new(args.symbol.to_string(), args.name, args.target_return);
+                asset.class = args.class;
+                asset.category = args.category;
+                asset.benchmark = args.benchmark;
+                asset.currency = args.currency;
+                asset.risk = args.risk;
+                asset.metadata = args.metadata;
                 entry.insert(asset);
             }
         }
     }
 
+    fn last_metric(&self, symbol: &str) -> AssetMetric {
+        match self.snapshots.get(symbol) {
+            None => AssetMetric::new_zero(),
+            Some(shot) => AssetMetric::from_record(shot),
+        }
+    }
+
+    // 某一天 (含) 之前最近的有效价格
+    fn known_price(&self, symbol: &str, date: &str) -> Option<Decimal> {
+        self.prices
+            .price_at(symbol, date)
+            .map(|point| point.price)
+            .filter(|price| price.is_positive())
+    }
+
+    fn push_output(
+        &mut self,
+        symbol: String,
+        record: &Record,
+        metric: AssetMetric,
+    ) -> Result<RecordOutput, EngineError> {
+        let price = self.prices.price_at(&symbol, &record.date).map(|p| p.price);
+        let output = RecordOutput::from_record_with_metric(symbol.clone(), record, metric, price)?;
+        self.record_outputs.push(output.clone());
+        self.snapshots.insert(symbol, output.clone());
+        Ok(output)
+    }
+
     // 计算资产的最新的状态
-    fn calc_asset(&mut self, record: &Record) -> Result<Output, EngineError> {
+    fn calc_asset(&mut self, record: &Record) -> Result<RecordOutput, EngineError> {
         let symbol = record.details.get_symbol().to_string();
 
-        let last = match self.snapshots.get(&symbol) {
-            None => AssetMetric::new_zero(),
-            Some(shot) => AssetMetric::from_record(shot),
-        };
+        let last = self.last_metric(&symbol);
 
         let details = &record.details;
         let mut new_snapshot = last.clone();
 
+        // 金额都来自账本, 溢出的时候报错
+        let add = |a: Decimal, b: Decimal| a.checked_add(b).ok_or_else(EngineError::overflow);
+        let sub = |a: Decimal, b: Decimal| a.checked_sub(b).ok_or_else(EngineError::overflow);
+        let mul = |a: Decimal, b: Decimal| a.checked_mul(b).ok_or_else(EngineError::overflow);
+        let div = |a: Decimal, b: Decimal| a.checked_div(b).ok_or_else(EngineError::overflow);
+
         match details {
             Details::Trade(trade) => {
                 let value = trade.signed_amount.value;
-                if trade.buy() {
-                    new_snapshot.total_purchase = last.total_purchase + value;
+                // 通过现金账户的交易是现金和资产之间的内部转换, 不算作新的投入或者转出
+                if trade.account.is_some() {
+                    if trade.buy() {
+                        new_snapshot.total_transfer_in = add(last.total_transfer_in, value)?;
+                    } else {
+                        new_snapshot.total_transfer_out = add(last.total_transfer_out, value)?;
+                    }
+                } else if trade.buy() {
+                    new_snapshot.total_purchase = add(last.total_purchase, value)?;
                 } else {
-                    new_snapshot.total_sale = last.total_sale + value;
+                    new_snapshot.total_sale = add(last.total_sale, value)?;
                 }
 
                 // 最新的资产价值
-                new_snapshot.value = last.value + trade.signed_amount.to_f64();
+                new_snapshot.value = add(last.value, trade.signed_amount.to_decimal())?;
+
+                // 按照成交价格推算份额, 成交价格也记入历史价格; 没有成交价格的时候用之前最近的价格
+                let price = match trade.price.filter(|p| p.is_positive()) {
+                    Some(price) => {
+                        self.prices.add(
+                            &symbol,
+                            PricePoint::new(record.date.clone(), price, trade.unit.clone()),
+                        );
+                        Some(price)
+                    }
+                    None => self.known_price(&symbol, &record.date),
+                };
+                match price {
+                    Some(price) => {
+                        let units = div(trade.signed_amount.to_decimal(), price)?;
+                        new_snapshot.units = add(last.units, units)?;
+                    }
+                    None => {
+                        self.partial_units.insert(symbol.clone());
+                    }
+                }
+
+                // 费用不计入交易金额, 单独累积
+                if let Some(fee) = &trade.fee {
+                    new_snapshot.total_fee = add(last.total_fee, fee.amount)?;
+                }
+                if let Some(tax) = &trade.tax {
+                    new_snapshot.total_tax = add(last.total_tax, tax.amount)?;
+                }
+
+                if let Some(account) = &trade.account {
+                    if !account.is_cash() {
+                        return Err(EngineError::new(format!(
+                            "VIA account must be a {} symbol, found {}",
+                            CASH_NAMESPACE, account
+                        )));
+                    }
+
+                    // 买入从现金账户划出, 卖出回到现金账户, 费用都由现金账户承担
+                    let charges = add(
+                        trade.fee.as_ref().map_or(Decimal::ZERO, |c| c.amount),
+                        trade.tax.as_ref().map_or(Decimal::ZERO, |c| c.amount),
+                    )?;
+                    let cash = account.to_string();
+                    let cash_last = self.last_metric(&cash);
+                    let mut cash_snapshot = cash_last.clone();
+                    if trade.buy() {
+                        let amount = add(value, charges)?;
+                        cash_snapshot.total_transfer_out =
+                            add(cash_last.total_transfer_out, amount)?;
+                        cash_snapshot.value = sub(cash_last.value, amount)?;
+                    } else {
+                        let amount = sub(value, charges)?;
+                        cash_snapshot.total_transfer_in = add(cash_last.total_transfer_in, amount)?;
+                        cash_snapshot.value = add(cash_last.value, amount)?;
+                    }
+
+                    let output = self.push_output(symbol, record, new_snapshot)?;
+                    self.push_output(cash, record, cash_snapshot)?;
+                    return Ok(output);
+                }
             }
 
             Details::Mark(mark) => {
@@ -242,44 +849,217 @@ impl EngineState {
                 new_snapshot.total_purchase = last.total_purchase;
                 new_snapshot.total_sale = last.total_sale;
             }
+
+            Details::Fee(fee) => {
+                // 单独的费用记录只影响费用, 不影响资产价值
+                if record.action == Action::Tax {
+                    new_snapshot.total_tax = add(last.total_tax, fee.charge.amount)?;
+                } else {
+                    new_snapshot.total_fee = add(last.total_fee, fee.charge.amount)?;
+                }
+            }
+
+            Details::Split(split) => {
+                // 拆分只改变份额和单位价格, 投入和价值都不变
+                new_snapshot.units = mul(last.units, split.ratio)?;
+                self.prices
+                    .adjust_split(&symbol, split.ratio)
+                    .ok_or_else(EngineError::overflow)?;
+            }
+
+            Details::Merge(merge) => {
+                // 源资产清零, 所有指标并入目标资产
+                let target = merge.target.to_string();
+                let mut merged = self.last_metric(&target);
+                merged.absorb(&last, merge.ratio)?;
+                if self.partial_units.contains(&symbol) {
+                    self.partial_units.insert(target.clone());
+                }
+
+                self.push_output(symbol, record, AssetMetric::new_zero())?;
+                return self.push_output(target, record, merged);
+            }
+
+            Details::Transfer(transfer) => {
+                // 内部转换: 源资产转出, 目标资产转入, 总投入和总转出都不变
+                new_snapshot.total_transfer_out = add(last.total_transfer_out, transfer.amount)?;
+                new_snapshot.value = sub(last.value, transfer.amount)?;
+                if last.value.is_positive() {
+                    // 按照转出的价值比例减少份额
+                    let remaining = div(new_snapshot.value, last.value)?;
+                    new_snapshot.units = mul(last.units, remaining.max(Decimal::ZERO))?;
+                }
+
+                let target = transfer.target.to_string();
+                let target_last = self.last_metric(&target);
+                let mut target_snapshot = target_last.clone();
+                target_snapshot.total_transfer_in =
+                    add(target_last.total_transfer_in, transfer.amount)?;
+                target_snapshot.value = add(target_last.value, transfer.amount)?;
+                let price = transfer
+                    .price
+                    .filter(|p| p.is_positive())
+                    .or_else(|| self.known_price(&target, &record.date));
+                match price {
+                    Some(price) => {
+                        target_snapshot.units =
+                            add(target_last.units, div(transfer.amount, price)?)?;
+                    }
+                    None => {
+                        self.partial_units.insert(target.clone());
+                    }
+                }
+
+                self.push_output(symbol, record, new_snapshot)?;
+                return self.push_output(target, record, target_snapshot);
+            }
+
+            Details::Price(price) => {
+                self.prices.add(
+                    &symbol,
+                    PricePoint::new(record.date.clone(), price.price, price.unit.clone()),
+                );
+
+                // 有份额并且份额完整的时候按照 份额 x 价格 重新估值, 否则保持原来的价值
+                if last.units.is_positive() && !self.partial_units.contains(&symbol) {
+                    new_snapshot.value =
+                        mul(last.units, price.price)?.round(VALUE_SCALE).normalize();
+                }
+            }
+
+            Details::Cash(cash) => {
+                if !cash.symbol.is_cash() {
+                    return Err(EngineError::new(format!(
+                        "DEPOSIT and WITHDRAW only apply to {} symbols, found {}",
+                        CASH_NAMESPACE, cash.symbol
+                    )));
+                }
+
+                // 存入现金是新的投入, 取出现金是转出
+                if record.action == Action::Deposit {
+                    new_snapshot.total_purchase = add(last.total_purchase, cash.amount)?;
+                    new_snapshot.value = add(last.value, cash.amount)?;
+                } else {
+                    new_snapshot.total_sale = add(last.total_sale, cash.amount)?;
+                    new_snapshot.value = sub(last.value, cash.amount)?;
+                }
+            }
         }
 
-        let output = RecordOutput::from_record_with_metric(&record, new_snapshot);
-        self.record_outputs.push(output.clone());
-        self.snapshots.insert(symbol, output.clone());
-        Ok(Output::Record(output))
+        self.push_output(symbol, record, new_snapshot)
     }
 
     fn update_portfolio(&mut self, statement: PortfolioStatement) -> Result<(), EngineError> {
         let portfolio = Portfolio {
             name: statement.name.clone(),
             assets: statement
-                .assets
-                .iter()
+                .symbols()
                 .map(|x| {
                     let symbol = x.to_string();
-                    let asset = self
-                        .assets
+                    self.assets
                         .get(&symbol)
                         .cloned()
-                        .unwrap_or_else(|| Asset::new(symbol, None, None));
-
-                    asset
+                        .unwrap_or_else(|| Asset::new(symbol, None, None))
                 })
                 .collect(),
+            members: statement.members.clone(),
             target_return: statement.target_return.unwrap_or(0.0),
+            rebalance: statement.rebalance.clone(),
         };
 
         self.portfolios.push(portfolio);
         Ok(())
     }
 
+    // 组合的名字不能重复, 子组合必须存在, 并且不能直接或者间接包含自己
+    // 直接列出的资产不能再出现在子组合里, 否则计算权重的时候会算两次
+    fn check_portfolios(&self) -> Result<(), EngineError> {
+        // 子组合按名字引用, 名字重复的时候不知道指的是哪一个
+        for (index, portfolio) in self.portfolios.iter().enumerate() {
+            if self.portfolios[..index]
+                .iter()
+                .any(|p| p.name == portfolio.name)
+            {
+                return Err(EngineError::new(format!(
+                    "Portfolio \"{}\" is defined more than once",
+                    portfolio.name
+                )));
+            }
+        }
+        for portfolio in self.portfolios.iter() {
+            self.check_portfolio(portfolio, &mut vec![portfolio.name.clone()])?;
+        }
+        for portfolio in self.portfolios.iter() {
+            for name in portfolio.portfolios() {
+                let mut symbols = Vec::new();
+                self.expand_symbols(name, &mut symbols);
+                if let Some(asset) = portfolio
+                    .assets
+                    .iter()
+                    .find(|asset| symbols.contains(asset.get_symbol()))
+                {
+                    return Err(EngineError::new(format!(
+                        "{} is listed in portfolio \"{}\" and again through portfolio \"{}\"",
+                        asset.get_symbol(),
+                        portfolio.name,
+                        name
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // 组合展开之后所有的资产, 调用之前已经检查过子组合都存在并且没有循环
+    fn expand_symbols(&self, name: &str, symbols: &mut Vec<String>) {
+        let Some(portfolio) = self.portfolios.iter().find(|p| p.name == name) else {
+            return;
+        };
+        symbols.extend(portfolio.assets.iter().map(|a| a.get_symbol().clone()));
+        for child in portfolio.portfolios() {
+            self.expand_symbols(child, symbols);
+        }
+    }
+
+    fn check_portfolio(
+        &self,
+        portfolio: &Portfolio,
+        path: &mut Vec<String>,
+    ) -> Result<(), EngineError> {
+        for name in portfolio.portfolios() {
+            let Some(child) = self.portfolios.iter().find(|p| &p.name == name) else {
+                return Err(EngineError::new(format!(
+                    "Unknown portfolio \"{}\" in portfolio \"{}\"",
+                    name, portfolio.name
+                )));
+            };
+            if let Some(start) = path.iter().position(|n| n == name) {
+                let cycle: Vec<String> = path[start..]
+                    .iter()
+                    .chain(std::iter::once(name))
+                    .map(|n| format!("\"{}\"", n))
+                    .collect();
+                return Err(EngineError::new(format!(
+                    "Portfolio cycle: {}",
+                    cycle.join(" -> ")
+                )));
+            }
+
+            path.push(name.clone());
+            self.check_portfolio(child, path)?;
+            path.pop();
+        }
+
+        Ok(())
+    }
+
     // 更新组合里面的资产名字
     fn update_portfolio_assets(&mut self, asset: Asset) {
         // 遍历每一个组合，如果存在 asset 就更新
         for portfolio in self.portfolios.iter_mut() {
             // 直接使用新的信息覆盖
-            for (_i, a) in portfolio.assets.iter_mut().enumerate() {
+            for a in portfolio.assets.iter_mut() {
                 if a.get_symbol() == asset.get_symbol() {
                     *a = asset.clone();
                 }
@@ -289,72 +1069,127 @@ impl EngineState {
 }
 
 impl RecordOutput {
-    fn from_record_with_metric(record: &Record, metric: AssetMetric) -> Self {
-        Self {
+    fn from_record_with_metric(
+        symbol: String,
+        record: &Record,
+        metric: AssetMetric,
+        price: Option<Decimal>,
+    ) -> Result<Self, EngineError> {
+        Ok(Self {
+            symbol,
             program: record.clone(),
             total_purchase: metric.total_purchase,
             total_sale: metric.total_sale,
+            total_transfer_in: metric.total_transfer_in,
+            total_transfer_out: metric.total_transfer_out,
             value: metric.value,
-            profit: metric.get_profit(),
-        }
+            units: metric.units,
+            cost_per_unit: metric.get_cost_per_unit()?,
+            price,
+            total_fee: metric.total_fee,
+            total_tax: metric.total_tax,
+            profit: metric.get_profit()?,
+            gross_profit: metric.get_gross_profit()?,
+        })
     }
 }
 
+#[derive(Default)]
 struct UpsertAssetArgs {
     symbol: String,
     name: Option<String>,
     target_return: Option<f64>,
+    class: Option<AssetClass>,
+    category: Option<String>,
+    benchmark: Option<String>,
+    currency: Option<String>,
+    risk: Option<u8>,
+    metadata: Metadata,
 }
 
-struct DefineStatementResult {}
-
 pub struct Engine {
     state: EngineState,
+
+    // IMPORT 语句里相对路径的基准目录, dsl::loader 读入的路径已经是绝对路径, 不受它影响
+    base_dir: PathBuf,
+
+    // 通过 API 导入, 等待下一次 evaluate 执行的记录
+    pending_records: Vec<Record>,
 }
 
 impl Engine {
     pub fn new() -> Self {
         Self {
-            state: EngineState {
-                portfolios: Vec::new(),
-                plans: Vec::new(),
-                assets: HashMap::new(),
-                record_outputs: Vec::new(),
-                snapshots: HashMap::new(),
-            },
+            state: EngineState::new(),
+            base_dir: PathBuf::new(),
+            pending_records: Vec::new(),
         }
     }
 
+    pub fn with_base_dir<P: AsRef<Path>>(mut self, base_dir: P) -> Self {
+        self.base_dir = base_dir.as_ref().to_path_buf();
+        self
+    }
+
+    // 导入价格 CSV, 和 IMPORT PRICES 语句的效果一样
+    pub fn import_prices<P: AsRef<Path>>(&mut self, path: P) -> Result<(), EngineError> {
+        let records = prices::read_price_csv(self.base_dir.join(path))?;
+        self.pending_records.extend(records);
+        Ok(())
+    }
+
     pub fn evaluate(&mut self, program: Program) -> Result<AnalysisReport, EngineError> {
-        let mut record_statements = Vec::new();
-        for statement in program.statements.iter() {
-            match statement {
-                Statement::Record(rec) => record_statements.push(rec),
-                Statement::Plan(_) => {}
-                Statement::Define(define) => self.evaluate_define(&define)?,
-                Statement::Portfolio(statement) => self.evaluate_portfolio(statement)?,
-            }
+        // 记录和它的来源位置, 通过 API 导入的记录没有位置
+        let mut record_statements: Vec<(Record, Option<Origin>)> =
+            std::mem::take(&mut self.pending_records)
+                .into_iter()
+                .map(|rec| (rec, None))
+                .collect();
+        for (statement, origin) in program.statements {
+            let result = match statement {
+                Statement::Record(rec) => {
+                    record_statements.push((rec, origin.clone()));
+                    Ok(())
+                }
+                Statement::Plan(plan) => {
+                    self.state.plans.push(plan);
+                    Ok(())
+                }
+                Statement::Define(define) => self.evaluate_define(&define),
+                Statement::Portfolio(statement) => self.evaluate_portfolio(&statement),
+                // 导入的记录出错时指向 IMPORT 语句
+                Statement::Import(import) => self.evaluate_import(&import).map(|records| {
+                    record_statements.extend(records.into_iter().map(|rec| (rec, origin.clone())))
+                }),
+                // 常量在解析时已经展开了
+                Statement::Let(_) => Ok(()),
+                Statement::Include(include) => Err(EngineError::new(format!(
+                    "INCLUDE \"{}\" must be resolved by dsl::loader before evaluation",
+                    include.path
+                ))),
+            };
+            result.map_err(|e| e.at(origin.as_ref()))?;
         }
 
+        self.state.check_portfolios()?;
+
         // 先按照日期排序
-        record_statements.sort_by(|a, b| a.date.cmp(&b.date));
-        record_statements
-            .iter()
-            .try_for_each(|rec| self.evaluate_record(rec).map(|_| ()))?;
+        record_statements.sort_by(|(a, _), (b, _)| a.date.cmp(&b.date));
+        for (rec, origin) in record_statements.iter() {
+            self.evaluate_record(rec)
+                .map_err(|e| e.at(origin.as_ref()))?;
+        }
 
         let mut result = AnalysisReport::new();
         result.assets = self.state.assets.values().cloned().collect();
 
         // 按照天聚合每个资产的数据
         for output in self.state.record_outputs.iter() {
-            let symbol = output.program.details.get_symbol().to_string();
+            let symbol = output.symbol.clone();
             let date = output.program.date.clone();
 
             // 如果不存在就初始化
-            let by_symbol = result
-                .daily_snapshot
-                .entry(symbol.clone())
-                .or_insert(Vec::new());
+            let by_symbol = result.daily_snapshot.entry(symbol.clone()).or_default();
 
             match by_symbol.iter_mut().find(|x| x.date == date) {
                 Some(by_date) => {
@@ -373,35 +1208,67 @@ impl Engine {
         }
 
         result.portfolios = self.state.portfolios.clone();
+        result.plans = self.state.plans.clone();
+        result.prices = self.state.prices.clone();
         Ok(result)
     }
 
-    fn evaluate_record(&mut self, record: &Record) -> Result<Output, EngineError> {
-        // 先更新资产的基本信息
+    fn evaluate_record(&mut self, record: &Record) -> Result<(), EngineError> {
         let details = &record.details;
         let symbol = details.get_symbol().to_string();
+
+        // 从来没有持有过的标的, 比如只用作基准的指数, 价格只记入历史价格, 不算作资产
+        if let Details::Price(price) = details
+            && !self.state.snapshots.contains_key(&symbol)
+        {
+            self.state.prices.add(
+                &symbol,
+                PricePoint::new(record.date.clone(), price.price, price.unit.clone()),
+            );
+            return Ok(());
+        }
+
+        // 先更新资产的基本信息
         let args = UpsertAssetArgs {
             symbol,
-            name: None,
-            target_return: None,
+            ..Default::default()
         };
         self.state.upsert_asset(args);
 
+        // 合并和转换的目标资产也需要登记
+        if let Some(target) = details.get_target() {
+            self.state.upsert_asset(UpsertAssetArgs {
+                symbol: target.to_string(),
+                ..Default::default()
+            });
+        }
+
         // 现在计算资产的最新价值等基础财务指标
-        let output = self.state.calc_asset(record)?;
+        self.state.calc_asset(record)?;
 
-        Ok(output)
+        Ok(())
     }
 
-    fn evaluate_plan(&self, _plan: Plan) -> Result<(), EngineError> {
-        Ok(())
+    fn evaluate_import(&self, import: &Import) -> Result<Vec<Record>, EngineError> {
+        let path = self.base_dir.join(&import.path);
+        let records = match import.kind {
+            ImportKind::Prices => prices::read_price_csv(path)?,
+        };
+
+        Ok(records)
     }
 
     fn evaluate_define(&mut self, define: &Define) -> Result<(), EngineError> {
         self.state.upsert_asset(UpsertAssetArgs {
             symbol: define.symbol.to_string(),
             name: define.alias.clone(),
-            target_return: define.target_return.clone(),
+            target_return: define.target_return,
+            class: define.class,
+            category: define.category.clone(),
+            benchmark: define.benchmark.as_ref().map(|b| b.to_string()),
+            currency: define.currency.clone(),
+            risk: define.risk,
+            metadata: define.metadata.clone(),
         });
 
         Ok(())
@@ -413,3 +1280,499 @@ impl Engine {
         Ok(())
     }
 }
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsl::{Lexer, Parser};
+    use crate::evaluator::evaluate_str;
+
+    fn last_snapshot<'a>(report: &'a AnalysisReport, symbol: &str) -> &'a Snapshot {
+        report.daily_snapshot[symbol]
+            .last()
+            .and_then(|daily| daily.snapshots.last())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_trade_fee_and_tax() {
+        let report = evaluate_str(
+            r#"
+            2024-01-01 TRADE ETF:510300 +5000 CNY @ 4.56 FEE 5 CNY
+            2024-03-01 TRADE ETF:510300 -2000 CNY @ 4.65 FEE 2 CNY TAX 1 CNY
+            2024-03-31 MARK ETF:510300 VALUE 3200 CNY
+            2024-06-30 FEE ETF:510300 4 CNY
+            "#,
+        );
+
+        let shot = last_snapshot(&report, "ETF:510300");
+        assert_eq!(shot.total_purchase, 5000.0);
+        assert_eq!(shot.total_sale, 2000.0);
+        assert_eq!(shot.total_fee, 11.0);
+        assert_eq!(shot.total_tax, 1.0);
+        assert_eq!(shot.gross_profit, 200.0);
+        assert_eq!(shot.profit, 188.0);
+    }
+
+    #[test]
+    fn test_split_adjusts_units() {
+        let report = evaluate_str(
+            r#"
+            2024-01-01 TRADE ETF:510300 +4000 CNY @ 4
+            2024-05-01 SPLIT ETF:510300 2:1
+            "#,
+        );
+
+        let shot = last_snapshot(&report, "ETF:510300");
+        assert_eq!(shot.total_purchase, 4000.0);
+        assert_eq!(shot.units, 2000.0);
+        assert_eq!(shot.cost_per_unit, Some(Decimal::from(2)));
+    }
+
+    #[test]
+    fn test_merge_moves_holdings() {
+        let report = evaluate_str(
+            r#"
+            2024-01-01 TRADE ETF:159915 +3000 CNY @ 3 FEE 3 CNY
+            2024-01-01 TRADE ETF:159949 +1000 CNY @ 1
+            2024-07-01 MERGE ETF:159915 INTO ETF:159949 2
+            "#,
+        );
+
+        let source = last_snapshot(&report, "ETF:159915");
+        assert_eq!(source.value, 0.0);
+        assert_eq!(source.units, 0.0);
+
+        let target = last_snapshot(&report, "ETF:159949");
+        assert_eq!(target.total_purchase, 4000.0);
+        assert_eq!(target.value, 4000.0);
+        assert_eq!(target.units, 3000.0);
+        assert_eq!(target.total_fee, 3.0);
+        assert!(report.assets.iter().any(|a| a.symbol == "ETF:159949"));
+    }
+
+    #[test]
+    fn test_transfer_between_assets() {
+        let report = evaluate_str(
+            r#"
+            2024-01-01 TRADE ETF:510300 +5000 CNY @ 5
+            2024-04-01 TRANSFER ETF:510300 -> ETF:159915 3000 CNY @ 2
+            "#,
+        );
+
+        let source = last_snapshot(&report, "ETF:510300");
+        assert_eq!(source.total_purchase, 5000.0);
+        assert_eq!(source.total_transfer_out, 3000.0);
+        assert_eq!(source.value, 2000.0);
+        assert_eq!(source.units, 400.0);
+        assert_eq!(source.profit, 0.0);
+
+        let target = last_snapshot(&report, "ETF:159915");
+        assert_eq!(target.total_purchase, 0.0);
+        assert_eq!(target.total_transfer_in, 3000.0);
+        assert_eq!(target.value, 3000.0);
+        assert_eq!(target.units, 1500.0);
+        assert_eq!(target.profit, 0.0);
+    }
+
+    #[test]
+    fn test_cash_account() {
+        let report = evaluate_str(
+            r#"
+            PORTFOLIO "ETF 长期投资"
+                ASSETS ETF:510300, CASH:BROKER
+            END
+
+            2024-01-01 DEPOSIT CASH:BROKER 10000 CNY
+            2024-01-02 TRADE ETF:510300 +5000 CNY @ 5 VIA CASH:BROKER FEE 5 CNY
+            2024-02-01 TRADE ETF:510300 -2000 CNY @ 5 VIA CASH:BROKER FEE 2 CNY
+            2024-03-01 WITHDRAW CASH:BROKER 1000 CNY
+            "#,
+        );
+
+        let cash = report.asset_summary("CASH:BROKER").unwrap();
+        assert_eq!(cash.value, 5993.0);
+        assert_eq!(cash.total_purchase, 10000.0);
+        assert_eq!(cash.total_sale, 1000.0);
+        assert_eq!(cash.profit, 0.0);
+
+        let etf = report.asset_summary("ETF:510300").unwrap();
+        assert_eq!(etf.total_purchase, 0.0);
+        assert_eq!(etf.total_transfer_in, 5000.0);
+        assert_eq!(etf.total_transfer_out, 2000.0);
+
+        // 通过现金账户买入的金额只在存入的时候算一次投入
+        let portfolio = report.portfolio_summary(&report.portfolios[0]);
+        assert_eq!(portfolio.total_purchase, 10000.0);
+        assert_eq!(portfolio.total_sale, 1000.0);
+        assert_eq!(portfolio.value, 8993.0);
+        assert_eq!(portfolio.profit, -7.0);
+    }
+
+    #[test]
+    fn test_via_trade_counted_once() {
+        let report = evaluate_str(
+            r#"
+            PORTFOLIO "P" ASSETS CASH:BROKER, ETF:510300 END
+
+            2024-01-01 DEPOSIT CASH:BROKER 10000 CNY
+            2024-01-02 TRADE ETF:510300 +5000 CNY VIA CASH:BROKER
+            "#,
+        );
+
+        let portfolio = report.portfolio_summary(&report.portfolios[0]);
+        assert_eq!(portfolio.total_purchase, 10000.0);
+        assert_eq!(portfolio.value, 10000.0);
+        assert_eq!(portfolio.profit, 0.0);
+    }
+
+    #[test]
+    fn test_nested_portfolios() {
+        let report = evaluate_str(
+            r#"
+            PORTFOLIO "家庭资产"
+                ASSETS "ETF 长期投资", CASH:BANK
+            END
+            PORTFOLIO "ETF 长期投资"
+                ASSETS ETF:510300, ETF:159915
+            END
+
+            2024-01-01 DEPOSIT CASH:BANK 2000 CNY
+            2024-01-02 TRADE ETF:510300 +5000 CNY
+            2024-01-02 TRADE ETF:159915 +3000 CNY
+            2024-02-01 MARK ETF:159915 VALUE 3300 CNY
+            "#,
+        );
+
+        let household = &report.portfolios[0];
+        assert_eq!(
+            report.portfolio_symbols(household),
+            vec!["CASH:BANK", "ETF:510300", "ETF:159915"]
+        );
+        let summary = report.portfolio_summary(household);
+        assert_eq!(summary.value, 10300.0);
+        assert_eq!(summary.profit, 300.0);
+        let history = report.portfolio_history(household);
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[1].1.value, 10000.0);
+    }
+
+    #[test]
+    fn test_shared_assets() {
+        let report = evaluate_str(
+            r#"
+            PORTFOLIO "长期" ASSETS ETF:510300, ETF:511010 END
+            PORTFOLIO "定投" ASSETS ETF:510300, ETF:159915 END
+            PORTFOLIO "全部" ASSETS "长期", "定投" END
+
+            2024-01-02 TRADE ETF:510300 +5000 CNY
+            2024-01-02 TRADE ETF:511010 +3000 CNY
+            2024-01-02 TRADE ETF:159915 +2000 CNY
+            "#,
+        );
+
+        // 分别加起来会把 ETF:510300 算两次
+        let long = report.portfolio_summary(&report.portfolios[0]).value;
+        let plan = report.portfolio_summary(&report.portfolios[1]).value;
+        assert_eq!(long + plan, 15000.0);
+        assert_eq!(report.book_summary().value, 10000.0);
+        assert_eq!(
+            report.portfolio_summary(&report.portfolios[2]).value,
+            10000.0
+        );
+
+        // "全部" 同时包含两个子组合, 和它们相互包含, 不算共享
+        let shared = report.shared_assets();
+        assert_eq!(shared.len(), 1);
+        assert_eq!(shared[0].symbol, "ETF:510300");
+        let owners: Vec<&str> = shared[0]
+            .owners
+            .iter()
+            .map(|owner| owner.portfolio.as_str())
+            .collect();
+        assert_eq!(owners, vec!["长期", "定投"]);
+        assert!((shared[0].book_weight - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_shared_assets_through_child() {
+        let report = evaluate_str(
+            r#"
+            PORTFOLIO "P1" ASSETS ETF:510300 END
+            PORTFOLIO "P2" ASSETS "P3" END
+            PORTFOLIO "P3" ASSETS ETF:510300, ETF:511010 END
+            PORTFOLIO "P4" ASSETS "P3" END
+
+            2024-01-02 TRADE ETF:510300 +5000 CNY
+            2024-01-02 TRADE ETF:511010 +3000 CNY
+            "#,
+        );
+
+        let shared = report.shared_assets();
+        assert_eq!(shared.len(), 2);
+        assert_eq!(
+            shared[0].owners,
+            vec![
+                AssetOwner {
+                    portfolio: "P1".to_string(),
+                    via: None,
+                },
+                AssetOwner {
+                    portfolio: "P2".to_string(),
+                    via: Some("P3".to_string()),
+                },
+                AssetOwner {
+                    portfolio: "P3".to_string(),
+                    via: None,
+                },
+                AssetOwner {
+                    portfolio: "P4".to_string(),
+                    via: Some("P3".to_string()),
+                },
+            ]
+        );
+        // 两个组合通过同一个子组合包含资产也会重复计算
+        assert_eq!(shared[1].symbol, "ETF:511010");
+        assert_eq!(shared[1].owners.len(), 2);
+
+        // 只有父组合和它的子组合包含的时候不算共享
+        let report = evaluate_str(
+            r#"
+            PORTFOLIO "P2" ASSETS "P3" END
+            PORTFOLIO "P3" ASSETS ETF:510300 END
+            2024-01-02 TRADE ETF:510300 +5000 CNY
+            "#,
+        );
+        assert!(report.shared_assets().is_empty());
+    }
+
+    #[test]
+    fn test_portfolio_cycle() {
+        let evaluate = |input: &str| {
+            let tokens = Lexer::new(input).tokenize().unwrap();
+            let program = Parser::new(tokens).parse().unwrap();
+            Engine::new().evaluate(program).err().unwrap().message
+        };
+
+        let input = r#"
+            PORTFOLIO "A" ASSETS "B" END
+            PORTFOLIO "B" ASSETS ETF:510300, "C" END
+            PORTFOLIO "C" ASSETS "A" END
+            "#;
+        assert_eq!(
+            evaluate(input),
+            r#"Portfolio cycle: "A" -> "B" -> "C" -> "A""#
+        );
+
+        let input = r#"PORTFOLIO "A" ASSETS "B" END"#;
+        assert_eq!(evaluate(input), r#"Unknown portfolio "B" in portfolio "A""#);
+
+        let input = r#"
+            PORTFOLIO "A" ASSETS ETF:510300 END
+            PORTFOLIO "A" ASSETS ETF:511010 END
+            "#;
+        assert_eq!(
+            evaluate(input),
+            r#"Portfolio "A" is defined more than once"#
+        );
+
+        // 孙组合里的资产也算
+        let input = r#"
+            PORTFOLIO "A" ASSETS ETF:510300, "B" END
+            PORTFOLIO "B" ASSETS "C" END
+            PORTFOLIO "C" ASSETS ETF:510300 END
+            "#;
+        assert_eq!(
+            evaluate(input),
+            r#"ETF:510300 is listed in portfolio "A" and again through portfolio "B""#
+        );
+    }
+
+    #[test]
+    fn test_price_valuation() {
+        let report = evaluate_str(
+            r#"
+            2024-01-02 TRADE ETF:510300 +4000 CNY @ 4
+            PRICE 2024-01-31 ETF:510300 4.5 CNY
+            2024-02-15 MARK ETF:510300 VALUE 4200 CNY
+            PRICE 2024-02-29 ETF:510300 5 CNY
+            PRICE 2024-02-29 INDEX:000300 3500 CNY
+            "#,
+        );
+
+        let days = &report.daily_snapshot["ETF:510300"];
+        assert_eq!(days[1].snapshots[0].value, 4500.0);
+        assert_eq!(days[1].snapshots[0].price, Some(Decimal::from_f64(4.5)));
+        assert_eq!(days[2].snapshots[0].value, 4200.0);
+        assert_eq!(days[3].snapshots[0].value, 5000.0);
+        assert_eq!(report.prices.latest("ETF:510300").unwrap().price, 5.0);
+
+        // 只有价格的标的不算作资产
+        assert_eq!(report.prices.latest("INDEX:000300").unwrap().price, 3500.0);
+        assert!(!report.daily_snapshot.contains_key("INDEX:000300"));
+        assert!(report.assets.iter().all(|a| a.symbol != "INDEX:000300"));
+    }
+
+    #[test]
+    fn test_price_valuation_with_unpriced_trades() {
+        // 没有成交价格的交易按照之前最近的价格推算份额
+        let report = evaluate_str(
+            r#"
+            2024-01-02 TRADE ETF:510300 +5000 CNY @ 5
+            2024-01-10 TRADE ETF:510300 +3000 CNY
+            2024-01-20 TRADE ETF:510300 -2000 CNY
+            2024-01-31 PRICE ETF:510300 5 CNY
+            2024-02-29 PRICE ETF:510300 6 CNY
+            "#,
+        );
+        let history = report.asset_history("ETF:510300");
+        assert_eq!(history[1].1.value, 8000.0);
+        assert_eq!(history[3].1.value, 6000.0);
+        assert_eq!(history[3].1.profit, 0.0);
+        assert_eq!(history[4].1.value, 7200.0);
+        assert_eq!(history[4].1.profit, 1200.0);
+
+        // 转入的份额也按照目标资产之前的价格推算
+        let report = evaluate_str(
+            r#"
+            2024-01-02 TRADE ETF:510300 +1000 CNY @ 4
+            2024-01-02 TRADE ETF:159915 +2000 CNY @ 2
+            2024-01-10 TRANSFER ETF:510300 -> ETF:159915 1000 CNY
+            2024-01-31 PRICE ETF:159915 3 CNY
+            "#,
+        );
+        assert_eq!(report.asset_summary("ETF:159915").unwrap().value, 4500.0);
+
+        // 之前没有价格的时候推算不出份额, PRICE 保持原来的价值
+        let report = evaluate_str(
+            r#"
+            2024-01-02 TRADE ETF:510300 +3000 CNY
+            2024-01-10 TRADE ETF:510300 +5000 CNY @ 5
+            2024-01-31 PRICE ETF:510300 6 CNY
+            "#,
+        );
+        let summary = report.asset_summary("ETF:510300").unwrap();
+        assert_eq!(summary.value, 8000.0);
+        assert_eq!(summary.profit, 0.0);
+    }
+
+    #[test]
+    fn test_import_prices() {
+        let dir = std::env::temp_dir().join(format!("cashly-import-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("prices.csv"),
+            "date,symbol,close\n2024-01-31,ETF:510300,4.5\n2024-02-29,ETF:510300,5\n",
+        )
+        .unwrap();
+
+        let tokens = Lexer::new(
+            r#"
+            IMPORT PRICES "prices.csv"
+            2024-01-02 TRADE ETF:510300 +4000 CNY @ 4
+            "#,
+        )
+        .tokenize()
+        .unwrap();
+        let program = Parser::new(tokens).parse().unwrap();
+        let report = Engine::new().with_base_dir(&dir).evaluate(program).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(report.asset_summary("ETF:510300").unwrap().value, 5000.0);
+        assert_eq!(report.prices.history("ETF:510300").len(), 3);
+    }
+
+    #[test]
+    fn test_amounts_do_not_drift() {
+        let input: String = (1..=12)
+            .map(|month| format!("2024-{:02}-01 TRADE ETF:510300 +3000.10 CNY\n", month))
+            .collect();
+        let report = evaluate_str(&input.repeat(50));
+
+        let summary = report.asset_summary("ETF:510300").unwrap();
+        assert_eq!(summary.total_purchase.to_string(), "1800060.00");
+        assert_eq!(summary.profit, Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_large_amounts_do_not_panic() {
+        let report = evaluate_str("2024-01-01 TRADE ETF:510300 +1e20 CNY @ 0.5");
+        assert_eq!(
+            last_snapshot(&report, "ETF:510300").units,
+            Decimal::from(2).shift(20).unwrap()
+        );
+
+        // 超出范围的时候报错而不是 panic
+        let input = r#"
+            2024-01-01 DEPOSIT CASH:BANK 1e36 * 100 CNY
+            2024-01-02 DEPOSIT CASH:BANK 1e36 * 100 CNY
+        "#;
+        let tokens = Lexer::new(input).tokenize().unwrap();
+        let program = Parser::new(tokens).parse().unwrap();
+        let error = Engine::new().evaluate(program).err().unwrap();
+        assert_eq!(error.message, "Arithmetic overflow");
+    }
+
+    #[test]
+    fn test_units_and_prices_are_decimal() {
+        let report = evaluate_str(
+            r#"
+            2024-01-02 TRADE ETF:510300 +1000 CNY @ 3
+            2024-01-03 SPLIT ETF:510300 1:3
+            PRICE 2024-01-31 ETF:510300 1.10 CNY
+            "#,
+        );
+
+        // 1000 / 3 拆成 1/3, 再按 1.10 估值, 结果没有二进制浮点的误差
+        let shot = last_snapshot(&report, "ETF:510300");
+        assert_eq!(shot.units.round(6).to_string(), "111.111111");
+        assert_eq!(shot.value.to_string(), "122.2222222222");
+        assert_eq!(
+            report.prices.history("ETF:510300")[0].price,
+            Decimal::from(9)
+        );
+    }
+
+    #[test]
+    fn test_deposit_requires_cash_symbol() {
+        let tokens = Lexer::new("2024-01-01 DEPOSIT ETF:510300 100 CNY")
+            .tokenize()
+            .unwrap();
+        let program = Parser::new(tokens).parse().unwrap();
+        assert!(Engine::new().evaluate(program).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let input = r#"
+            DEFINE ETF:510300
+            ALIAS "沪深300"
+            END
+            2024-01-01 TRADE ETF:510300 +5000 CNY @ 4 FEE 5 CNY
+            2024-02-01 MARK ETF:510300 VALUE 5200 CNY NOTE "月末"
+            "#;
+        let tokens = Lexer::new(input).tokenize().unwrap();
+        let program = Parser::new(tokens).parse().unwrap();
+
+        let json = serde_json::to_string(&program).unwrap();
+        assert!(json.contains(r#""type":"record""#));
+        let decoded: Program = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, program);
+
+        let report = Engine::new().evaluate(program).unwrap();
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["assets"][0]["alias"], "沪深300");
+        let decoded: AnalysisReport = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            decoded.asset_summary("ETF:510300"),
+            report.asset_summary("ETF:510300")
+        );
+    }
+}
@@ -0,0 +1,142 @@
+use crate::decimal::Decimal;
+use crate::dsl::ast::{Details, Metadata};
+use crate::evaluator::engine::{AnalysisReport, Asset, Snapshot, VALUE_SCALE};
+
+// 按照 TAG 或者 META 筛选记录和资产
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    // 标签, 可以带也可以不带前面的 '#'
+    Tag(String),
+    Meta(String, String),
+}
+
+impl Filter {
+    pub fn matches(&self, metadata: &Metadata) -> bool {
+        match self {
+            Filter::Tag(tag) => metadata.has_tag(tag),
+            Filter::Meta(key, value) => metadata.get(key) == Some(value),
+        }
+    }
+}
+
+// 一笔交易到现在的表现
+#[derive(Debug, Clone, PartialEq)]
+pub struct TradeResult {
+    pub date: String,
+    pub symbol: String,
+    // 买入为正, 卖出为负
+    pub amount: Decimal,
+    // 手续费和税费
+    pub charges: Decimal,
+    // 下面的指标需要成交价格推算份额, 没有价格的时候为 None
+    // 买入为正, 卖出为负, 拆分之后按照新的份额计算
+    pub units: Option<Decimal>,
+    // 这些份额按照最新价格的价值
+    pub value: Option<Decimal>,
+    // value - amount - charges, 卖出的收益是卖出之后少亏或者少赚的部分
+    pub profit: Option<Decimal>,
+}
+
+// DEFINE 里带有对应元信息的资产
+pub fn filter_assets<'a>(report: &'a AnalysisReport, filter: &Filter) -> Vec<&'a Asset> {
+    report
+        .assets
+        .iter()
+        .filter(|asset| filter.matches(&asset.metadata))
+        .collect()
+}
+
+// 符合条件的记录执行后的快照, 按日期排列
+// MERGE, TRANSFER 和 VIA 会在多个资产下产生快照, 这里只取记录本身的资产
+pub fn filter_snapshots<'a>(report: &'a AnalysisReport, filter: &Filter) -> Vec<&'a Snapshot> {
+    let mut snapshots: Vec<&Snapshot> = report
+        .daily_snapshot
+        .values()
+        .flat_map(|days| days.iter())
+        .flat_map(|day| day.snapshots.iter())
+        .filter(|shot| filter.matches(&shot.statement.metadata))
+        .filter(|shot| shot.symbol == shot.statement.details.get_symbol().to_string())
+        .collect();
+    snapshots.sort_by(|a, b| a.date.cmp(&b.date).then(a.symbol.cmp(&b.symbol)));
+
+    snapshots
+}
+
+// 符合条件的交易, 比如 Filter::Tag("bonus") 可以看到所有标记了 #bonus 的交易赚了多少
+pub fn filter_trades(report: &AnalysisReport, filter: &Filter) -> Vec<TradeResult> {
+    filter_snapshots(report, filter)
+        .into_iter()
+        .filter_map(|shot| match &shot.statement.details {
+            Details::Trade(trade) => Some((shot, trade)),
+            _ => None,
+        })
+        .map(|(shot, trade)| {
+            let amount = trade.signed_amount.to_decimal();
+            let charges = trade.fee.as_ref().map_or(Decimal::ZERO, |c| c.amount)
+                + trade.tax.as_ref().map_or(Decimal::ZERO, |c| c.amount);
+
+            // 历史价格已经按拆分调整过, 用它推算的份额和最新价格可以直接相乘
+            let units = trade
+                .price
+                .and_then(|_| report.prices.price_at(&shot.symbol, &shot.date))
+                .filter(|point| point.price.is_positive())
+                .and_then(|point| amount.checked_div(point.price));
+            let value = units.and_then(|units| {
+                let latest = report.prices.latest(&shot.symbol)?;
+                let value = units.checked_mul(latest.price)?;
+                Some(value.round(VALUE_SCALE).normalize())
+            });
+
+            TradeResult {
+                date: shot.date.clone(),
+                symbol: shot.symbol.clone(),
+                amount,
+                charges,
+                units,
+                value,
+                profit: value.map(|value| value - amount - charges),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluator::evaluate_str;
+
+    #[test]
+    fn test_filter_tagged_trades() {
+        let input = r#"
+            DEFINE ETF:510300
+              TAG #core
+              META broker "华泰"
+            END
+            2024-01-02 TRADE ETF:510300 +1000 CNY @ 4.00 FEE 1 CNY
+              TAG #bonus
+            2024-02-01 TRADE ETF:510300 +2000 CNY @ 5.00
+            2024-03-01 TRADE ETF:159915 +500 CNY
+              TAG #bonus
+            2024-04-01 SPLIT ETF:510300 2
+            2024-04-30 PRICE ETF:510300 3.00 CNY
+        "#;
+        let report = evaluate_str(input);
+
+        let assets = filter_assets(&report, &Filter::Meta("broker".into(), "华泰".into()));
+        assert_eq!(assets.len(), 1);
+        assert!(filter_assets(&report, &Filter::Tag("#bonus".into())).is_empty());
+
+        let trades = filter_trades(&report, &Filter::Tag("bonus".into()));
+        assert_eq!(trades.len(), 2);
+
+        // 4 元买入 250 份, 拆分后是 500 份, 按 3 元估值
+        let first = &trades[0];
+        assert_eq!(first.units, Some(Decimal::from(500)));
+        assert_eq!(first.value, Some(Decimal::from(1500)));
+        assert_eq!(first.profit, Some(Decimal::from(499)));
+
+        // 没有成交价格的交易无法计算收益
+        assert_eq!(trades[1].symbol, "ETF:159915");
+        assert_eq!(trades[1].profit, None);
+    }
+}
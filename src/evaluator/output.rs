@@ -1,53 +1,34 @@
-use crate::dsl::ast::{Record, Symbol};
-
-pub struct AssetMetric {
-    // 总投入
-    pub total_purchase: f64,
-    // 总转出
-    pub total_sale: f64,
-    // 期末价值
-    pub value: f64,
-    // 累积收益, 正负均有可能
-    pub profit: f64,
-}
+use crate::decimal::Decimal;
+use crate::dsl::ast::Record;
 
 #[derive(Debug, Clone)]
 pub struct RecordOutput {
+    // 这条结果对应的资产, MERGE 会同时产生源资产和目标资产的结果
+    pub symbol: String,
     pub program: Record,
 
     // 总投入
-    pub total_purchase: f64,
+    pub total_purchase: Decimal,
     // 总转出
-    pub total_sale: f64,
+    pub total_sale: Decimal,
+    // 从其他资产转入
+    pub total_transfer_in: Decimal,
+    // 转出到其他资产
+    pub total_transfer_out: Decimal,
     // 期末价值
-    pub value: f64,
-    // 累积收益, 正负均有可能
-    pub profit: f64,
-}
-
-impl RecordOutput {
-    pub fn new(program: Record) -> Self {
-        Self {
-            program,
-            total_purchase: 0.0,
-            total_sale: 0.0,
-            value: 0.0,
-            profit: 0.0,
-        }
-    }
-}
-
-pub struct DefineOutput {}
-
-struct PlanSnapshot {}
-
-// 每个资产的每日快照
-struct AssetSnapshot {
-    date: String,
-    symbol: Symbol,
-}
-
-pub enum Output {
-    Record(RecordOutput),
-    Define(DefineOutput),
+    pub value: Decimal,
+    // 持有份额, 只有带价格的交易才能推算
+    pub units: Decimal,
+    // 每份的平均持有成本, 拆分后会随份额调整
+    pub cost_per_unit: Option<Decimal>,
+    // 当天或者之前最近的单位价格
+    pub price: Option<Decimal>,
+    // 累积手续费
+    pub total_fee: Decimal,
+    // 累积税费
+    pub total_tax: Decimal,
+    // 累积收益(扣除费用后), 正负均有可能
+    pub profit: Decimal,
+    // 累积收益(扣除费用前)
+    pub gross_profit: Decimal,
 }
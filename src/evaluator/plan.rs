@@ -0,0 +1,223 @@
+use crate::decimal::Decimal;
+use crate::dsl::ast::{Details, Frequency, Plan, PlanRule, Schedule, Sign};
+use crate::evaluator::calendar::{date_from_days, days_from_date, parse_date};
+use crate::evaluator::engine::AnalysisReport;
+
+// 定投计划里某个标的在一个周期内的执行情况
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlanPeriod {
+    pub symbol: String,
+    // 周期的第一天, 第一个周期从计划的开始日期算起
+    pub start: String,
+    // 计划投入
+    pub planned: Decimal,
+    // 实际买入
+    pub actual: Decimal,
+}
+
+impl PlanPeriod {
+    pub fn is_met(&self) -> bool {
+        self.actual >= self.planned
+    }
+}
+
+// 计划从 START 开始 (没有的话从第一笔买入开始), 到 END_DATE 或者账本的最后一天为止,
+// 每个 SCHEDULE 按照频率切分周期, 统计每个周期里实际买入的金额
+pub fn plan_adherence(report: &AnalysisReport, plan: &Plan) -> Vec<PlanPeriod> {
+    let schedules: Vec<&Schedule> = plan
+        .rules
+        .iter()
+        .filter_map(|rule| match rule {
+            PlanRule::Schedule(schedule) => Some(schedule),
+            _ => None,
+        })
+        .collect();
+    let purchases: Vec<Vec<(String, Decimal)>> = schedules
+        .iter()
+        .map(|schedule| purchases(report, &schedule.target.to_string()))
+        .collect();
+
+    let start = plan
+        .rules
+        .iter()
+        .find_map(|rule| match rule {
+            PlanRule::StartDate(date) => Some(date.clone()),
+            _ => None,
+        })
+        .or_else(|| {
+            purchases
+                .iter()
+                .filter_map(|p| p.first().map(|(date, _)| date.clone()))
+                .min()
+        });
+
+    // 计划不能超过账本的最后一天, 之后的周期还没有发生
+    let end_date = plan.rules.iter().find_map(|rule| match rule {
+        PlanRule::EndDate(date) => Some(date.clone()),
+        _ => None,
+    });
+    let last_date = report
+        .daily_snapshot
+        .values()
+        .filter_map(|days| days.last().map(|day| day.date.clone()))
+        .max();
+    let end = match (end_date, last_date) {
+        (Some(end), Some(last)) => Some(end.min(last)),
+        (end, last) => end.or(last),
+    };
+
+    let (Some(start), Some(end)) = (start, end) else {
+        return Vec::new();
+    };
+
+    let mut periods = Vec::new();
+    for (schedule, purchases) in schedules.iter().zip(purchases.iter()) {
+        let Some(last) = period_index(&start, &end, &schedule.frequency).filter(|i| *i >= 0) else {
+            continue;
+        };
+
+        let mut actual = vec![Decimal::ZERO; last as usize + 1];
+        for (date, amount) in purchases.iter() {
+            if let Some(index) = period_index(&start, date, &schedule.frequency)
+                && (0..=last).contains(&index)
+            {
+                actual[index as usize] += *amount;
+            }
+        }
+
+        for (index, actual) in actual.into_iter().enumerate() {
+            periods.push(PlanPeriod {
+                symbol: schedule.target.to_string(),
+                start: period_start(&start, index as i64, &schedule.frequency),
+                planned: schedule.amount,
+                actual,
+            });
+        }
+    }
+
+    periods
+}
+
+// 某个标的所有的买入记录, 按日期排列
+fn purchases(report: &AnalysisReport, symbol: &str) -> Vec<(String, Decimal)> {
+    let Some(days) = report.daily_snapshot.get(symbol) else {
+        return Vec::new();
+    };
+
+    days.iter()
+        .flat_map(|day| day.snapshots.iter())
+        .filter_map(|shot| match &shot.statement.details {
+            Details::Trade(trade)
+                if trade.symbol.to_string() == symbol
+                    && trade.signed_amount.sign == Sign::Positive =>
+            {
+                Some((shot.date.clone(), trade.signed_amount.value))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+// date 落在从 start 开始的第几个周期, 月/季/年按照自然周期切分
+pub(crate) fn period_index(start: &str, date: &str, frequency: &Frequency) -> Option<i64> {
+    let (start_year, start_month, _) = parse_date(start)?;
+    let (year, month, _) = parse_date(date)?;
+    let months = |y: i64, m: u32| y * 12 + m as i64 - 1;
+
+    let index = match frequency {
+        Frequency::Daily => days_from_date(date)? - days_from_date(start)?,
+        Frequency::Weekly => (days_from_date(date)? - days_from_date(start)?).div_euclid(7),
+        Frequency::Monthly => months(year, month) - months(start_year, start_month),
+        Frequency::Quarterly => {
+            months(year, month).div_euclid(3) - months(start_year, start_month).div_euclid(3)
+        }
+        Frequency::Yearly => year - start_year,
+    };
+
+    Some(index)
+}
+
+pub(crate) fn period_start(start: &str, index: i64, frequency: &Frequency) -> String {
+    if index == 0 {
+        return start.to_string();
+    }
+
+    let Some((year, month, _)) = parse_date(start) else {
+        return start.to_string();
+    };
+    let first_of_month = |months: i64| {
+        format!(
+            "{:04}-{:02}-01",
+            months.div_euclid(12),
+            months.rem_euclid(12) + 1
+        )
+    };
+    let months = year * 12 + month as i64 - 1;
+
+    match frequency {
+        Frequency::Daily | Frequency::Weekly => {
+            let step = if *frequency == Frequency::Daily { 1 } else { 7 };
+            let days = days_from_date(start).unwrap_or_default();
+            date_from_days(days + index * step)
+        }
+        Frequency::Monthly => first_of_month(months + index),
+        Frequency::Quarterly => first_of_month((months.div_euclid(3) + index) * 3),
+        Frequency::Yearly => format!("{:04}-01-01", year + index),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluator::evaluate_str;
+
+    #[test]
+    fn test_monthly_plan_adherence() {
+        let input = r#"
+            PLAN "定投"
+              SCHEDULE MONTHLY 1000 CNY INTO ETF:510300
+              START 2024-01-15
+            END
+            2024-01-20 TRADE ETF:510300 +1000 CNY
+            2024-03-05 TRADE ETF:510300 +600 CNY
+            2024-03-25 TRADE ETF:510300 +400 CNY
+            2024-04-10 MARK ETF:510300 VALUE 2100 CNY
+        "#;
+        let report = evaluate_str(input);
+
+        let periods = plan_adherence(&report, &report.plans[0]);
+        let summary: Vec<(&str, f64, bool)> = periods
+            .iter()
+            .map(|p| (p.start.as_str(), p.actual.to_f64(), p.is_met()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("2024-01-15", 1000.0, true),
+                ("2024-02-01", 0.0, false),
+                ("2024-03-01", 1000.0, true),
+                ("2024-04-01", 0.0, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_weekly_period_index() {
+        assert_eq!(
+            period_index("2024-01-01", "2024-01-07", &Frequency::Weekly),
+            Some(0)
+        );
+        assert_eq!(
+            period_index("2024-01-01", "2024-01-08", &Frequency::Weekly),
+            Some(1)
+        );
+        assert_eq!(
+            period_start("2024-01-01", 2, &Frequency::Weekly),
+            "2024-01-15"
+        );
+        assert_eq!(
+            period_start("2024-02-10", 1, &Frequency::Quarterly),
+            "2024-04-01"
+        );
+    }
+}
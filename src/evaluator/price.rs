@@ -0,0 +1,128 @@
+use crate::decimal::Decimal;
+use crate::evaluator::engine::VALUE_SCALE;
+use std::collections::HashMap;
+
+// 某一天的单位价格
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PricePoint {
+    pub date: String,
+    pub price: Decimal,
+    pub unit: String,
+}
+
+impl PricePoint {
+    pub fn new(date: String, price: Decimal, unit: String) -> Self {
+        Self { date, price, unit }
+    }
+}
+
+// 每个资产的历史价格, 按日期升序保存
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PriceHistory {
+    prices: HashMap<String, Vec<PricePoint>>,
+}
+
+impl PriceHistory {
+    pub fn new() -> Self {
+        Self {
+            prices: HashMap::new(),
+        }
+    }
+
+    // 同一天的价格以最后一次为准
+    pub fn add(&mut self, symbol: &str, point: PricePoint) {
+        let points = self.prices.entry(symbol.to_string()).or_default();
+        match points.binary_search_by(|p| p.date.cmp(&point.date)) {
+            Ok(index) => points[index] = point,
+            Err(index) => points.insert(index, point),
+        }
+    }
+
+    // 指定日期当天或者之前最近的价格
+    pub fn price_at(&self, symbol: &str, date: &str) -> Option<&PricePoint> {
+        let points = self.prices.get(symbol)?;
+        let index = points.partition_point(|p| p.date.as_str() <= date);
+        if index == 0 {
+            None
+        } else {
+            points.get(index - 1)
+        }
+    }
+
+    pub fn latest(&self, symbol: &str) -> Option<&PricePoint> {
+        self.prices.get(symbol).and_then(|points| points.last())
+    }
+
+    pub fn history(&self, symbol: &str) -> &[PricePoint] {
+        self.prices
+            .get(symbol)
+            .map_or(&[], |points| points.as_slice())
+    }
+
+    pub fn symbols(&self) -> impl Iterator<Item = &String> {
+        self.prices.keys()
+    }
+
+    // 拆分之后, 之前的价格按比例还原成新的份额价格
+    // 1:3 这样的比例除不尽, 调整后的价格和估值一样保留 VALUE_SCALE 位小数
+    // 溢出的时候返回 None, 不修改任何价格
+    pub fn adjust_split(&mut self, symbol: &str, ratio: Decimal) -> Option<()> {
+        if let Some(points) = self.prices.get_mut(symbol) {
+            let adjusted = points
+                .iter()
+                .map(|point| point.price.checked_div(ratio))
+                .collect::<Option<Vec<Decimal>>>()?;
+            for (point, price) in points.iter_mut().zip(adjusted) {
+                point.price = price.round(VALUE_SCALE).normalize();
+            }
+        }
+
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(date: &str, price: f64) -> PricePoint {
+        PricePoint::new(
+            date.to_string(),
+            Decimal::from_f64(price),
+            "CNY".to_string(),
+        )
+    }
+
+    #[test]
+    fn test_price_at() {
+        let mut history = PriceHistory::new();
+        history.add("ETF:510300", point("2024-02-01", 4.2));
+        history.add("ETF:510300", point("2024-01-01", 4.0));
+        history.add("ETF:510300", point("2024-02-01", 4.3));
+
+        assert_eq!(history.history("ETF:510300").len(), 2);
+        assert_eq!(history.price_at("ETF:510300", "2023-12-31"), None);
+        assert_eq!(
+            history.price_at("ETF:510300", "2024-01-15").unwrap().price,
+            4.0
+        );
+        assert_eq!(
+            history.price_at("ETF:510300", "2024-02-01").unwrap().price,
+            4.3
+        );
+        assert_eq!(history.latest("ETF:510300").unwrap().price, 4.3);
+    }
+
+    #[test]
+    fn test_adjust_split() {
+        let mut history = PriceHistory::new();
+        history.add("ETF:510300", point("2024-01-01", 4.0));
+        history
+            .adjust_split("ETF:510300", Decimal::from(2))
+            .unwrap();
+
+        assert_eq!(history.latest("ETF:510300").unwrap().price, 2.0);
+    }
+}
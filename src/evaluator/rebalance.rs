@@ -0,0 +1,726 @@
+use crate::decimal::Decimal;
+use crate::dsl::ast::{
+    Action, Details, MemberKind, Metadata, PortfolioMember, RebalancePolicy, Record, SignedAmount,
+    Symbol, TradeDetails,
+};
+use crate::evaluator::engine::{AnalysisReport, EngineError, Portfolio};
+use crate::evaluator::filter::{Filter, filter_snapshots};
+use crate::evaluator::plan::{period_index, period_start};
+use std::collections::HashSet;
+
+// 生成的调仓交易都带上这个标签
+pub const REBALANCE_TAG: &str = "rebalance";
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RebalanceMode {
+    // 买入低配的资产, 卖出超配的资产, 调整完正好是目标权重
+    #[default]
+    Full,
+    // 只用新投入的现金买入低配的资产, 不卖出
+    ContributionOnly,
+}
+
+#[derive(Debug, Clone)]
+pub struct RebalanceOptions {
+    // 按照这一天 (含) 之前最近的估值计算, 生成的交易也用这个日期
+    pub date: String,
+    // 这次新投入的现金, 可以为 0
+    pub new_cash: Decimal,
+    pub mode: RebalanceMode,
+    // 交易的金额单位, 组合里的资产都还没有记录的时候才需要指定, 指定了就要和资产价值的单位一致
+    pub unit: Option<String>,
+}
+
+impl RebalanceOptions {
+    pub fn new(date: &str) -> Self {
+        Self {
+            date: date.to_string(),
+            new_cash: Decimal::ZERO,
+            mode: RebalanceMode::Full,
+            unit: None,
+        }
+    }
+
+    pub fn with_new_cash(mut self, new_cash: Decimal) -> Self {
+        self.new_cash = new_cash;
+        self
+    }
+
+    pub fn with_mode(mut self, mode: RebalanceMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn with_unit(mut self, unit: &str) -> Self {
+        self.unit = Some(unit.to_string());
+        self
+    }
+}
+
+// 组合里一个资产当前的权重和目标权重的偏离
+#[derive(Debug, Clone, PartialEq)]
+pub struct Drift {
+    // 资产代码, 或者子组合的名字
+    pub symbol: String,
+    // 最新的期末价值
+    pub value: Decimal,
+    pub current_weight: f64,
+    pub target_weight: f64,
+    // 当前权重 - 目标权重, 正数表示超配
+    pub absolute_drift: f64,
+    // 绝对偏离 / 目标权重, 目标权重为 0 的时候为 None
+    pub relative_drift: Option<f64>,
+}
+
+// 按照最新的期末价值计算组合的权重偏离, 组合没有设置目标权重的时候为空
+// 子组合作为一个整体计算偏离
+pub fn allocation_drift(report: &AnalysisReport, portfolio: &Portfolio) -> Vec<Drift> {
+    if !portfolio.has_target_weights() {
+        return Vec::new();
+    }
+
+    let values = member_values(report, portfolio, None);
+    let total: Decimal = values.iter().sum();
+
+    portfolio
+        .members
+        .iter()
+        .zip(values)
+        .map(|(member, value)| {
+            let current_weight = if total.is_positive() {
+                value.to_f64() / total.to_f64()
+            } else {
+                0.0
+            };
+            let target_weight = member.weight.unwrap_or(Decimal::ZERO).to_f64();
+            let absolute_drift = current_weight - target_weight;
+
+            Drift {
+                symbol: member_name(member),
+                value,
+                current_weight,
+                target_weight,
+                absolute_drift,
+                relative_drift: (target_weight > 0.0).then(|| absolute_drift / target_weight),
+            }
+        })
+        .collect()
+}
+
+// 把组合调整到目标权重需要的交易, 金额保留到分, 用 dsl::formatter::format_record 就能输出成 TRADE 语句
+// 有价格的资产带上当天的价格, 这样执行之后可以推算份额
+pub fn rebalance_trades(
+    report: &AnalysisReport,
+    portfolio: &Portfolio,
+    options: &RebalanceOptions,
+) -> Result<Vec<Record>, EngineError> {
+    if !portfolio.has_target_weights() {
+        return Err(EngineError::new(format!(
+            "Portfolio \"{}\" has no target weights",
+            portfolio.name
+        )));
+    }
+    if let Some(child) = portfolio.portfolios().next() {
+        return Err(EngineError::new(format!(
+            "Portfolio \"{}\" contains portfolio \"{}\", rebalance each portfolio separately",
+            portfolio.name, child
+        )));
+    }
+    if options.new_cash.is_negative() {
+        return Err(EngineError::new(format!(
+            "New cash must not be negative, found {}",
+            options.new_cash
+        )));
+    }
+
+    // 没有子组合的时候成员就是 assets 里的资产, 顺序也一样
+    let values: Vec<Decimal> = portfolio
+        .assets
+        .iter()
+        .map(|asset| value_at(report, asset.get_symbol(), &options.date))
+        .collect();
+    let total = values.iter().copied().sum::<Decimal>() + options.new_cash;
+    let targets: Vec<Decimal> = portfolio
+        .members
+        .iter()
+        .map(|member| total * member.weight.unwrap_or(Decimal::ZERO))
+        .collect();
+
+    let amounts: Vec<Decimal> = match options.mode {
+        RebalanceMode::Full => targets
+            .iter()
+            .zip(values.iter())
+            .map(|(target, value)| *target - *value)
+            .collect(),
+        RebalanceMode::ContributionOnly => {
+            // 低配的金额合计不会少于新的现金, 按低配的金额等比例分配
+            let deficits: Vec<Decimal> = targets
+                .iter()
+                .zip(values.iter())
+                .map(|(target, value)| (*target - *value).max(Decimal::ZERO))
+                .collect();
+            let deficit: Decimal = deficits.iter().sum();
+            if deficit.is_positive() {
+                deficits
+                    .iter()
+                    .map(|d| *d * options.new_cash / deficit)
+                    .collect()
+            } else {
+                vec![Decimal::ZERO; deficits.len()]
+            }
+        }
+    };
+
+    let mut amounts: Vec<Decimal> = amounts.iter().map(|a| a.round(2).normalize()).collect();
+    // 四舍五入之后的差额放到金额最大的交易上, 保证合计正好等于新投入的现金
+    let residual = options.new_cash - amounts.iter().copied().sum::<Decimal>();
+    if let Some(largest) = amounts.iter_mut().max_by_key(|a| a.abs()) {
+        *largest += residual;
+    }
+
+    let unit = trade_unit(report, portfolio, options)?;
+    let trades = portfolio
+        .assets
+        .iter()
+        .zip(amounts)
+        .filter(|(_, amount)| !amount.is_zero())
+        .map(|(asset, amount)| {
+            let (namespace, name) = asset
+                .get_symbol()
+                .split_once(':')
+                .unwrap_or(("", asset.get_symbol()));
+            let signed_amount = if amount.is_positive() {
+                SignedAmount::positive(amount)
+            } else {
+                SignedAmount::negative(-amount)
+            };
+            let price = report
+                .prices
+                .price_at(asset.get_symbol(), &options.date)
+                .map(|point| point.price);
+
+            Record {
+                date: options.date.clone(),
+                action: Action::Trade,
+                details: Details::Trade(TradeDetails {
+                    symbol: Symbol::new(namespace.to_string(), name.to_string()),
+                    signed_amount,
+                    unit: unit.clone(),
+                    price,
+                    fee: None,
+                    tax: None,
+                    account: None,
+                }),
+                note: None,
+                metadata: Metadata {
+                    tags: vec![REBALANCE_TAG.to_string()],
+                    ..Metadata::default()
+                },
+            }
+        })
+        .collect();
+    Ok(trades)
+}
+
+// 调仓交易的金额单位, 也就是组合里资产的价值的单位
+// 引擎不做汇率换算, 资产的价值用的是它的记录的单位, 所以组合里的资产必须用同一个单位记录
+// 都还没有记录的时候用 RebalanceOptions 指定的单位
+fn trade_unit(
+    report: &AnalysisReport,
+    portfolio: &Portfolio,
+    options: &RebalanceOptions,
+) -> Result<String, EngineError> {
+    let mut found: Option<(&str, String)> = None;
+    for asset in &portfolio.assets {
+        let symbol = asset.get_symbol();
+        let Some(unit) = value_unit(report, symbol) else {
+            continue;
+        };
+        match &found {
+            Some((other, other_unit)) if *other_unit != unit => {
+                return Err(EngineError::new(format!(
+                    "Portfolio \"{}\" has assets valued in different units, {} in {} and {} in {}",
+                    portfolio.name, other, other_unit, symbol, unit
+                )));
+            }
+            Some(_) => {}
+            None => found = Some((symbol, unit)),
+        }
+    }
+
+    match (found, &options.unit) {
+        (Some((_, unit)), Some(option)) if unit != *option => Err(EngineError::new(format!(
+            "Portfolio \"{}\" is valued in {}, not {}",
+            portfolio.name, unit, option
+        ))),
+        (Some((_, unit)), _) => Ok(unit),
+        (None, Some(option)) => Ok(option.clone()),
+        (None, None) => Err(EngineError::new(format!(
+            "Unknown unit for portfolio \"{}\", set RebalanceOptions::with_unit",
+            portfolio.name
+        ))),
+    }
+}
+
+// 资产最后一条带金额的记录的单位
+fn value_unit(report: &AnalysisReport, symbol: &str) -> Option<String> {
+    report
+        .daily_snapshot
+        .get(symbol)
+        .into_iter()
+        .flatten()
+        .flat_map(|day| day.snapshots.iter())
+        .filter_map(|shot| match &shot.statement.details {
+            Details::Trade(trade) => Some(&trade.unit),
+            Details::Mark(mark) => Some(&mark.unit),
+            Details::Fee(fee) => Some(&fee.charge.unit),
+            Details::Transfer(transfer) => Some(&transfer.unit),
+            Details::Cash(cash) => Some(&cash.unit),
+            Details::Price(price) => Some(&price.unit),
+            Details::Split(_) | Details::Merge(_) => None,
+        })
+        .next_back()
+        .cloned()
+}
+
+// 再平衡策略在某一天触发
+#[derive(Debug, Clone, PartialEq)]
+pub struct RebalanceTrigger {
+    pub date: String,
+    // 按照当天 (含) 之前最近的估值, 偏离目标最多的资产的绝对偏离
+    pub max_drift: f64,
+    // 从这次触发到下一次触发之前, 第一条带 #rebalance 标签的组合资产记录的日期
+    pub rebalanced_on: Option<String>,
+}
+
+impl RebalanceTrigger {
+    pub fn is_rebalanced(&self) -> bool {
+        self.rebalanced_on.is_some()
+    }
+}
+
+// 按照组合的 REBALANCE 策略回看历史, 列出每一次应该再平衡的日期, 以及有没有记录再平衡
+// 定期策略在组合第一条记录之后每个周期的第一天触发
+// 偏离策略在偏离超过阈值的那天触发, 回到阈值以内之后才会再次触发
+pub fn rebalance_triggers(report: &AnalysisReport, portfolio: &Portfolio) -> Vec<RebalanceTrigger> {
+    if !portfolio.has_target_weights() {
+        return Vec::new();
+    }
+    let Some(policy) = &portfolio.rebalance else {
+        return Vec::new();
+    };
+    let history = report.portfolio_history(portfolio);
+    let (Some((first, _)), Some((last, _))) = (history.first(), history.last()) else {
+        return Vec::new();
+    };
+
+    let dates: Vec<String> = match policy {
+        RebalancePolicy::Calendar(frequency) => {
+            let count = period_index(first, last, frequency).unwrap_or(0);
+            (1..=count)
+                .map(|index| period_start(first, index, frequency))
+                .collect()
+        }
+        RebalancePolicy::Drift(threshold) => {
+            let threshold = threshold.to_f64();
+            let mut dates = Vec::new();
+            let mut triggered = false;
+            for (date, _) in history.iter() {
+                // 留一点余量, 避免浮点误差让正好等于阈值的偏离被漏掉
+                let drifted = max_drift(report, portfolio, date) + 1e-9 >= threshold;
+                if drifted && !triggered {
+                    dates.push(date.clone());
+                }
+                triggered = drifted;
+            }
+            dates
+        }
+    };
+
+    let symbols = report.portfolio_symbols(portfolio);
+    let mut rebalanced: Vec<&String> = filter_snapshots(report, &Filter::Tag(REBALANCE_TAG.into()))
+        .into_iter()
+        .filter(|shot| symbols.contains(&shot.symbol))
+        .map(|shot| &shot.date)
+        .collect();
+    rebalanced.dedup();
+
+    dates
+        .iter()
+        .enumerate()
+        .map(|(index, date)| {
+            let next = dates.get(index + 1);
+            let rebalanced_on = rebalanced
+                .iter()
+                .find(|d| {
+                    d.as_str() >= date.as_str() && next.is_none_or(|n| d.as_str() < n.as_str())
+                })
+                .map(|d| d.to_string());
+
+            RebalanceTrigger {
+                date: date.clone(),
+                max_drift: max_drift(report, portfolio, date),
+                rebalanced_on,
+            }
+        })
+        .collect()
+}
+
+// 某一天 (含) 之前最近的估值下, 偏离目标最多的成员的绝对偏离
+fn max_drift(report: &AnalysisReport, portfolio: &Portfolio, date: &str) -> f64 {
+    let values: Vec<f64> = member_values(report, portfolio, Some(date))
+        .iter()
+        .map(|value| value.to_f64())
+        .collect();
+    let total: f64 = values.iter().sum();
+    if total <= 0.0 {
+        return 0.0;
+    }
+
+    values
+        .iter()
+        .zip(portfolio.members.iter())
+        .map(|(value, member)| {
+            (value / total - member.weight.unwrap_or(Decimal::ZERO).to_f64()).abs()
+        })
+        .fold(0.0, f64::max)
+}
+
+// 成员的名字, 资产是代码, 子组合是组合的名字
+fn member_name(member: &PortfolioMember) -> String {
+    match &member.kind {
+        MemberKind::Symbol(symbol) => symbol.to_string(),
+        MemberKind::Portfolio(name) => name.clone(),
+    }
+}
+
+// 每个成员在某一天 (含) 之前最近的期末价值, 没有日期的时候用最新的价值
+// 按照 ASSETS 里书写的顺序, 子组合按照展开后的资产汇总
+// 两个子组合包含同一个资产的时候只算在前一个里, 这样合计和 portfolio_summary 一致
+fn member_values(
+    report: &AnalysisReport,
+    portfolio: &Portfolio,
+    date: Option<&str>,
+) -> Vec<Decimal> {
+    let symbol_value = |symbol: &str| match date {
+        Some(date) => value_at(report, symbol, date),
+        None => report
+            .asset_summary(symbol)
+            .map_or(Decimal::ZERO, |summary| summary.value),
+    };
+
+    let mut seen = HashSet::new();
+    portfolio
+        .members
+        .iter()
+        .map(|member| {
+            let symbols = match &member.kind {
+                MemberKind::Symbol(symbol) => vec![symbol.to_string()],
+                MemberKind::Portfolio(name) => report
+                    .find_portfolio(name)
+                    .map(|child| report.portfolio_symbols(child))
+                    .unwrap_or_default(),
+            };
+            symbols
+                .into_iter()
+                .filter(|symbol| seen.insert(symbol.clone()))
+                .map(|symbol| symbol_value(&symbol))
+                .sum()
+        })
+        .collect()
+}
+
+// 某一天 (含) 之前最近的期末价值
+fn value_at(report: &AnalysisReport, symbol: &str, date: &str) -> Decimal {
+    let history = report.asset_history(symbol);
+    let index = history.partition_point(|(d, _)| d.as_str() <= date);
+    if index == 0 {
+        Decimal::ZERO
+    } else {
+        history[index - 1].1.value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsl::formatter::format_record;
+    use crate::evaluator::evaluate_str;
+
+    const INPUT: &str = r#"
+        PORTFOLIO "股债平衡"
+          ASSETS ETF:510300 60%, ETF:511010 40%
+        END
+        2024-01-02 TRADE ETF:510300 +6000 CNY
+        2024-01-02 TRADE ETF:511010 +4000 CNY
+        2024-06-30 MARK ETF:510300 VALUE 8000 CNY
+        2024-06-30 PRICE ETF:511010 1.25 CNY
+    "#;
+
+    fn format_trades(trades: &[Record]) -> Vec<String> {
+        trades.iter().map(format_record).collect()
+    }
+
+    #[test]
+    fn test_allocation_drift() {
+        let report = evaluate_str(INPUT);
+
+        let drift = allocation_drift(&report, &report.portfolios[0]);
+        assert_eq!(drift.len(), 2);
+        assert_eq!(drift[0].symbol, "ETF:510300");
+        assert!((drift[0].current_weight - 2.0 / 3.0).abs() < 1e-9);
+        assert!((drift[0].absolute_drift - (2.0 / 3.0 - 0.6)).abs() < 1e-9);
+        assert!((drift[1].relative_drift.unwrap() + 1.0 / 6.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_calendar_rebalance_triggers() {
+        let input = r#"
+            PORTFOLIO "股债平衡"
+              ASSETS ETF:510300 60%, ETF:511010 40%
+              REBALANCE QUARTERLY
+            END
+            2024-01-02 TRADE ETF:510300 +6000 CNY
+            2024-01-02 TRADE ETF:511010 +4000 CNY
+            2024-03-31 MARK ETF:510300 VALUE 7000 CNY
+            2024-04-08 TRADE ETF:510300 -400 CNY
+              TAG #rebalance
+            2024-04-08 TRADE ETF:511010 +400 CNY
+              TAG #rebalance
+            2024-08-15 MARK ETF:510300 VALUE 6000 CNY
+        "#;
+        let report = evaluate_str(input);
+
+        let triggers = rebalance_triggers(&report, &report.portfolios[0]);
+        let summary: Vec<(&str, Option<&str>)> = triggers
+            .iter()
+            .map(|t| (t.date.as_str(), t.rebalanced_on.as_deref()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![("2024-04-01", Some("2024-04-08")), ("2024-07-01", None)]
+        );
+        // 7000 / 11000 - 60%
+        assert!((triggers[0].max_drift - (7.0 / 11.0 - 0.6)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_drift_rebalance_triggers() {
+        let input = r#"
+            PORTFOLIO "股债平衡"
+              ASSETS ETF:510300 60%, ETF:511010 40%
+              REBALANCE DRIFT 5%
+            END
+            2024-01-02 TRADE ETF:510300 +6000 CNY
+            2024-01-02 TRADE ETF:511010 +4000 CNY
+            2024-02-29 MARK ETF:510300 VALUE 6500 CNY
+            2024-03-29 MARK ETF:510300 VALUE 8000 CNY
+            2024-04-30 MARK ETF:510300 VALUE 8500 CNY
+            2024-05-06 TRADE ETF:510300 -1500 CNY
+              TAG #rebalance
+            2024-05-06 TRADE ETF:511010 +1500 CNY
+              TAG #rebalance
+            2024-06-28 MARK ETF:510300 VALUE 5000 CNY
+        "#;
+        let report = evaluate_str(input);
+
+        // 偏离一直超过阈值的时候不会重复触发
+        let triggers = rebalance_triggers(&report, &report.portfolios[0]);
+        let summary: Vec<(&str, Option<&str>)> = triggers
+            .iter()
+            .map(|t| (t.date.as_str(), t.rebalanced_on.as_deref()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![("2024-03-29", Some("2024-05-06")), ("2024-06-28", None)]
+        );
+        assert!(!triggers[1].is_rebalanced());
+    }
+
+    #[test]
+    fn test_nested_portfolio_drift() {
+        let input = r#"
+            PORTFOLIO "家庭资产"
+              ASSETS "股债平衡" 80%, CASH:BANK 20%
+            END
+            PORTFOLIO "股债平衡"
+              ASSETS ETF:510300 60%, ETF:511010 40%
+            END
+            2024-01-01 DEPOSIT CASH:BANK 3000 CNY
+            2024-01-02 TRADE ETF:510300 +6000 CNY
+            2024-01-02 TRADE ETF:511010 +4000 CNY
+            2024-06-30 MARK ETF:510300 VALUE 8000 CNY
+        "#;
+        let report = evaluate_str(input);
+        let household = &report.portfolios[0];
+
+        // 子组合作为一个整体, 12000 / 15000; 成员按照 ASSETS 里的顺序
+        let drift = allocation_drift(&report, household);
+        assert_eq!(drift[0].symbol, "股债平衡");
+        assert_eq!(drift[0].value, Decimal::from(12000));
+        assert!((drift[0].current_weight - 0.8).abs() < 1e-9);
+        assert_eq!(drift[1].symbol, "CASH:BANK");
+        assert!((drift[1].target_weight - 0.2).abs() < 1e-9);
+
+        let options = RebalanceOptions::new("2024-07-01");
+        let error = rebalance_trades(&report, household, &options).unwrap_err();
+        assert!(
+            error
+                .message
+                .contains("rebalance each portfolio separately")
+        );
+    }
+
+    #[test]
+    fn test_shared_child_asset_drift() {
+        let input = r#"
+            PORTFOLIO "全部"
+              ASSETS "长期" 50%, "定投" 50%
+            END
+            PORTFOLIO "长期" ASSETS ETF:510300, ETF:511010 END
+            PORTFOLIO "定投" ASSETS ETF:510300, ETF:159915 END
+            2024-01-02 TRADE ETF:510300 +5000 CNY
+            2024-01-02 TRADE ETF:511010 +3000 CNY
+            2024-01-02 TRADE ETF:159915 +2000 CNY
+        "#;
+        let report = evaluate_str(input);
+
+        // ETF:510300 只算在 "长期" 里
+        let drift = allocation_drift(&report, &report.portfolios[0]);
+        let values: Vec<Decimal> = drift.iter().map(|d| d.value).collect();
+        assert_eq!(values, vec![Decimal::from(8000), Decimal::from(2000)]);
+        assert!((drift[0].current_weight - 0.8).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_full_rebalance() {
+        let report = evaluate_str(INPUT);
+        let portfolio = &report.portfolios[0];
+
+        // 总共 12000 + 1000, 目标是 7800 和 5200
+        let options = RebalanceOptions::new("2024-07-01").with_new_cash(Decimal::from(1000));
+        let trades = rebalance_trades(&report, portfolio, &options).unwrap();
+        assert_eq!(
+            format_trades(&trades),
+            vec![
+                "2024-07-01 TRADE ETF:510300 -200 CNY\n  TAG #rebalance",
+                "2024-07-01 TRADE ETF:511010 +1200 CNY @ 1.25\n  TAG #rebalance",
+            ]
+        );
+
+        // 按照之前的估值, 当时正好是目标权重
+        let options = RebalanceOptions::new("2024-01-31");
+        assert!(
+            rebalance_trades(&report, portfolio, &options)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_contribution_only_rebalance() {
+        let report = evaluate_str(INPUT);
+        let portfolio = &report.portfolios[0];
+
+        // 现金不够补齐低配, 全部买入债券
+        let options = RebalanceOptions::new("2024-07-01")
+            .with_new_cash(Decimal::from(1000))
+            .with_mode(RebalanceMode::ContributionOnly);
+        let trades = rebalance_trades(&report, portfolio, &options).unwrap();
+        assert_eq!(
+            format_trades(&trades),
+            vec!["2024-07-01 TRADE ETF:511010 +1000 CNY @ 1.25\n  TAG #rebalance"]
+        );
+
+        // 现金足够的时候两个都买入, 买完正好是目标权重
+        let options = options.with_new_cash(Decimal::from(2333));
+        let trades = rebalance_trades(&report, portfolio, &options).unwrap();
+        let amounts: Vec<f64> = trades
+            .iter()
+            .map(|t| match &t.details {
+                Details::Trade(trade) => trade.signed_amount.to_f64(),
+                _ => 0.0,
+            })
+            .collect();
+        assert_eq!(amounts, vec![599.8, 1733.2]);
+    }
+
+    #[test]
+    fn test_rebalance_trade_unit() {
+        let input = r#"
+            DEFINE ETF:QQQ
+              CURRENCY USD
+            END
+            PORTFOLIO "美股"
+              ASSETS ETF:QQQ 50%, ETF:VOO 50%
+            END
+            2024-01-02 TRADE ETF:QQQ +6000 CNY
+            2024-01-02 TRADE ETF:VOO +4000 CNY
+        "#;
+        let report = evaluate_str(input);
+        let portfolio = &report.portfolios[0];
+
+        // 价值是按照记录的单位计算的, 和 CURRENCY 无关
+        let options = RebalanceOptions::new("2024-07-01");
+        assert_eq!(
+            format_trades(&rebalance_trades(&report, portfolio, &options).unwrap()),
+            vec![
+                "2024-07-01 TRADE ETF:QQQ -1000 CNY\n  TAG #rebalance",
+                "2024-07-01 TRADE ETF:VOO +1000 CNY\n  TAG #rebalance",
+            ]
+        );
+
+        let error = rebalance_trades(&report, portfolio, &options.with_unit("USD")).unwrap_err();
+        assert_eq!(
+            error.message,
+            "Portfolio \"美股\" is valued in CNY, not USD"
+        );
+
+        // 不同单位的价值不能相加
+        let input = input.replace("+4000 CNY", "+4000 HKD");
+        let report = evaluate_str(&input);
+        let options = RebalanceOptions::new("2024-07-01");
+        let error = rebalance_trades(&report, &report.portfolios[0], &options).unwrap_err();
+        assert_eq!(
+            error.message,
+            "Portfolio \"美股\" has assets valued in different units, ETF:QQQ in CNY and ETF:VOO in HKD"
+        );
+
+        // 没有交易过的资产用其他资产的单位
+        let input = r#"
+            PORTFOLIO "股债平衡"
+              ASSETS ETF:510300 60%, ETF:511010 40%
+            END
+            2024-01-02 TRADE ETF:510300 +6000 CNY
+        "#;
+        let report = evaluate_str(input);
+        let trades = rebalance_trades(&report, &report.portfolios[0], &options).unwrap();
+        assert_eq!(
+            format_trades(&trades)[1],
+            "2024-07-01 TRADE ETF:511010 +2400 CNY\n  TAG #rebalance"
+        );
+
+        // 都没有记录的时候需要指定单位
+        let input = r#"
+            PORTFOLIO "新组合"
+              ASSETS ETF:510300 60%, ETF:511010 40%
+            END
+        "#;
+        let report = evaluate_str(input);
+        let portfolio = &report.portfolios[0];
+        let options = options.with_new_cash(Decimal::from(1000));
+        let error = rebalance_trades(&report, portfolio, &options).unwrap_err();
+        assert_eq!(
+            error.message,
+            "Unknown unit for portfolio \"新组合\", set RebalanceOptions::with_unit"
+        );
+        let trades = rebalance_trades(&report, portfolio, &options.with_unit("CNY")).unwrap();
+        assert_eq!(
+            format_trades(&trades),
+            vec![
+                "2024-07-01 TRADE ETF:510300 +600 CNY\n  TAG #rebalance",
+                "2024-07-01 TRADE ETF:511010 +400 CNY\n  TAG #rebalance",
+            ]
+        );
+    }
+}
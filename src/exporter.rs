@@ -0,0 +1,4 @@
+pub mod beancount;
+pub mod html;
+pub mod report;
+pub mod terminal;
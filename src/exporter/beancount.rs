@@ -0,0 +1,486 @@
+use crate::decimal::Decimal;
+use crate::dsl::ast::{
+    Action, Charge, Define, Details, Program, Record, Statement, Symbol, TradeDetails,
+};
+use crate::dsl::formatter::{format_action, format_record};
+use std::collections::{BTreeMap, HashMap};
+
+// 导出时使用的账户名称
+#[derive(Debug, Clone)]
+pub struct BeancountOptions {
+    // 投资资产的根账户, 资产账户为 <root>:<namespace>:<name>
+    pub asset_root: String,
+    // CASH:<name> 对应 <cash_root>:<name>
+    pub cash_root: String,
+    // 没有 VIA 的交易和存取款的资金来源
+    pub funding_account: String,
+    pub fee_account: String,
+    pub tax_account: String,
+    // 卖出时的盈亏
+    pub pnl_account: String,
+    // 份额保留的小数位数
+    pub unit_precision: usize,
+}
+
+impl Default for BeancountOptions {
+    fn default() -> Self {
+        Self {
+            asset_root: "Assets:Invest".to_string(),
+            cash_root: "Assets:Cash".to_string(),
+            funding_account: "Equity:Contributions".to_string(),
+            fee_account: "Expenses:Invest:Fees".to_string(),
+            tax_account: "Expenses:Invest:Taxes".to_string(),
+            pnl_account: "Income:Invest:PnL".to_string(),
+            unit_precision: 4,
+        }
+    }
+}
+
+pub fn export(program: &Program) -> String {
+    export_with_options(program, &BeancountOptions::default())
+}
+
+// DEFINE 生成 commodity, TRADE 生成带成本和价格的交易, MARK 生成 price,
+// NOTE 作为交易的 metadata; beancount 里没有对应概念的记录会以注释的形式保留
+pub fn export_with_options(program: &Program, options: &BeancountOptions) -> String {
+    let mut exporter = Exporter::new(options);
+
+    let mut records: Vec<&Record> = Vec::new();
+    for (statement, _) in program.statements.iter() {
+        match statement {
+            Statement::Record(record) => records.push(record),
+            Statement::Define(define) => exporter.add_define(define),
+            _ => {}
+        }
+    }
+    records.sort_by(|a, b| a.date.cmp(&b.date));
+
+    let entries: Vec<String> = records
+        .iter()
+        .map(|record| exporter.export_record(record))
+        .collect();
+
+    let mut output = exporter.header(records.first().map(|r| r.date.as_str()));
+    for entry in entries {
+        output.push('\n');
+        output.push_str(&entry);
+        output.push('\n');
+    }
+
+    output
+}
+
+struct Exporter<'a> {
+    options: &'a BeancountOptions,
+    aliases: HashMap<String, String>,
+    // 根据带价格的交易推算出来的份额
+    units: HashMap<String, Decimal>,
+    // 用到的账户和 commodity, 按名字排序输出
+    accounts: BTreeMap<String, ()>,
+    commodities: BTreeMap<String, Option<String>>,
+}
+
+impl<'a> Exporter<'a> {
+    fn new(options: &'a BeancountOptions) -> Self {
+        Self {
+            options,
+            aliases: HashMap::new(),
+            units: HashMap::new(),
+            accounts: BTreeMap::new(),
+            commodities: BTreeMap::new(),
+        }
+    }
+
+    fn add_define(&mut self, define: &Define) {
+        let symbol = define.symbol.to_string();
+        if let Some(alias) = &define.alias {
+            self.aliases.insert(symbol, alias.clone());
+        }
+        self.commodity(&define.symbol);
+    }
+
+    // 所有账户和 commodity 都在第一条记录的日期开立
+    fn header(&self, first_date: Option<&str>) -> String {
+        let date = first_date.unwrap_or("1970-01-01");
+        let mut lines = Vec::new();
+
+        for (commodity, name) in self.commodities.iter() {
+            lines.push(format!("{} commodity {}", date, commodity));
+            if let Some(name) = name {
+                lines.push(format!("  name: {}", quote(name)));
+            }
+        }
+        if !self.commodities.is_empty() {
+            lines.push(String::new());
+        }
+
+        for account in self.accounts.keys() {
+            lines.push(format!("{} open {}", date, account));
+        }
+
+        let mut header = lines.join("\n");
+        header.push('\n');
+        header
+    }
+
+    fn account(&mut self, account: String) -> String {
+        self.accounts.insert(account.clone(), ());
+        account
+    }
+
+    fn asset_account(&mut self, symbol: &Symbol) -> String {
+        let account = if symbol.is_cash() {
+            format!(
+                "{}:{}",
+                self.options.cash_root,
+                account_component(&symbol.name)
+            )
+        } else {
+            format!(
+                "{}:{}:{}",
+                self.options.asset_root,
+                account_component(&symbol.namespace),
+                account_component(&symbol.name)
+            )
+        };
+        self.account(account)
+    }
+
+    fn funding_account(&mut self, trade: &TradeDetails) -> String {
+        match &trade.account {
+            Some(account) => self.asset_account(account),
+            None => self.account(self.options.funding_account.clone()),
+        }
+    }
+
+    fn commodity(&mut self, symbol: &Symbol) -> String {
+        let commodity = commodity_name(symbol);
+        let alias = self.aliases.get(&symbol.to_string()).cloned();
+        let entry = self.commodities.entry(commodity.clone()).or_default();
+        if alias.is_some() {
+            *entry = alias;
+        }
+        commodity
+    }
+
+    fn export_record(&mut self, record: &Record) -> String {
+        match &record.details {
+            Details::Trade(trade) => self.export_trade(record, trade),
+            Details::Mark(mark) => {
+                let symbol = mark.symbol.to_string();
+                match self.units.get(&symbol).filter(|units| units.is_positive()) {
+                    Some(units) => {
+                        let price = mark.value / *units;
+                        let commodity = self.commodity(&mark.symbol);
+                        format!(
+                            "{} price {} {} {}",
+                            record.date,
+                            commodity,
+                            format_number(price, 6),
+                            mark.unit
+                        )
+                    }
+                    None => comment(record, "no units known, cannot derive a price"),
+                }
+            }
+            Details::Price(price) => {
+                let commodity = self.commodity(&price.symbol);
+                format!(
+                    "{} price {} {} {}",
+                    record.date,
+                    commodity,
+                    format_number(price.price, 6),
+                    price.unit
+                )
+            }
+            Details::Fee(fee) => {
+                let expense = if record.action == Action::Tax {
+                    self.account(self.options.tax_account.clone())
+                } else {
+                    self.account(self.options.fee_account.clone())
+                };
+                let funding = self.account(self.options.funding_account.clone());
+                let lines = vec![
+                    posting(&expense, &format_charge(&fee.charge)),
+                    posting(&funding, &format_charge(&negate(&fee.charge))),
+                ];
+                transaction(
+                    record,
+                    &format!("{} {}", format_action(&record.action), fee.symbol),
+                    lines,
+                )
+            }
+            Details::Cash(cash) => {
+                let account = self.asset_account(&cash.symbol);
+                let funding = self.account(self.options.funding_account.clone());
+                let amount = if record.action == Action::Deposit {
+                    cash.amount
+                } else {
+                    -cash.amount
+                };
+                let lines = vec![
+                    posting(
+                        &account,
+                        &format!("{} {}", format_amount(amount), cash.unit),
+                    ),
+                    posting(
+                        &funding,
+                        &format!("{} {}", format_amount(-amount), cash.unit),
+                    ),
+                ];
+                transaction(
+                    record,
+                    &format!("{} {}", format_action(&record.action), cash.symbol),
+                    lines,
+                )
+            }
+            Details::Split(split) => {
+                let symbol = split.symbol.to_string();
+                if let Some(units) = self.units.get_mut(&symbol) {
+                    *units = *units * split.ratio;
+                }
+                comment(record, "splits are not supported by beancount")
+            }
+            Details::Merge(_) | Details::Transfer(_) => {
+                comment(record, "not supported by beancount export")
+            }
+        }
+    }
+
+    fn export_trade(&mut self, record: &Record, trade: &TradeDetails) -> String {
+        let account = self.asset_account(&trade.symbol);
+        let funding = self.funding_account(trade);
+        let amount = trade.signed_amount.value;
+        let unit = &trade.unit;
+        let precision = self.options.unit_precision;
+
+        let mut lines = Vec::new();
+        let fee = trade.fee.as_ref().map_or(Decimal::ZERO, |c| c.amount);
+        let tax = trade.tax.as_ref().map_or(Decimal::ZERO, |c| c.amount);
+
+        match trade.price.filter(|p| p.is_positive()) {
+            Some(price) => {
+                let commodity = self.commodity(&trade.symbol);
+                let units = amount / price;
+                let symbol = trade.symbol.to_string();
+                let held = self.units.entry(symbol).or_default();
+
+                if trade.buy() {
+                    *held += units;
+                    lines.push(posting(
+                        &account,
+                        &format!(
+                            "{} {} {{{{{} {}}}}} @ {} {}",
+                            format_number(units, precision),
+                            commodity,
+                            format_amount(amount),
+                            unit,
+                            format_number(price, 6),
+                            unit
+                        ),
+                    ));
+                } else {
+                    *held -= units;
+                    lines.push(posting(
+                        &account,
+                        &format!(
+                            "-{} {} {{}} @ {} {}",
+                            format_number(units, precision),
+                            commodity,
+                            format_number(price, 6),
+                            unit
+                        ),
+                    ));
+                }
+            }
+            // 没有价格时无法推算份额, 直接按金额记账
+            None => lines.push(posting(
+                &account,
+                &format!(
+                    "{} {}",
+                    format_amount(trade.signed_amount.to_decimal()),
+                    unit
+                ),
+            )),
+        }
+
+        let cash = if trade.buy() {
+            -(amount + fee + tax)
+        } else {
+            amount - fee - tax
+        };
+        lines.push(posting(
+            &funding,
+            &format!("{} {}", format_amount(cash), unit),
+        ));
+
+        if let Some(fee) = &trade.fee {
+            let expense = self.account(self.options.fee_account.clone());
+            lines.push(posting(&expense, &format_charge(fee)));
+        }
+        if let Some(tax) = &trade.tax {
+            let expense = self.account(self.options.tax_account.clone());
+            lines.push(posting(&expense, &format_charge(tax)));
+        }
+
+        // 卖出时成本由 beancount 按批次计算, 差额记入盈亏
+        if trade.sell() && trade.price.is_some() {
+            let pnl = self.account(self.options.pnl_account.clone());
+            lines.push(format!("  {}", pnl));
+        }
+
+        transaction(record, &format!("TRADE {}", trade.symbol), lines)
+    }
+}
+
+fn transaction(record: &Record, narration: &str, postings: Vec<String>) -> String {
+    let mut lines = vec![format!("{} * {}", record.date, quote(narration))];
+    if let Some(note) = &record.note {
+        lines.push(format!("  note: {}", quote(note)));
+    }
+    lines.extend(postings);
+
+    lines.join("\n")
+}
+
+fn posting(account: &str, amount: &str) -> String {
+    format!("  {}  {}", account, amount)
+}
+
+fn comment(record: &Record, reason: &str) -> String {
+    let text = format_record(record);
+    let mut lines: Vec<String> = text.lines().map(|line| format!("; {}", line)).collect();
+    lines.push(format!("; ({})", reason));
+
+    lines.join("\n")
+}
+
+fn format_charge(charge: &Charge) -> String {
+    format!("{} {}", format_amount(charge.amount), charge.unit)
+}
+
+fn negate(charge: &Charge) -> Charge {
+    Charge::new(-charge.amount, charge.unit.clone())
+}
+
+// beancount 的 commodity 必须是大写字母开头, 例如 ETF:510300 对应 ETF.510300
+pub fn commodity_name(symbol: &Symbol) -> String {
+    let raw = format!("{}.{}", symbol.namespace, symbol.name).to_uppercase();
+    let mut name: String = raw
+        .chars()
+        .map(|ch| match ch {
+            'A'..='Z' | '0'..='9' | '.' | '_' | '-' | '\'' => ch,
+            _ => '_',
+        })
+        .collect();
+    if !name.starts_with(|ch: char| ch.is_ascii_uppercase()) {
+        name.insert(0, 'X');
+    }
+    name
+}
+
+// 账户名的每一段只能包含字母数字和 '-', 并且以大写字母或数字开头
+fn account_component(value: &str) -> String {
+    let mut component: String = value
+        .chars()
+        .map(|ch| if ch.is_ascii_alphanumeric() { ch } else { '-' })
+        .collect();
+    if let Some(first) = component.get_mut(0..1) {
+        first.make_ascii_uppercase();
+    }
+    component
+}
+
+// 金额按照原始精度输出, 去掉末尾的 0
+fn format_amount(value: Decimal) -> String {
+    value.normalize().to_string()
+}
+
+fn format_number(value: Decimal, precision: usize) -> String {
+    let text = format!("{:.*}", precision, value);
+    let text = if text.contains('.') {
+        text.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        text.as_str()
+    };
+
+    if text == "-0" {
+        "0".to_string()
+    } else {
+        text.to_string()
+    }
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsl::{Lexer, Parser};
+
+    fn export_input(input: &str) -> String {
+        let tokens = Lexer::new(input).tokenize().unwrap();
+        let program = Parser::new(tokens).parse().unwrap();
+        export(&program)
+    }
+
+    #[test]
+    fn test_export_trades() {
+        let output = export_input(
+            r#"
+            DEFINE ETF:510300
+                ALIAS "沪深300ETF"
+            END
+
+            2024-01-02 TRADE ETF:510300 +5000 CNY @ 4 VIA CASH:BROKER FEE 5 CNY
+                NOTE "新年第一笔定投"
+            2024-01-01 DEPOSIT CASH:BROKER 10000 CNY
+            2024-02-01 TRADE ETF:510300 -2000 CNY @ 5 VIA CASH:BROKER
+            2024-03-31 MARK ETF:510300 VALUE 3600 CNY
+            2024-04-01 SPLIT ETF:510300 2
+            "#,
+        );
+
+        let expected = r#"2024-01-01 commodity ETF.510300
+  name: "沪深300ETF"
+
+2024-01-01 open Assets:Cash:BROKER
+2024-01-01 open Assets:Invest:ETF:510300
+2024-01-01 open Equity:Contributions
+2024-01-01 open Expenses:Invest:Fees
+2024-01-01 open Income:Invest:PnL
+
+2024-01-01 * "DEPOSIT CASH:BROKER"
+  Assets:Cash:BROKER  10000 CNY
+  Equity:Contributions  -10000 CNY
+
+2024-01-02 * "TRADE ETF:510300"
+  note: "新年第一笔定投"
+  Assets:Invest:ETF:510300  1250 ETF.510300 {{5000 CNY}} @ 4 CNY
+  Assets:Cash:BROKER  -5005 CNY
+  Expenses:Invest:Fees  5 CNY
+
+2024-02-01 * "TRADE ETF:510300"
+  Assets:Invest:ETF:510300  -400 ETF.510300 {} @ 5 CNY
+  Assets:Cash:BROKER  2000 CNY
+  Income:Invest:PnL
+
+2024-03-31 price ETF.510300 4.235294 CNY
+
+; 2024-04-01 SPLIT ETF:510300 2
+; (splits are not supported by beancount)
+"#;
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn test_commodity_name() {
+        let symbol = Symbol::new("etf".to_string(), "510300".to_string());
+        assert_eq!(commodity_name(&symbol), "ETF.510300");
+
+        let symbol = Symbol::new("_x".to_string(), "a b".to_string());
+        assert_eq!(commodity_name(&symbol), "X_X.A_B");
+    }
+}
@@ -0,0 +1,477 @@
+use crate::decimal::Decimal;
+use crate::evaluator::calendar::{date_from_days, days_from_date};
+use crate::evaluator::engine::{AnalysisReport, Summary};
+use crate::evaluator::plan::{PlanPeriod, plan_adherence};
+use crate::exporter::report::{
+    Align, Language, Table, asset_table, format_money, portfolio_table, shared_asset_table,
+    sorted_assets,
+};
+
+// 图表的配色, 按顺序循环使用
+const PALETTE: [&str; 8] = [
+    "#4e79a7", "#f28e2b", "#59a14f", "#e15759", "#76b7b2", "#edc948", "#b07aa1", "#9c755f",
+];
+
+const CHART_WIDTH: f64 = 640.0;
+const CHART_HEIGHT: f64 = 260.0;
+const MARGIN_LEFT: f64 = 80.0;
+const MARGIN_RIGHT: f64 = 16.0;
+const MARGIN_TOP: f64 = 16.0;
+const MARGIN_BOTTOM: f64 = 28.0;
+
+const STYLE: &str = "body{font-family:-apple-system,'PingFang SC','Microsoft YaHei',sans-serif;\
+margin:24px;color:#222}table{border-collapse:collapse;margin:8px 0 24px}\
+th,td{border:1px solid #ddd;padding:4px 8px}th{background:#f5f5f5}td.num{text-align:right}\
+.legend span{display:inline-block;margin-right:16px}\
+.legend i{display:inline-block;width:10px;height:10px;margin-right:4px}\
+svg text{font-size:11px;fill:#555}";
+
+// 一条折线, x 是从 1970-01-01 开始的天数
+struct Series {
+    name: String,
+    color: &'static str,
+    dashed: bool,
+    points: Vec<(f64, f64)>,
+}
+
+// 生成一个不依赖外部资源和 JS 的 HTML 文件, 图表都是内嵌的 SVG
+pub fn render(report: &AnalysisReport, language: Language) -> String {
+    let mut body = String::new();
+
+    body.push_str(&format!(
+        "<h1>{}</h1>\n",
+        language.pick("投资报表", "Investment Report")
+    ));
+    body.push_str(&format!("<h2>{}</h2>\n", language.pick("资产", "Assets")));
+    body.push_str(&table_html(&asset_table(report, language)));
+    if !report.portfolios.is_empty() {
+        body.push_str(&format!(
+            "<h2>{}</h2>\n",
+            language.pick("组合", "Portfolios")
+        ));
+        body.push_str(&table_html(&portfolio_table(report, language)));
+    }
+    let shared = shared_asset_table(report, language);
+    if !shared.rows.is_empty() {
+        body.push_str(&format!(
+            "<h2>{}</h2>\n",
+            language.pick("多个组合共享的资产", "Shared Assets")
+        ));
+        body.push_str(&table_html(&shared));
+    }
+
+    body.push_str(&format!(
+        "<h2>{}</h2>\n",
+        language.pick("资产配置", "Allocation")
+    ));
+    body.push_str(&allocation_chart(report));
+
+    body.push_str(&format!(
+        "<h2>{}</h2>\n",
+        language.pick("价值和累积投入", "Value vs Contribution")
+    ));
+    for portfolio in report.portfolios.iter() {
+        let history = report.portfolio_history(portfolio);
+        body.push_str(&format!("<h3>{}</h3>\n", escape(&portfolio.name)));
+        body.push_str(&value_chart(&history, language));
+    }
+    for asset in sorted_assets(report) {
+        let history = report.asset_history(asset.get_symbol());
+        if history.is_empty() {
+            continue;
+        }
+        let title = match asset.get_alias() {
+            Some(alias) => format!("{} ({})", alias, asset.get_symbol()),
+            None => asset.get_symbol().clone(),
+        };
+        body.push_str(&format!("<h3>{}</h3>\n", escape(&title)));
+        body.push_str(&value_chart(&history, language));
+    }
+
+    body.push_str(&format!(
+        "<h2>{}</h2>\n",
+        language.pick("累积收益", "Profit")
+    ));
+    body.push_str(&profit_chart(report));
+
+    if !report.plans.is_empty() {
+        body.push_str(&format!(
+            "<h2>{}</h2>\n",
+            language.pick("计划执行情况", "Plan Adherence")
+        ));
+        for plan in report.plans.iter() {
+            body.push_str(&format!("<h3>{}</h3>\n", escape(&plan.name)));
+            body.push_str(&plan_timeline(&plan_adherence(report, plan)));
+        }
+    }
+
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n\
+         <style>{}</style>\n</head>\n<body>\n{}</body>\n</html>\n",
+        language.pick("投资报表", "Investment Report"),
+        STYLE,
+        body
+    )
+}
+
+fn table_html(table: &Table) -> String {
+    let mut html = String::from("<table>\n<tr>");
+    for header in table.headers.iter() {
+        html.push_str(&format!("<th>{}</th>", escape(header)));
+    }
+    html.push_str("</tr>\n");
+
+    for row in table.rows.iter() {
+        html.push_str("<tr>");
+        for (cell, align) in row.iter().zip(table.align.iter()) {
+            match align {
+                Align::Left => {
+                    html.push_str(&format!("<td>{}</td>", escape(cell)));
+                }
+                Align::Right => {
+                    html.push_str(&format!("<td class=\"num\">{}</td>", escape(cell)));
+                }
+            }
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</table>\n");
+
+    html
+}
+
+// 期末价值和净投入 (投入 - 转出 + 转入 - 转出到其他资产) 的对比
+fn value_chart(history: &[(String, Summary)], language: Language) -> String {
+    let points = |metric: fn(&Summary) -> f64| -> Vec<(f64, f64)> {
+        history
+            .iter()
+            .filter_map(|(date, summary)| Some((days_from_date(date)? as f64, metric(summary))))
+            .collect()
+    };
+
+    line_chart(&[
+        Series {
+            name: language.pick("期末价值", "Value").to_string(),
+            color: PALETTE[0],
+            dashed: false,
+            points: points(|s| s.value.to_f64()),
+        },
+        Series {
+            name: language.pick("累积投入", "Contribution").to_string(),
+            color: PALETTE[1],
+            dashed: true,
+            points: points(|s| {
+                (s.total_purchase - s.total_sale + s.total_transfer_in - s.total_transfer_out)
+                    .to_f64()
+            }),
+        },
+    ])
+}
+
+// 每个组合和资产的累积收益曲线, 组合用虚线
+fn profit_chart(report: &AnalysisReport) -> String {
+    let mut series = Vec::new();
+    let histories = report
+        .portfolios
+        .iter()
+        .map(|p| (p.name.clone(), true, report.portfolio_history(p)))
+        .chain(sorted_assets(report).into_iter().map(|asset| {
+            let name = asset
+                .get_alias()
+                .clone()
+                .unwrap_or(asset.get_symbol().clone());
+            (name, false, report.asset_history(asset.get_symbol()))
+        }));
+
+    for (index, (name, dashed, history)) in histories.enumerate() {
+        if history.is_empty() {
+            continue;
+        }
+        series.push(Series {
+            name,
+            color: PALETTE[index % PALETTE.len()],
+            dashed,
+            points: history
+                .iter()
+                .filter_map(|(date, s)| Some((days_from_date(date)? as f64, s.profit.to_f64())))
+                .collect(),
+        });
+    }
+
+    line_chart(&series)
+}
+
+fn line_chart(series: &[Series]) -> String {
+    let points = || series.iter().flat_map(|s| s.points.iter());
+    if points().next().is_none() {
+        return String::new();
+    }
+
+    let (mut min_x, mut max_x) = (f64::MAX, f64::MIN);
+    // y 轴总是包含 0
+    let (mut min_y, mut max_y) = (0.0_f64, 0.0_f64);
+    for (x, y) in points() {
+        min_x = min_x.min(*x);
+        max_x = max_x.max(*x);
+        min_y = min_y.min(*y);
+        max_y = max_y.max(*y);
+    }
+    if max_x == min_x {
+        max_x += 1.0;
+    }
+    if max_y == min_y {
+        max_y += 1.0;
+    }
+
+    let plot_width = CHART_WIDTH - MARGIN_LEFT - MARGIN_RIGHT;
+    let plot_height = CHART_HEIGHT - MARGIN_TOP - MARGIN_BOTTOM;
+    let sx = |x: f64| MARGIN_LEFT + (x - min_x) / (max_x - min_x) * plot_width;
+    let sy = |y: f64| MARGIN_TOP + (max_y - y) / (max_y - min_y) * plot_height;
+
+    let mut svg = svg_open(CHART_WIDTH, CHART_HEIGHT);
+
+    // 横向的刻度线和金额
+    for step in 0..=4 {
+        let value = min_y + (max_y - min_y) * step as f64 / 4.0;
+        let y = sy(value);
+        svg.push_str(&format!(
+            "<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"#eee\"/>\
+             <text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"end\">{}</text>\n",
+            MARGIN_LEFT,
+            y,
+            CHART_WIDTH - MARGIN_RIGHT,
+            y,
+            MARGIN_LEFT - 6.0,
+            y + 4.0,
+            format_money(Decimal::from_f64(value))
+        ));
+    }
+    svg.push_str(&format!(
+        "<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"#999\"/>\n",
+        MARGIN_LEFT,
+        sy(0.0),
+        CHART_WIDTH - MARGIN_RIGHT,
+        sy(0.0)
+    ));
+
+    // 开始和结束的日期
+    let baseline = CHART_HEIGHT - 8.0;
+    svg.push_str(&format!(
+        "<text x=\"{:.1}\" y=\"{:.1}\">{}</text>\
+         <text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"end\">{}</text>\n",
+        MARGIN_LEFT,
+        baseline,
+        date_from_days(min_x as i64),
+        CHART_WIDTH - MARGIN_RIGHT,
+        baseline,
+        date_from_days(max_x as i64)
+    ));
+
+    for s in series.iter() {
+        let coordinates: Vec<String> = s
+            .points
+            .iter()
+            .map(|(x, y)| format!("{:.1},{:.1}", sx(*x), sy(*y)))
+            .collect();
+        svg.push_str(&format!(
+            "<polyline fill=\"none\" stroke=\"{}\" stroke-width=\"2\"{} points=\"{}\"/>\n",
+            s.color,
+            if s.dashed {
+                " stroke-dasharray=\"6 4\""
+            } else {
+                ""
+            },
+            coordinates.join(" ")
+        ));
+    }
+    svg.push_str("</svg>\n");
+
+    let legend: Vec<(String, &str)> = series.iter().map(|s| (s.name.clone(), s.color)).collect();
+    svg + &legend_html(&legend)
+}
+
+// 按照最新的期末价值画饼图, 价值为负或者为 0 的资产不参与
+fn allocation_chart(report: &AnalysisReport) -> String {
+    let slices: Vec<(String, f64)> = sorted_assets(report)
+        .into_iter()
+        .filter_map(|asset| {
+            let summary = report.asset_summary(asset.get_symbol())?;
+            let name = asset
+                .get_alias()
+                .clone()
+                .unwrap_or(asset.get_symbol().clone());
+            summary
+                .value
+                .is_positive()
+                .then_some((name, summary.value.to_f64()))
+        })
+        .collect();
+    let total: f64 = slices.iter().map(|(_, value)| value).sum();
+    if total <= 0.0 {
+        return String::new();
+    }
+
+    let (cx, cy, r) = (110.0, 110.0, 100.0);
+    let mut svg = svg_open(220.0, 220.0);
+    let mut legend = Vec::new();
+    let mut angle = -std::f64::consts::FRAC_PI_2;
+    for (index, (name, value)) in slices.iter().enumerate() {
+        let color = PALETTE[index % PALETTE.len()];
+        let share = value / total;
+        legend.push((format!("{} {:.1}%", name, share * 100.0), color));
+
+        if slices.len() == 1 {
+            svg.push_str(&format!(
+                "<circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"{}\"/>\n",
+                cx, cy, r, color
+            ));
+            break;
+        }
+
+        let end = angle + share * std::f64::consts::TAU;
+        svg.push_str(&format!(
+            "<path d=\"M{},{} L{:.2},{:.2} A{},{} 0 {} 1 {:.2},{:.2} Z\" fill=\"{}\">\
+             <title>{}</title></path>\n",
+            cx,
+            cy,
+            cx + r * angle.cos(),
+            cy + r * angle.sin(),
+            r,
+            r,
+            if share > 0.5 { 1 } else { 0 },
+            cx + r * end.cos(),
+            cy + r * end.sin(),
+            color,
+            escape(name)
+        ));
+        angle = end;
+    }
+    svg.push_str("</svg>\n");
+
+    svg + &legend_html(&legend)
+}
+
+// 每个标的一行, 每个周期一个方块: 绿色完成, 橙色部分完成, 红色没有执行
+fn plan_timeline(periods: &[PlanPeriod]) -> String {
+    let mut rows: Vec<(&str, Vec<&PlanPeriod>)> = Vec::new();
+    for period in periods.iter() {
+        match rows.iter_mut().find(|(symbol, _)| *symbol == period.symbol) {
+            Some((_, row)) => row.push(period),
+            None => rows.push((&period.symbol, vec![period])),
+        }
+    }
+    if rows.is_empty() {
+        return String::new();
+    }
+
+    let (label_width, cell, gap) = (120.0, 14.0, 3.0);
+    let columns = rows.iter().map(|(_, row)| row.len()).max().unwrap_or(0);
+    let width = label_width + columns as f64 * (cell + gap);
+    let height = rows.len() as f64 * (cell + gap) + gap;
+    let mut svg = svg_open(width, height);
+
+    for (row_index, (symbol, row)) in rows.iter().enumerate() {
+        let y = gap + row_index as f64 * (cell + gap);
+        svg.push_str(&format!(
+            "<text x=\"0\" y=\"{:.1}\">{}</text>\n",
+            y + cell - 3.0,
+            escape(symbol)
+        ));
+        for (index, period) in row.iter().enumerate() {
+            let color = if period.is_met() {
+                "#59a14f"
+            } else if period.actual.is_positive() {
+                "#f28e2b"
+            } else {
+                "#e15759"
+            };
+            svg.push_str(&format!(
+                "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{}\" height=\"{}\" fill=\"{}\">\
+                 <title>{} {}/{}</title></rect>\n",
+                label_width + index as f64 * (cell + gap),
+                y,
+                cell,
+                cell,
+                color,
+                period.start,
+                format_money(period.actual),
+                format_money(period.planned)
+            ));
+        }
+    }
+    svg.push_str("</svg>\n");
+
+    svg
+}
+
+fn svg_open(width: f64, height: f64) -> String {
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\">\n",
+        width, height, width, height
+    )
+}
+
+fn legend_html(items: &[(String, &str)]) -> String {
+    let mut html = String::from("<div class=\"legend\">");
+    for (name, color) in items.iter() {
+        html.push_str(&format!(
+            "<span><i style=\"background:{}\"></i>{}</span>",
+            color,
+            escape(name)
+        ));
+    }
+    html.push_str("</div>\n");
+
+    html
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluator::evaluate_str;
+
+    #[test]
+    fn test_render_html_report() {
+        let report = evaluate_str(
+            r#"
+            DEFINE ETF:510300
+            ALIAS "沪深300 <ETF>"
+            END
+            PORTFOLIO "核心" ASSETS ETF:510300, ETF:159915 END
+            PLAN "定投"
+              SCHEDULE MONTHLY 1000 CNY INTO ETF:510300
+              START 2024-01-01
+            END
+            2024-01-02 TRADE ETF:510300 +1000 CNY @ 4
+            2024-01-02 TRADE ETF:159915 +500 CNY
+            2024-03-03 TRADE ETF:510300 +1000 CNY @ 4.2
+            2024-03-31 MARK ETF:510300 VALUE 2150 CNY
+            "#,
+        );
+
+        let html = render(&report, Language::Chinese);
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(!html.contains("<script"));
+        assert!(html.contains("沪深300 &lt;ETF&gt;"));
+        // 组合和两个资产各一张价值图, 再加一张收益图
+        assert_eq!(html.matches("<polyline").count(), 3 * 2 + 3);
+        assert_eq!(html.matches("<path").count(), 2);
+        // 1 月和 3 月完成, 2 月没有执行
+        assert_eq!(html.matches("fill=\"#59a14f\"><title>").count(), 2);
+        assert!(html.contains("<title>2024-02-01 0.00/1000.00</title>"));
+    }
+
+    #[test]
+    fn test_empty_report() {
+        let html = render(&AnalysisReport::new(), Language::English);
+        assert!(html.contains("<h1>Investment Report</h1>"));
+        assert!(!html.contains("<svg"));
+    }
+}
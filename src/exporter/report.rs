@@ -0,0 +1,641 @@
+use crate::decimal::Decimal;
+use crate::evaluator::allocation::{AllocationKey, allocation, benchmark_comparison};
+use crate::evaluator::engine::{AnalysisReport, Asset, Portfolio, Snapshot, Summary};
+use crate::evaluator::filter::{Filter, filter_trades};
+use crate::evaluator::rebalance::{allocation_drift, rebalance_triggers};
+
+// 报表表头使用的语言
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Language {
+    #[default]
+    Chinese,
+    English,
+}
+
+impl Language {
+    pub(crate) fn pick(self, zh: &'static str, en: &'static str) -> &'static str {
+        match self {
+            Language::Chinese => zh,
+            Language::English => en,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Align {
+    Left,
+    Right,
+}
+
+// 和具体输出格式无关的表格, CSV/Markdown 等都从这里渲染
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    pub headers: Vec<String>,
+    pub align: Vec<Align>,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    fn new(columns: Vec<(&str, Align)>) -> Self {
+        Self {
+            headers: columns.iter().map(|(h, _)| h.to_string()).collect(),
+            align: columns.iter().map(|(_, a)| *a).collect(),
+            rows: Vec::new(),
+        }
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut output = csv_line(&self.headers);
+        for row in self.rows.iter() {
+            output.push_str(&csv_line(row));
+        }
+
+        output
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut output = markdown_line(&self.headers);
+        let separators: Vec<String> = self
+            .align
+            .iter()
+            .map(|align| match align {
+                Align::Left => "---".to_string(),
+                Align::Right => "---:".to_string(),
+            })
+            .collect();
+        output.push_str(&format!("|{}|\n", separators.join("|")));
+        for row in self.rows.iter() {
+            output.push_str(&markdown_line(row));
+        }
+
+        output
+    }
+}
+
+// 和 Summary 字段一一对应的列, 表头和字段注释保持一致
+fn summary_columns(language: Language) -> Vec<(&'static str, Align)> {
+    [
+        ("总投入", "Total Purchase"),
+        ("总转出", "Total Sale"),
+        ("转入", "Transfer In"),
+        ("转出", "Transfer Out"),
+        ("期末价值", "Value"),
+        ("累积手续费", "Total Fee"),
+        ("累积税费", "Total Tax"),
+        ("累积收益", "Profit"),
+        ("累积收益(扣除费用前)", "Gross Profit"),
+    ]
+    .iter()
+    .map(|(zh, en)| (language.pick(zh, en), Align::Right))
+    .collect()
+}
+
+fn summary_cells(summary: &Summary) -> Vec<String> {
+    [
+        summary.total_purchase,
+        summary.total_sale,
+        summary.total_transfer_in,
+        summary.total_transfer_out,
+        summary.value,
+        summary.total_fee,
+        summary.total_tax,
+        summary.profit,
+        summary.gross_profit,
+    ]
+    .iter()
+    .map(|value| format_money(*value))
+    .collect()
+}
+
+// 按照标的排序的资产汇总表
+pub fn asset_table(report: &AnalysisReport, language: Language) -> Table {
+    let mut columns = vec![
+        (language.pick("标的", "Symbol"), Align::Left),
+        (language.pick("名称", "Name"), Align::Left),
+    ];
+    columns.extend(summary_columns(language));
+    let mut table = Table::new(columns);
+
+    for asset in sorted_assets(report) {
+        let Some(summary) = report.asset_summary(asset.get_symbol()) else {
+            continue;
+        };
+        let mut row = vec![
+            asset.get_symbol().clone(),
+            asset.get_alias().clone().unwrap_or_default(),
+        ];
+        row.extend(summary_cells(&summary));
+        table.rows.push(row);
+    }
+
+    table
+}
+
+// 组合汇总表, 按照定义的顺序, 最后一行是去掉重复之后整个账本的合计
+pub fn portfolio_table(report: &AnalysisReport, language: Language) -> Table {
+    let mut columns = vec![(language.pick("组合", "Portfolio"), Align::Left)];
+    columns.extend(summary_columns(language));
+    let mut table = Table::new(columns);
+
+    for portfolio in report.portfolios.iter() {
+        let mut row = vec![portfolio.name.clone()];
+        row.extend(summary_cells(&report.portfolio_summary(portfolio)));
+        table.rows.push(row);
+    }
+    let mut row = vec![language.pick("整个账本", "Whole Book").to_string()];
+    row.extend(summary_cells(&report.book_summary()));
+    table.rows.push(row);
+
+    table
+}
+
+// 被多个组合同时包含的资产, 组合的合计会重复计算这些资产
+pub fn shared_asset_table(report: &AnalysisReport, language: Language) -> Table {
+    let mut table = Table::new(vec![
+        (language.pick("标的", "Symbol"), Align::Left),
+        (language.pick("所在组合", "Portfolios"), Align::Left),
+        (language.pick("期末价值", "Value"), Align::Right),
+        (language.pick("占账本比例", "Book Weight"), Align::Right),
+    ]);
+
+    for shared in report.shared_assets() {
+        // 通过子组合包含的资产在组合后面注明是哪个子组合
+        let owners: Vec<String> = shared
+            .owners
+            .iter()
+            .map(|owner| match &owner.via {
+                Some(child) => format!(
+                    "{} ({} {})",
+                    owner.portfolio,
+                    language.pick("经由", "via"),
+                    child
+                ),
+                None => owner.portfolio.clone(),
+            })
+            .collect();
+        table.rows.push(vec![
+            shared.symbol,
+            owners.join(", "),
+            format_money(shared.value),
+            format_percent(shared.book_weight),
+        ]);
+    }
+
+    table
+}
+
+// 单个资产的每日快照, 每天取最后一条
+pub fn snapshot_table(report: &AnalysisReport, symbol: &str, language: Language) -> Table {
+    let mut table = Table::new(vec![
+        (language.pick("日期", "Date"), Align::Left),
+        (language.pick("总投入", "Total Purchase"), Align::Right),
+        (language.pick("总转出", "Total Sale"), Align::Right),
+        (language.pick("转入", "Transfer In"), Align::Right),
+        (language.pick("转出", "Transfer Out"), Align::Right),
+        (language.pick("期末价值", "Value"), Align::Right),
+        (language.pick("持有份额", "Units"), Align::Right),
+        (language.pick("单位价格", "Price"), Align::Right),
+        (language.pick("累积手续费", "Total Fee"), Align::Right),
+        (language.pick("累积税费", "Total Tax"), Align::Right),
+        (language.pick("累积收益", "Profit"), Align::Right),
+    ]);
+
+    let days = report.daily_snapshot.get(symbol).map(Vec::as_slice);
+    for day in days.unwrap_or_default() {
+        let Some(shot) = day.snapshots.last() else {
+            continue;
+        };
+        table.rows.push(snapshot_cells(shot));
+    }
+
+    table
+}
+
+fn snapshot_cells(shot: &Snapshot) -> Vec<String> {
+    vec![
+        shot.date.clone(),
+        format_money(shot.total_purchase),
+        format_money(shot.total_sale),
+        format_money(shot.total_transfer_in),
+        format_money(shot.total_transfer_out),
+        format_money(shot.value),
+        format_units(shot.units),
+        shot.price.map(format_units).unwrap_or_default(),
+        format_money(shot.total_fee),
+        format_money(shot.total_tax),
+        format_money(shot.profit),
+    ]
+}
+
+// 按资产类别, 分类等属性汇总的资产配置
+pub fn allocation_table(report: &AnalysisReport, key: AllocationKey, language: Language) -> Table {
+    let title = match key {
+        AllocationKey::Class => language.pick("资产类别", "Class"),
+        AllocationKey::Category => language.pick("分类", "Category"),
+        AllocationKey::Currency => language.pick("币种", "Currency"),
+        AllocationKey::Risk => language.pick("风险等级", "Risk"),
+    };
+    let mut table = Table::new(vec![
+        (title, Align::Left),
+        (language.pick("期末价值", "Value"), Align::Right),
+        (language.pick("占比", "Weight"), Align::Right),
+        (language.pick("标的", "Symbols"), Align::Left),
+    ]);
+
+    for slice in allocation(report, key) {
+        table.rows.push(vec![
+            slice
+                .label
+                .unwrap_or_else(|| language.pick("未分类", "Unclassified").to_string()),
+            format_money(slice.value),
+            format_percent(slice.weight),
+            slice.symbols.join(" "),
+        ]);
+    }
+
+    table
+}
+
+// 设置了 BENCHMARK 的资产和基准的收益对比
+pub fn benchmark_table(report: &AnalysisReport, language: Language) -> Table {
+    let mut table = Table::new(vec![
+        (language.pick("标的", "Symbol"), Align::Left),
+        (language.pick("基准", "Benchmark"), Align::Left),
+        (language.pick("开始日期", "Start"), Align::Left),
+        (language.pick("结束日期", "End"), Align::Left),
+        (language.pick("收益率", "Return"), Align::Right),
+        (
+            language.pick("基准收益率", "Benchmark Return"),
+            Align::Right,
+        ),
+        (language.pick("超额收益率", "Excess Return"), Align::Right),
+    ]);
+
+    for asset in sorted_assets(report) {
+        let Some(comparison) = benchmark_comparison(report, asset.get_symbol()) else {
+            continue;
+        };
+        table.rows.push(vec![
+            comparison.symbol,
+            comparison.benchmark,
+            comparison.start,
+            comparison.end,
+            format_percent(comparison.asset_return),
+            format_percent(comparison.benchmark_return),
+            format_percent(comparison.excess_return),
+        ]);
+    }
+
+    table
+}
+
+// 组合当前权重和目标权重的偏离
+pub fn drift_table(report: &AnalysisReport, portfolio: &Portfolio, language: Language) -> Table {
+    let mut table = Table::new(vec![
+        (language.pick("标的", "Symbol"), Align::Left),
+        (language.pick("期末价值", "Value"), Align::Right),
+        (language.pick("当前权重", "Weight"), Align::Right),
+        (language.pick("目标权重", "Target"), Align::Right),
+        (language.pick("偏离", "Drift"), Align::Right),
+        (language.pick("相对偏离", "Relative Drift"), Align::Right),
+    ]);
+
+    for drift in allocation_drift(report, portfolio) {
+        table.rows.push(vec![
+            drift.symbol,
+            format_money(drift.value),
+            format_percent(drift.current_weight),
+            format_percent(drift.target_weight),
+            format_percent(drift.absolute_drift),
+            drift.relative_drift.map(format_percent).unwrap_or_default(),
+        ]);
+    }
+
+    table
+}
+
+// 组合的再平衡策略在历史上每一次触发的日期, 以及有没有记录再平衡
+pub fn rebalance_table(
+    report: &AnalysisReport,
+    portfolio: &Portfolio,
+    language: Language,
+) -> Table {
+    let mut table = Table::new(vec![
+        (language.pick("触发日期", "Triggered"), Align::Left),
+        (language.pick("最大偏离", "Max Drift"), Align::Right),
+        (language.pick("再平衡日期", "Rebalanced"), Align::Left),
+    ]);
+
+    for trigger in rebalance_triggers(report, portfolio) {
+        table.rows.push(vec![
+            trigger.date,
+            format_percent(trigger.max_drift),
+            trigger
+                .rebalanced_on
+                .unwrap_or_else(|| language.pick("未再平衡", "Missed").to_string()),
+        ]);
+    }
+
+    table
+}
+
+// 符合条件的交易明细, 比如所有标记了 #bonus 的交易
+pub fn trade_table(report: &AnalysisReport, filter: &Filter, language: Language) -> Table {
+    let mut table = Table::new(vec![
+        (language.pick("日期", "Date"), Align::Left),
+        (language.pick("标的", "Symbol"), Align::Left),
+        (language.pick("金额", "Amount"), Align::Right),
+        (language.pick("费用", "Charges"), Align::Right),
+        (language.pick("份额", "Units"), Align::Right),
+        (language.pick("当前价值", "Value"), Align::Right),
+        (language.pick("收益", "Profit"), Align::Right),
+    ]);
+
+    for trade in filter_trades(report, filter) {
+        table.rows.push(vec![
+            trade.date,
+            trade.symbol,
+            format_money(trade.amount),
+            format_money(trade.charges),
+            trade.units.map(format_units).unwrap_or_default(),
+            trade.value.map(format_money).unwrap_or_default(),
+            trade.profit.map(format_money).unwrap_or_default(),
+        ]);
+    }
+
+    table
+}
+
+// 完整的 Markdown 报表: 资产汇总, 组合汇总, 再加上每个资产的每日快照
+pub fn to_markdown(report: &AnalysisReport, language: Language) -> String {
+    let mut output = String::new();
+
+    output.push_str(&format!("## {}\n\n", language.pick("资产", "Assets")));
+    output.push_str(&asset_table(report, language).to_markdown());
+
+    if !report.portfolios.is_empty() {
+        output.push_str(&format!("\n## {}\n\n", language.pick("组合", "Portfolios")));
+        output.push_str(&portfolio_table(report, language).to_markdown());
+    }
+
+    let shared = shared_asset_table(report, language);
+    if !shared.rows.is_empty() {
+        output.push_str(&format!(
+            "\n## {}\n\n",
+            language.pick("多个组合共享的资产", "Shared Assets")
+        ));
+        output.push_str(&shared.to_markdown());
+    }
+
+    for asset in sorted_assets(report) {
+        let symbol = asset.get_symbol();
+        if !report.daily_snapshot.contains_key(symbol) {
+            continue;
+        }
+        let title = match asset.get_alias() {
+            Some(alias) => format!("{} ({})", alias, symbol),
+            None => symbol.clone(),
+        };
+        output.push_str(&format!("\n### {}\n\n", title));
+        output.push_str(&snapshot_table(report, symbol, language).to_markdown());
+    }
+
+    output
+}
+
+pub(crate) fn sorted_assets(report: &AnalysisReport) -> Vec<&Asset> {
+    let mut assets: Vec<&Asset> = report.assets.iter().collect();
+    assets.sort_by(|a, b| a.get_symbol().cmp(b.get_symbol()));
+    assets
+}
+
+// 只在展示的时候四舍五入到分
+pub(crate) fn format_money(value: Decimal) -> String {
+    format!("{:.2}", value)
+}
+
+pub(crate) fn format_percent(value: f64) -> String {
+    format!("{:.2}%", value * 100.0)
+}
+
+pub(crate) fn format_units(value: Decimal) -> String {
+    let text = format!("{:.4}", value);
+    text.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+fn csv_line(cells: &[String]) -> String {
+    let cells: Vec<String> = cells
+        .iter()
+        .map(|cell| {
+            if cell.contains([',', '"', '\n']) {
+                format!("\"{}\"", cell.replace('"', "\"\""))
+            } else {
+                cell.clone()
+            }
+        })
+        .collect();
+    format!("{}\n", cells.join(","))
+}
+
+fn markdown_line(cells: &[String]) -> String {
+    let cells: Vec<String> = cells.iter().map(|cell| cell.replace('|', "\\|")).collect();
+    format!("| {} |\n", cells.join(" | "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluator::evaluate_str;
+
+    const INPUT: &str = r#"
+        DEFINE ETF:510300
+        ALIAS "沪深300, ETF"
+        END
+
+        2024-01-01 TRADE ETF:510300 +4000 CNY @ 4 FEE 5 CNY
+        2024-01-01 TRADE ETF:159915 +1000 CNY
+        2024-02-01 MARK ETF:510300 VALUE 4200 CNY
+        PORTFOLIO "核心" ASSETS ETF:510300, ETF:159915 END
+    "#;
+
+    #[test]
+    fn test_asset_table_csv() {
+        let report = evaluate_str(INPUT);
+        let csv = asset_table(&report, Language::Chinese).to_csv();
+        let lines: Vec<&str> = csv.lines().collect();
+
+        assert_eq!(
+            lines[0],
+            "标的,名称,总投入,总转出,转入,转出,期末价值,累积手续费,累积税费,累积收益,累积收益(扣除费用前)"
+        );
+        assert_eq!(
+            lines[1],
+            "ETF:159915,,1000.00,0.00,0.00,0.00,1000.00,0.00,0.00,0.00,0.00"
+        );
+        assert_eq!(
+            lines[2],
+            "ETF:510300,\"沪深300, ETF\",4000.00,0.00,0.00,0.00,4200.00,5.00,0.00,195.00,200.00"
+        );
+    }
+
+    #[test]
+    fn test_markdown_report() {
+        let report = evaluate_str(INPUT);
+        let markdown = to_markdown(&report, Language::English);
+
+        assert!(markdown.contains("| Portfolio | Total Purchase |"));
+        assert!(markdown.contains("| 核心 | 5000.00 | 0.00 | 0.00 | 0.00 | 5200.00 |"));
+        assert!(markdown.contains("### 沪深300, ETF (ETF:510300)"));
+        assert!(markdown.contains("| 2024-02-01 | 4000.00 |"));
+        assert!(markdown.contains("|---|---:|"));
+    }
+
+    #[test]
+    fn test_allocation_and_benchmark_tables() {
+        let report = evaluate_str(
+            r#"
+            DEFINE ETF:510300
+              CLASS EQUITY
+              BENCHMARK INDEX:000300
+            END
+            PRICE 2024-01-01 INDEX:000300 4000 CNY
+            PRICE 2024-02-01 INDEX:000300 4200 CNY
+            2024-01-01 TRADE ETF:510300 +3000 CNY
+            2024-01-01 TRADE ETF:159915 +1000 CNY
+            2024-02-01 MARK ETF:510300 VALUE 3300 CNY
+            "#,
+        );
+
+        let csv = allocation_table(&report, AllocationKey::Class, Language::Chinese).to_csv();
+        assert_eq!(
+            csv,
+            "资产类别,期末价值,占比,标的\n\
+             EQUITY,3300.00,76.74%,ETF:510300\n\
+             未分类,1000.00,23.26%,ETF:159915\n"
+        );
+
+        let csv = benchmark_table(&report, Language::English).to_csv();
+        assert!(
+            csv.ends_with("ETF:510300,INDEX:000300,2024-01-01,2024-02-01,10.00%,5.00%,5.00%\n")
+        );
+    }
+
+    #[test]
+    fn test_shared_asset_table() {
+        let report = evaluate_str(
+            r#"
+            PORTFOLIO "长期" ASSETS ETF:510300, ETF:511010 END
+            PORTFOLIO "定投" ASSETS ETF:510300 END
+            2024-01-02 TRADE ETF:510300 +3000 CNY
+            2024-01-02 TRADE ETF:511010 +1000 CNY
+            "#,
+        );
+
+        let table = portfolio_table(&report, Language::English);
+        let totals: Vec<(&str, &str)> = table
+            .rows
+            .iter()
+            .map(|row| (row[0].as_str(), row[5].as_str()))
+            .collect();
+        assert_eq!(
+            totals,
+            vec![
+                ("长期", "4000.00"),
+                ("定投", "3000.00"),
+                ("Whole Book", "4000.00")
+            ]
+        );
+
+        let csv = shared_asset_table(&report, Language::Chinese).to_csv();
+        assert_eq!(
+            csv,
+            "标的,所在组合,期末价值,占账本比例\n\
+             ETF:510300,\"长期, 定投\",3000.00,75.00%\n"
+        );
+    }
+
+    #[test]
+    fn test_shared_asset_through_child() {
+        let report = evaluate_str(
+            r#"
+            PORTFOLIO "P1" ASSETS ETF:510300 END
+            PORTFOLIO "P2" ASSETS "P3", ETF:511010 END
+            PORTFOLIO "P3" ASSETS ETF:510300 END
+            2024-01-02 TRADE ETF:510300 +3000 CNY
+            2024-01-02 TRADE ETF:511010 +1000 CNY
+            "#,
+        );
+
+        let csv = shared_asset_table(&report, Language::English).to_csv();
+        assert_eq!(
+            csv,
+            "Symbol,Portfolios,Value,Book Weight\n\
+             ETF:510300,\"P1, P2 (via P3), P3\",3000.00,75.00%\n"
+        );
+    }
+
+    #[test]
+    fn test_drift_table() {
+        let report = evaluate_str(
+            r#"
+            PORTFOLIO "核心" ASSETS ETF:510300 50%, ETF:159915 50% END
+            2024-01-01 TRADE ETF:510300 +3000 CNY
+            2024-01-01 TRADE ETF:159915 +1000 CNY
+            "#,
+        );
+        let csv = drift_table(&report, &report.portfolios[0], Language::English).to_csv();
+
+        assert_eq!(
+            csv,
+            "Symbol,Value,Weight,Target,Drift,Relative Drift\n\
+             ETF:510300,3000.00,75.00%,50.00%,25.00%,50.00%\n\
+             ETF:159915,1000.00,25.00%,50.00%,-25.00%,-50.00%\n"
+        );
+    }
+
+    #[test]
+    fn test_rebalance_table() {
+        let report = evaluate_str(
+            r#"
+            PORTFOLIO "核心" ASSETS ETF:510300 50%, ETF:159915 50% REBALANCE YEARLY END
+            2023-06-01 TRADE ETF:510300 +3000 CNY
+            2023-06-01 TRADE ETF:159915 +1000 CNY
+            2024-01-05 TRADE ETF:510300 -1000 CNY TAG #rebalance
+            2024-01-05 TRADE ETF:159915 +1000 CNY TAG #rebalance
+            2025-03-01 MARK ETF:510300 VALUE 2200 CNY
+            "#,
+        );
+        let csv = rebalance_table(&report, &report.portfolios[0], Language::Chinese).to_csv();
+
+        assert_eq!(
+            csv,
+            "触发日期,最大偏离,再平衡日期\n\
+             2024-01-01,25.00%,2024-01-05\n\
+             2025-01-01,0.00%,未再平衡\n"
+        );
+    }
+
+    #[test]
+    fn test_tagged_trade_table() {
+        let report = evaluate_str(
+            r#"
+            2024-01-01 TRADE ETF:510300 +4000 CNY @ 4 FEE 5 CNY
+              TAG #bonus
+            2024-01-01 TRADE ETF:159915 +1000 CNY
+            2024-02-01 PRICE ETF:510300 4.2 CNY
+            "#,
+        );
+        let csv = trade_table(
+            &report,
+            &Filter::Tag("bonus".to_string()),
+            Language::English,
+        )
+        .to_csv();
+
+        assert_eq!(
+            csv,
+            "Date,Symbol,Amount,Charges,Units,Value,Profit\n\
+             2024-01-01,ETF:510300,4000.00,5.00,1000,4200.00,195.00\n"
+        );
+    }
+}
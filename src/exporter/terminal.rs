@@ -0,0 +1,294 @@
+use crate::evaluator::engine::AnalysisReport;
+use crate::exporter::report::{
+    Align, Language, Table, asset_table, format_money, portfolio_table, shared_asset_table,
+    sorted_assets,
+};
+
+const UNICODE_BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+const ASCII_BARS: [char; 8] = ['_', '.', '-', '~', '=', '+', '*', '#'];
+
+const GREEN: &str = "\x1b[32m";
+const RED: &str = "\x1b[31m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+#[derive(Debug, Clone)]
+pub struct TerminalOptions {
+    pub language: Language,
+    // 是否输出 ANSI 颜色, 重定向到文件的时候应该关闭
+    pub color: bool,
+    // 终端不支持 Unicode 方块字符的时候用 ASCII 画走势
+    pub unicode: bool,
+    // A 股习惯红涨绿跌, 默认是绿色表示收益
+    pub red_for_gain: bool,
+    // 走势图最多使用的字符数
+    pub sparkline_width: usize,
+}
+
+impl Default for TerminalOptions {
+    fn default() -> Self {
+        Self {
+            language: Language::Chinese,
+            color: true,
+            unicode: true,
+            red_for_gain: false,
+            sparkline_width: 40,
+        }
+    }
+}
+
+// 在终端里查看的文本报表: 资产和组合的汇总表, 以及每个资产期末价值的走势
+pub fn render(report: &AnalysisReport, options: &TerminalOptions) -> String {
+    let language = options.language;
+    let mut output = String::new();
+
+    output.push_str(&render_table(&asset_table(report, language), options));
+    if !report.portfolios.is_empty() {
+        output.push('\n');
+        output.push_str(&render_table(&portfolio_table(report, language), options));
+    }
+
+    // 共享的资产在各个组合里都计算了一次, 提醒不要把组合的价值直接相加
+    let shared = shared_asset_table(report, language);
+    if !shared.rows.is_empty() {
+        output.push('\n');
+        output.push_str(&paint(
+            language.pick(
+                "注意: 下面的资产同时属于多个组合",
+                "Warning: these assets belong to multiple portfolios",
+            ),
+            BOLD,
+            options.color,
+        ));
+        output.push('\n');
+        output.push_str(&render_table(&shared, options));
+    }
+
+    let mut lines: Vec<(String, String, String)> = Vec::new();
+    for asset in sorted_assets(report) {
+        let history = report.asset_history(asset.get_symbol());
+        let Some((_, last)) = history.last() else {
+            continue;
+        };
+        let values: Vec<f64> = history.iter().map(|(_, s)| s.value.to_f64()).collect();
+        let name = asset
+            .get_alias()
+            .clone()
+            .unwrap_or(asset.get_symbol().clone());
+        lines.push((
+            name,
+            sparkline(&values, options.sparkline_width, options.unicode),
+            format_money(last.value),
+        ));
+    }
+
+    if !lines.is_empty() {
+        output.push('\n');
+        output.push_str(&paint(
+            language.pick("期末价值走势", "Value History"),
+            BOLD,
+            options.color,
+        ));
+        output.push('\n');
+
+        let name_width = lines.iter().map(|(n, _, _)| display_width(n)).max();
+        let line_width = lines.iter().map(|(_, l, _)| display_width(l)).max();
+        for (name, line, value) in lines.iter() {
+            output.push_str(&format!(
+                "{}  {}  {}\n",
+                pad(name, name_width.unwrap_or(0), Align::Left),
+                pad(line, line_width.unwrap_or(0), Align::Left),
+                value
+            ));
+        }
+    }
+
+    output
+}
+
+pub fn render_table(table: &Table, options: &TerminalOptions) -> String {
+    let mut widths: Vec<usize> = table.headers.iter().map(|h| display_width(h)).collect();
+    for row in table.rows.iter() {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(display_width(cell));
+        }
+    }
+
+    // 收益列按照正负上色
+    let profit_headers = [
+        options.language.pick("累积收益", "Profit"),
+        options.language.pick("收益", "Profit"),
+        options
+            .language
+            .pick("累积收益(扣除费用前)", "Gross Profit"),
+    ];
+    let colored: Vec<bool> = table
+        .headers
+        .iter()
+        .map(|h| profit_headers.contains(&h.as_str()))
+        .collect();
+
+    let mut output = String::new();
+    let headers: Vec<String> = table
+        .headers
+        .iter()
+        .zip(widths.iter().zip(table.align.iter()))
+        .map(|(h, (width, align))| pad(h, *width, *align))
+        .collect();
+    output.push_str(&paint(headers.join("  ").trim_end(), BOLD, options.color));
+    output.push('\n');
+
+    let rule: Vec<String> = widths.iter().map(|w| "-".repeat(*w)).collect();
+    output.push_str(&rule.join("  "));
+    output.push('\n');
+
+    for row in table.rows.iter() {
+        let cells: Vec<String> = row
+            .iter()
+            .enumerate()
+            .map(|(index, cell)| {
+                let text = pad(cell, widths[index], table.align[index]);
+                if !colored[index] {
+                    return text;
+                }
+                match cell.parse::<f64>() {
+                    Ok(value) if value > 0.0 => paint(&text, gain_color(options), options.color),
+                    Ok(value) if value < 0.0 => paint(&text, loss_color(options), options.color),
+                    _ => text,
+                }
+            })
+            .collect();
+        output.push_str(cells.join("  ").trim_end());
+        output.push('\n');
+    }
+
+    output
+}
+
+// 把一组数值画成一行字符, 数据点多于宽度的时候每段取最后一个值
+pub fn sparkline(values: &[f64], width: usize, unicode: bool) -> String {
+    if values.is_empty() || width == 0 {
+        return String::new();
+    }
+
+    let sampled: Vec<f64> = if values.len() > width {
+        (1..=width)
+            .map(|i| values[i * values.len() / width - 1])
+            .collect()
+    } else {
+        values.to_vec()
+    };
+
+    let bars = if unicode { &UNICODE_BARS } else { &ASCII_BARS };
+    let min = sampled.iter().cloned().fold(f64::MAX, f64::min);
+    let max = sampled.iter().cloned().fold(f64::MIN, f64::max);
+    sampled
+        .iter()
+        .map(|value| {
+            if max <= min {
+                return bars[bars.len() / 2];
+            }
+            let level = (value - min) / (max - min) * (bars.len() - 1) as f64;
+            bars[level.round() as usize]
+        })
+        .collect()
+}
+
+// 终端里的显示宽度, 中日韩文字和全角符号占两列
+pub fn display_width(text: &str) -> usize {
+    text.chars().map(char_width).sum()
+}
+
+fn char_width(ch: char) -> usize {
+    match ch as u32 {
+        0x0300..=0x036F | 0x200B..=0x200F => 0,
+        0x1100..=0x115F
+        | 0x2E80..=0x303E
+        | 0x3041..=0x33FF
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xA000..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x20000..=0x3FFFD => 2,
+        _ => 1,
+    }
+}
+
+fn pad(text: &str, width: usize, align: Align) -> String {
+    let fill = " ".repeat(width.saturating_sub(display_width(text)));
+    match align {
+        Align::Left => format!("{}{}", text, fill),
+        Align::Right => format!("{}{}", fill, text),
+    }
+}
+
+fn gain_color(options: &TerminalOptions) -> &'static str {
+    if options.red_for_gain { RED } else { GREEN }
+}
+
+fn loss_color(options: &TerminalOptions) -> &'static str {
+    if options.red_for_gain { GREEN } else { RED }
+}
+
+fn paint(text: &str, color: &str, enabled: bool) -> String {
+    if enabled {
+        format!("{}{}{}", color, text, RESET)
+    } else {
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluator::evaluate_str;
+
+    #[test]
+    fn test_display_width() {
+        assert_eq!(display_width("沪深300ETF"), 10);
+        assert_eq!(display_width("ETF:510300"), 10);
+        assert_eq!(pad("沪深", 6, Align::Right), "  沪深");
+    }
+
+    #[test]
+    fn test_sparkline() {
+        assert_eq!(sparkline(&[1.0, 2.0, 3.0, 8.0], 10, true), "▁▂▃█");
+        assert_eq!(sparkline(&[5.0, 5.0], 10, false), "==");
+        assert_eq!(sparkline(&[1.0, 2.0, 3.0, 4.0], 2, true), "▁█");
+    }
+
+    #[test]
+    fn test_render_aligned_table() {
+        let report = evaluate_str(
+            r#"
+            DEFINE ETF:510300
+            ALIAS "沪深300ETF"
+            END
+            2024-01-01 TRADE ETF:510300 +1000 CNY
+            2024-01-01 TRADE ETF:159915 +1000 CNY
+            2024-02-01 MARK ETF:510300 VALUE 1100 CNY
+            2024-02-01 MARK ETF:159915 VALUE 900 CNY
+            "#,
+        );
+        let options = TerminalOptions {
+            color: false,
+            ..TerminalOptions::default()
+        };
+
+        let text = render(&report, &options);
+        let lines: Vec<&str> = text.lines().collect();
+        // 中文名称按两列宽度对齐, 数值列右对齐
+        assert!(lines[2].starts_with("ETF:159915              1000.00"));
+        assert!(lines[3].starts_with("ETF:510300  沪深300ETF  1000.00"));
+        assert!(text.contains("沪深300ETF  ▁█  1100.00"));
+        assert!(!text.contains('\x1b'));
+
+        let colored = render(&report, &TerminalOptions::default());
+        assert!(colored.contains("\x1b[32m"));
+        assert!(colored.contains("\x1b[31m"));
+    }
+}
@@ -0,0 +1,71 @@
+pub mod beancount;
+pub mod broker;
+pub mod csv;
+pub mod prices;
+
+use crate::dsl::ast::Symbol;
+
+#[derive(Debug)]
+pub struct ImportError {
+    pub message: String,
+    // 出错的行号, 从 1 开始, 0 表示和具体的行无关
+    pub line: usize,
+}
+
+impl ImportError {
+    pub fn new(message: String, line: usize) -> Self {
+        Self { message, line }
+    }
+}
+
+impl std::fmt::Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.line > 0 {
+            write!(f, "line {}: {}", self.line, self.message)
+        } else {
+            write!(f, "{}", self.message)
+        }
+    }
+}
+
+// 把 2024/01/31 或者 2024-1-31 这样的日期统一成 2024-01-31
+pub(crate) fn normalize_date(value: &str) -> Option<String> {
+    let parts: Vec<&str> = value.trim().split(['-', '/', '.']).collect();
+    if parts.len() != 3 || parts[0].len() != 4 {
+        return None;
+    }
+
+    let year: u32 = parts[0].parse().ok()?;
+    let month: u32 = parts[1].parse().ok()?;
+    let day: u32 = parts[2].parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    Some(format!("{:04}-{:02}-{:02}", year, month, day))
+}
+
+pub(crate) fn parse_symbol(value: &str, line: usize) -> Result<Symbol, ImportError> {
+    match value.split_once(':') {
+        Some((namespace, name)) if !namespace.is_empty() && !name.is_empty() => {
+            Ok(Symbol::new(namespace.to_string(), name.to_string()))
+        }
+        _ => Err(ImportError::new(
+            format!("Invalid symbol, expected NAMESPACE:NAME, found {}", value),
+            line,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_date() {
+        assert_eq!(normalize_date("2024-01-31"), Some("2024-01-31".to_string()));
+        assert_eq!(normalize_date("2024/1/5"), Some("2024-01-05".to_string()));
+        assert_eq!(normalize_date("2024-13-01"), None);
+        assert_eq!(normalize_date("31/01/2024"), None);
+    }
+}
@@ -0,0 +1,551 @@
+use super::{ImportError, normalize_date};
+use crate::decimal::Decimal;
+use crate::dsl::ast::{
+    Action, CASH_NAMESPACE, CashDetails, Charge, Define, Details, Metadata, PriceDetails, Program,
+    Record, SignedAmount, Statement, Symbol, TradeDetails,
+};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+// 支持的 Beancount 子集: open, commodity(name 作为别名), price,
+// 以及带成本 {} 或价格 @ 的交易; 其余的指令会被忽略
+#[derive(Debug, Clone)]
+pub struct BeancountImportOptions {
+    // commodity 里没有 '.' 时使用的命名空间, ETF.510300 会还原成 ETF:510300
+    pub default_namespace: String,
+    // 这个账户下的子账户当作现金账户, 生成 VIA 和 DEPOSIT/WITHDRAW
+    pub cash_root: Option<String>,
+    // 账户名里包含这些关键字的记为手续费
+    pub fee_keywords: Vec<String>,
+    // 账户名里包含这些关键字的记为税费
+    pub tax_keywords: Vec<String>,
+}
+
+impl Default for BeancountImportOptions {
+    fn default() -> Self {
+        Self {
+            default_namespace: "STOCK".to_string(),
+            cash_root: None,
+            fee_keywords: vec!["Fee".to_string(), "Commission".to_string()],
+            tax_keywords: vec!["Tax".to_string()],
+        }
+    }
+}
+
+pub fn read_beancount<P: AsRef<Path>>(
+    path: P,
+    options: &BeancountImportOptions,
+) -> Result<Program, ImportError> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| ImportError::new(format!("Failed to read {}: {}", path.display(), e), 0))?;
+
+    parse_beancount(&text, options)
+}
+
+// 生成的 Program 里先是所有的 DEFINE, 然后按文件顺序排列记录
+pub fn parse_beancount(
+    text: &str,
+    options: &BeancountImportOptions,
+) -> Result<Program, ImportError> {
+    let importer = Importer { options };
+    let mut defines: BTreeMap<String, Define> = BTreeMap::new();
+    let mut records = Vec::new();
+
+    let entries = split_entries(text);
+    for entry in entries.iter() {
+        let line = entry.line;
+        let words = split_words(&entry.header);
+        let Some(date) = words.first().and_then(|w| normalize_date(w)) else {
+            // option, include, plugin 之类没有日期的指令
+            continue;
+        };
+
+        match words.get(1).map(|w| w.as_str()) {
+            Some("commodity") => {
+                let commodity = words
+                    .get(2)
+                    .ok_or_else(|| ImportError::new("Missing commodity".to_string(), line))?;
+                let symbol = importer.symbol(commodity);
+                let define = defines.entry(symbol.to_string()).or_insert(Define {
+                    symbol,
+                    alias: None,
+                    target_return: None,
+                    class: None,
+                    category: None,
+                    benchmark: None,
+                    currency: None,
+                    risk: None,
+                    metadata: Metadata::default(),
+                });
+                if let Some(name) = entry.metadata("name") {
+                    define.alias = Some(name);
+                }
+            }
+            Some("price") => {
+                let (commodity, price, unit) = match &words[2..] {
+                    [commodity, price, unit, ..] => (commodity, price, unit),
+                    _ => {
+                        return Err(ImportError::new(
+                            "Expected: price <commodity> <number> <currency>".to_string(),
+                            line,
+                        ));
+                    }
+                };
+                records.push(Record {
+                    date,
+                    action: Action::Price,
+                    details: Details::Price(PriceDetails {
+                        symbol: importer.symbol(commodity),
+                        price: parse_number(price, line)?,
+                        unit: unit.clone(),
+                    }),
+                    note: None,
+                    metadata: Metadata::default(),
+                });
+            }
+            Some("*") | Some("!") | Some("txn") => {
+                records.extend(importer.transaction(&date, &words[2..], entry)?);
+            }
+            _ => {}
+        }
+    }
+
+    let mut program = Program::new();
+    for define in defines.into_values() {
+        program.add_statement(Statement::Define(define));
+    }
+    for record in records {
+        program.add_statement(Statement::Record(record));
+    }
+
+    Ok(program)
+}
+
+// 一条指令: 不缩进的首行, 以及后面缩进的 posting 和 metadata
+struct Entry {
+    line: usize,
+    header: String,
+    body: Vec<(usize, String)>,
+}
+
+impl Entry {
+    fn metadata(&self, key: &str) -> Option<String> {
+        self.body.iter().find_map(|(_, text)| {
+            let (name, value) = text.split_once(':')?;
+            if name.trim() == key && !value.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+                split_words(value).into_iter().next()
+            } else {
+                None
+            }
+        })
+    }
+
+    fn postings(&self) -> impl Iterator<Item = &(usize, String)> {
+        // metadata 的 key 是小写开头, 账户是大写开头
+        self.body.iter().filter(|(_, text)| {
+            text.starts_with(|c: char| c.is_uppercase() || c == '!' || c == '*')
+        })
+    }
+}
+
+fn split_entries(text: &str) -> Vec<Entry> {
+    let mut entries: Vec<Entry> = Vec::new();
+
+    for (index, raw) in text.lines().enumerate() {
+        let content = strip_comment(raw);
+        if content.trim().is_empty() {
+            continue;
+        }
+
+        if content.starts_with([' ', '\t']) {
+            if let Some(entry) = entries.last_mut() {
+                entry.body.push((index + 1, content.trim().to_string()));
+            }
+        } else {
+            entries.push(Entry {
+                line: index + 1,
+                header: content.trim().to_string(),
+                body: Vec::new(),
+            });
+        }
+    }
+
+    entries
+}
+
+// 去掉 ';' 开始的注释, 字符串里的 ';' 保留
+fn strip_comment(line: &str) -> &str {
+    let mut in_quotes = false;
+    for (index, ch) in line.char_indices() {
+        match ch {
+            '"' => in_quotes = !in_quotes,
+            ';' if !in_quotes => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+// 按空白切分, 双引号里的内容作为一个整体并去掉引号
+fn split_words(text: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut chars = text.trim().chars().peekable();
+
+    while let Some(&ch) = chars.peek() {
+        if ch.is_whitespace() {
+            chars.next();
+        } else if ch == '"' {
+            chars.next();
+            let mut word = String::new();
+            while let Some(ch) = chars.next() {
+                match ch {
+                    '\\' => word.extend(chars.next()),
+                    '"' => break,
+                    ch => word.push(ch),
+                }
+            }
+            words.push(word);
+        } else {
+            let mut word = String::new();
+            while let Some(&ch) = chars.peek() {
+                if ch.is_whitespace() {
+                    break;
+                }
+                word.push(ch);
+                chars.next();
+            }
+            words.push(word);
+        }
+    }
+
+    words
+}
+
+#[derive(Debug, Default)]
+struct Posting {
+    account: String,
+    units: Option<Decimal>,
+    currency: String,
+    // 每份成本或者总成本, 都换算成每份
+    cost: Option<Decimal>,
+    cost_currency: Option<String>,
+    has_cost: bool,
+    price: Option<Decimal>,
+    price_currency: Option<String>,
+}
+
+impl Posting {
+    fn parse(text: &str, line: usize) -> Result<Self, ImportError> {
+        let mut rest = text.trim_start_matches(['!', '*']).trim();
+        let account_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let mut posting = Posting {
+            account: rest[..account_end].to_string(),
+            ..Default::default()
+        };
+        rest = rest[account_end..].trim();
+        if rest.is_empty() {
+            return Ok(posting);
+        }
+
+        // 拆出价格部分
+        let (amount_part, price_part) = match rest.find('@') {
+            Some(index) => (&rest[..index], Some(&rest[index..])),
+            None => (rest, None),
+        };
+
+        // 拆出成本部分
+        let (amount_part, cost_part) = match amount_part.find('{') {
+            Some(index) => (&amount_part[..index], Some(&amount_part[index..])),
+            None => (amount_part, None),
+        };
+
+        let words = split_words(amount_part);
+        if let [number, currency, ..] = words.as_slice() {
+            posting.units = Some(parse_number(number, line)?);
+            posting.currency = currency.clone();
+        }
+        let units = posting.units.unwrap_or_default().abs();
+
+        if let Some(cost) = cost_part {
+            posting.has_cost = true;
+            let is_total = cost.starts_with("{{");
+            let inner = cost.trim_matches(['{', '}', ' ']);
+            let first = inner.split(',').next().unwrap_or("");
+            if let [number, currency, ..] = split_words(first).as_slice() {
+                let number = parse_number(number, line)?;
+                posting.cost = Some(if is_total && units.is_positive() {
+                    number / units
+                } else {
+                    number
+                });
+                posting.cost_currency = Some(currency.clone());
+            }
+        }
+
+        if let Some(price) = price_part {
+            let is_total = price.starts_with("@@");
+            if let [number, currency, ..] = split_words(price.trim_start_matches('@')).as_slice() {
+                let number = parse_number(number, line)?;
+                posting.price = Some(if is_total && units.is_positive() {
+                    number / units
+                } else {
+                    number
+                });
+                posting.price_currency = Some(currency.clone());
+            }
+        }
+
+        Ok(posting)
+    }
+
+    fn is_trade(&self) -> bool {
+        self.units.is_some() && (self.has_cost || self.price.is_some())
+    }
+}
+
+struct Importer<'a> {
+    options: &'a BeancountImportOptions,
+}
+
+impl Importer<'_> {
+    fn symbol(&self, commodity: &str) -> Symbol {
+        match commodity.split_once('.') {
+            Some((namespace, name)) if !namespace.is_empty() && !name.is_empty() => {
+                Symbol::new(namespace.to_string(), name.to_string())
+            }
+            _ => Symbol::new(
+                self.options.default_namespace.clone(),
+                commodity.to_string(),
+            ),
+        }
+    }
+
+    fn cash_symbol(&self, account: &str) -> Option<Symbol> {
+        let root = self.options.cash_root.as_ref()?;
+        let name = account.strip_prefix(root.as_str())?.strip_prefix(':')?;
+        Some(Symbol::new(
+            CASH_NAMESPACE.to_string(),
+            name.replace([':', '-'], "_"),
+        ))
+    }
+
+    fn is_keyword_account(account: &str, keywords: &[String]) -> bool {
+        keywords
+            .iter()
+            .any(|keyword| account.contains(keyword.as_str()))
+    }
+
+    fn transaction(
+        &self,
+        date: &str,
+        texts: &[String],
+        entry: &Entry,
+    ) -> Result<Vec<Record>, ImportError> {
+        let postings = entry
+            .postings()
+            .map(|(line, text)| Posting::parse(text, *line))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let note = entry
+            .metadata("note")
+            .or_else(|| texts.last().filter(|text| !text.is_empty()).cloned());
+
+        let charge = |keywords: &[String]| {
+            postings
+                .iter()
+                .filter(|p| Self::is_keyword_account(&p.account, keywords))
+                .filter_map(|p| {
+                    p.units
+                        .map(|units| Charge::new(units.abs(), p.currency.clone()))
+                })
+                .reduce(|a, b| Charge::new(a.amount + b.amount, a.unit))
+        };
+        let mut fee = charge(&self.options.fee_keywords);
+        let mut tax = charge(&self.options.tax_keywords);
+
+        let account = postings
+            .iter()
+            .filter(|p| !p.is_trade())
+            .find_map(|p| self.cash_symbol(&p.account));
+
+        let mut records = Vec::new();
+        for posting in postings.iter().filter(|p| p.is_trade()) {
+            let units = posting.units.unwrap_or_default();
+            let price = posting.price.or(posting.cost);
+            let unit = posting
+                .cost_currency
+                .clone()
+                .or_else(|| posting.price_currency.clone())
+                .unwrap_or_default();
+
+            // 买入按成本计算金额, 卖出按成交价格计算金额
+            let per_unit = if units.is_positive() {
+                posting.cost.or(posting.price)
+            } else {
+                posting.price.or(posting.cost)
+            };
+            let Some(per_unit) = per_unit else {
+                return Err(ImportError::new(
+                    format!("Cannot determine the amount of {}", posting.currency),
+                    entry.line,
+                ));
+            };
+            // 份额 x 价格 的结果保留到分, 避免出现 4999.999999 这样的金额
+            let amount = (units.abs() * per_unit).round(2).normalize();
+            let signed_amount = if units.is_positive() {
+                SignedAmount::positive(amount)
+            } else {
+                SignedAmount::negative(amount)
+            };
+
+            records.push(Record {
+                date: date.to_string(),
+                action: Action::Trade,
+                details: Details::Trade(TradeDetails {
+                    symbol: self.symbol(&posting.currency),
+                    signed_amount,
+                    unit,
+                    price,
+                    // 费用只挂在第一笔交易上
+                    fee: fee.take(),
+                    tax: tax.take(),
+                    account: account.clone(),
+                }),
+                note: note.clone(),
+                metadata: Metadata::default(),
+            });
+        }
+
+        // 没有交易的时候, 从外部转入或转出现金账户的记为存取款
+        if records.is_empty() {
+            let cash: Vec<(&Posting, Symbol)> = postings
+                .iter()
+                .filter_map(|p| self.cash_symbol(&p.account).map(|symbol| (p, symbol)))
+                .collect();
+            if let [(posting, symbol)] = cash.as_slice()
+                && let Some(units) = posting.units.filter(|u| !u.is_zero())
+            {
+                let action = if units.is_positive() {
+                    Action::Deposit
+                } else {
+                    Action::Withdraw
+                };
+                records.push(Record {
+                    date: date.to_string(),
+                    action,
+                    details: Details::Cash(CashDetails {
+                        symbol: symbol.clone(),
+                        amount: units.abs(),
+                        unit: posting.currency.clone(),
+                    }),
+                    note,
+                    metadata: Metadata::default(),
+                });
+            }
+        }
+
+        Ok(records)
+    }
+}
+
+fn parse_number(value: &str, line: usize) -> Result<Decimal, ImportError> {
+    value
+        .replace(',', "")
+        .parse::<Decimal>()
+        .map_err(|_| ImportError::new(format!("Invalid number: {}", value), line))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsl::formatter::format_program;
+
+    #[test]
+    fn test_parse_beancount_subset() {
+        let text = r#"
+option "title" "家庭账本"
+
+2024-01-01 open Assets:Invest:ETF:510300
+2024-01-01 commodity ETF.510300
+  name: "沪深300ETF"
+
+2024-01-01 * "Deposit"
+  Assets:Cash:Broker  10,000.00 CNY
+  Equity:Contributions
+
+2024-01-02 * "Broker" "首次买入"
+  note: "新年第一笔定投"
+  Assets:Invest:ETF:510300  1250 ETF.510300 {4 CNY}
+  Assets:Cash:Broker  -5005 CNY
+  Expenses:Invest:Fees  5 CNY
+
+2024-02-01 * "卖出" ; 部分止盈
+  Assets:Invest:ETF:510300  -400 ETF.510300 {} @ 5 CNY
+  Assets:Cash:Broker  1998.80 CNY
+  Expenses:Invest:Taxes  1.20 CNY
+  Income:Invest:PnL
+
+2024-02-29 price ETF.510300 4.75 CNY
+2024-03-01 price AAPL 180.5 USD
+"#;
+        let options = BeancountImportOptions {
+            cash_root: Some("Assets:Cash".to_string()),
+            ..Default::default()
+        };
+        let program = parse_beancount(text, &options).unwrap();
+
+        let expected = r#"DEFINE ETF:510300
+  ALIAS "沪深300ETF"
+END
+2024-01-01 DEPOSIT CASH:Broker 10000.00 CNY
+  NOTE "Deposit"
+2024-01-02 TRADE ETF:510300 +5000 CNY @ 4 VIA CASH:Broker FEE 5 CNY
+  NOTE "新年第一笔定投"
+2024-02-01 TRADE ETF:510300 -2000 CNY @ 5 VIA CASH:Broker TAX 1.20 CNY
+  NOTE "卖出"
+2024-02-29 PRICE ETF:510300 4.75 CNY
+2024-03-01 PRICE STOCK:AAPL 180.5 USD
+"#;
+        assert_eq!(format_program(&program), expected);
+    }
+
+    #[test]
+    fn test_total_cost_and_price() {
+        let text = r#"
+2024-01-02 txn "买入"
+  Assets:Invest  300 ETF.159915 {{600 CNY}}
+  Assets:Bank
+2024-03-02 txn "卖出"
+  Assets:Invest  -100 ETF.159915 {} @@ 250 CNY
+  Assets:Bank
+"#;
+        let program = parse_beancount(text, &BeancountImportOptions::default()).unwrap();
+
+        let trades: Vec<&TradeDetails> = program
+            .statements
+            .iter()
+            .filter_map(|(s, _)| match s {
+                Statement::Record(Record {
+                    details: Details::Trade(trade),
+                    ..
+                }) => Some(trade),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[0].signed_amount.to_f64(), 600.0);
+        assert_eq!(trades[0].price, Some(Decimal::from(2)));
+        assert_eq!(trades[0].account, None);
+        assert_eq!(trades[1].signed_amount.to_f64(), -250.0);
+        assert_eq!(trades[1].price, Some(Decimal::from_f64(2.5)));
+    }
+
+    #[test]
+    fn test_sell_without_price_or_cost() {
+        let text = "2024-01-02 * \"x\"\n  Assets:Invest  -1 ETF.159915 {}\n  Assets:Bank\n";
+        let err = parse_beancount(text, &BeancountImportOptions::default()).unwrap_err();
+
+        assert_eq!(err.line, 1);
+    }
+}
@@ -0,0 +1,372 @@
+use super::{ImportError, csv, parse_symbol};
+use crate::decimal::Decimal;
+use crate::dsl::ast::{
+    Action, Charge, Details, Metadata, Program, Record, SignedAmount, Statement, TradeDetails,
+};
+use crate::dsl::formatter::format_record;
+use std::path::Path;
+
+// 券商对账单里买卖方向的表示方式
+#[derive(Debug, Clone, PartialEq)]
+pub enum SignConvention {
+    // 金额为正是买入, 为负是卖出
+    PositiveIsBuy,
+    // 按资金流向记账, 金额为负(资金流出)是买入
+    NegativeIsBuy,
+    // 单独的一列表示方向, 金额都是正数
+    SideColumn {
+        column: String,
+        buy: Vec<String>,
+        sell: Vec<String>,
+    },
+}
+
+// 对账单 CSV 的格式, 列名都按表头匹配(不区分大小写)
+#[derive(Debug, Clone)]
+pub struct BrokerLayout {
+    pub delimiter: char,
+    pub date_column: String,
+    // 支持 %Y %m %d 三个占位符, 例如 "%Y-%m-%d", "%Y%m%d", "%m/%d/%Y"
+    pub date_format: String,
+    pub symbol_column: String,
+    // 对账单里只有代码的时候, 补上的命名空间, 例如 "ETF"
+    pub symbol_namespace: Option<String>,
+    pub amount_column: String,
+    pub sign: SignConvention,
+    pub price_column: Option<String>,
+    pub fee_column: Option<String>,
+    pub tax_column: Option<String>,
+    pub unit_column: Option<String>,
+    // 没有币种列时使用的单位
+    pub unit: String,
+    pub note_column: Option<String>,
+    // 资金结算的现金账户, 生成 VIA 子句
+    pub account: Option<String>,
+}
+
+impl BrokerLayout {
+    pub fn new(date_column: &str, symbol_column: &str, amount_column: &str) -> Self {
+        Self {
+            delimiter: ',',
+            date_column: date_column.to_string(),
+            date_format: "%Y-%m-%d".to_string(),
+            symbol_column: symbol_column.to_string(),
+            symbol_namespace: None,
+            amount_column: amount_column.to_string(),
+            sign: SignConvention::PositiveIsBuy,
+            price_column: None,
+            fee_column: None,
+            tax_column: None,
+            unit_column: None,
+            unit: "CNY".to_string(),
+            note_column: None,
+            account: None,
+        }
+    }
+}
+
+pub fn read_broker_csv<P: AsRef<Path>>(
+    path: P,
+    layout: &BrokerLayout,
+) -> Result<Vec<Record>, ImportError> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| ImportError::new(format!("Failed to read {}: {}", path.display(), e), 0))?;
+
+    parse_broker_csv(&text, layout)
+}
+
+// 对账单的每一行生成一条 TRADE 记录, 金额为 0 的行会被跳过
+pub fn parse_broker_csv(text: &str, layout: &BrokerLayout) -> Result<Vec<Record>, ImportError> {
+    let rows = csv::parse(text, layout.delimiter);
+    let Some((header, rows)) = rows.split_first() else {
+        return Ok(Vec::new());
+    };
+    let reader = RowReader { header, layout };
+
+    let mut records = Vec::new();
+    for row in rows {
+        if let Some(record) = reader.read_row(row)? {
+            records.push(record);
+        }
+    }
+
+    Ok(records)
+}
+
+pub fn to_program(records: Vec<Record>) -> Program {
+    let mut program = Program::new();
+    for record in records {
+        program.add_statement(Statement::Record(record));
+    }
+
+    program
+}
+
+// 生成可以直接追加到账本里的 DSL 文本
+pub fn to_dsl(records: &[Record]) -> String {
+    records
+        .iter()
+        .map(|record| format_record(record) + "\n")
+        .collect()
+}
+
+struct RowReader<'a> {
+    header: &'a csv::CsvRow,
+    layout: &'a BrokerLayout,
+}
+
+impl RowReader<'_> {
+    fn column(&self, name: &str) -> Result<usize, ImportError> {
+        self.header
+            .fields
+            .iter()
+            .position(|field| field.eq_ignore_ascii_case(name))
+            .ok_or_else(|| ImportError::new(format!("Missing column: {}", name), self.header.line))
+    }
+
+    fn field<'r>(&self, row: &'r csv::CsvRow, name: &str) -> Result<&'r str, ImportError> {
+        let index = self.column(name)?;
+        Ok(row.fields.get(index).map_or("", |s| s.as_str()))
+    }
+
+    fn optional_number(
+        &self,
+        row: &csv::CsvRow,
+        name: &Option<String>,
+    ) -> Result<Option<Decimal>, ImportError> {
+        match name {
+            None => Ok(None),
+            Some(name) => {
+                let value = self.field(row, name)?;
+                if value.is_empty() {
+                    Ok(None)
+                } else {
+                    parse_number(value, row.line).map(Some)
+                }
+            }
+        }
+    }
+
+    fn read_row(&self, row: &csv::CsvRow) -> Result<Option<Record>, ImportError> {
+        let layout = self.layout;
+
+        let raw_date = self.field(row, &layout.date_column)?;
+        let date = parse_date(raw_date, &layout.date_format).ok_or_else(|| {
+            ImportError::new(
+                format!("Invalid date {}, expected {}", raw_date, layout.date_format),
+                row.line,
+            )
+        })?;
+
+        let raw_symbol = self.field(row, &layout.symbol_column)?;
+        let symbol = match &layout.symbol_namespace {
+            Some(namespace) if !raw_symbol.contains(':') => {
+                parse_symbol(&format!("{}:{}", namespace, raw_symbol), row.line)?
+            }
+            _ => parse_symbol(raw_symbol, row.line)?,
+        };
+
+        let amount = parse_number(self.field(row, &layout.amount_column)?, row.line)?;
+        if amount.is_zero() {
+            return Ok(None);
+        }
+
+        let buy = match &layout.sign {
+            SignConvention::PositiveIsBuy => amount.is_positive(),
+            SignConvention::NegativeIsBuy => amount.is_negative(),
+            SignConvention::SideColumn { column, buy, sell } => {
+                let side = self.field(row, column)?;
+                if buy.iter().any(|b| b.eq_ignore_ascii_case(side)) {
+                    true
+                } else if sell.iter().any(|s| s.eq_ignore_ascii_case(side)) {
+                    false
+                } else {
+                    return Err(ImportError::new(
+                        format!("Unknown trade side: {}", side),
+                        row.line,
+                    ));
+                }
+            }
+        };
+        let signed_amount = if buy {
+            SignedAmount::positive(amount.abs())
+        } else {
+            SignedAmount::negative(amount.abs())
+        };
+
+        let unit = match &layout.unit_column {
+            Some(column) => Some(self.field(row, column)?.to_string()),
+            None => None,
+        }
+        .filter(|unit| !unit.is_empty())
+        .unwrap_or_else(|| layout.unit.clone());
+
+        let charge = |amount: Option<Decimal>| {
+            amount
+                .filter(|a| !a.is_zero())
+                .map(|a| Charge::new(a.abs(), unit.clone()))
+        };
+        let fee = charge(self.optional_number(row, &layout.fee_column)?);
+        let tax = charge(self.optional_number(row, &layout.tax_column)?);
+
+        let price = self
+            .optional_number(row, &layout.price_column)?
+            .filter(|p| p.is_positive());
+
+        let account = match &layout.account {
+            Some(account) => Some(parse_symbol(account, 0)?),
+            None => None,
+        };
+
+        let note = match &layout.note_column {
+            Some(column) => Some(self.field(row, column)?.to_string()),
+            None => None,
+        }
+        .filter(|note| !note.is_empty());
+
+        Ok(Some(Record {
+            date,
+            action: Action::Trade,
+            details: Details::Trade(TradeDetails {
+                symbol,
+                signed_amount,
+                unit,
+                price,
+                fee,
+                tax,
+                account,
+            }),
+            note,
+            metadata: Metadata::default(),
+        }))
+    }
+}
+
+// 金额里可能带有千分位
+fn parse_number(value: &str, line: usize) -> Result<Decimal, ImportError> {
+    value
+        .replace(',', "")
+        .parse::<Decimal>()
+        .map_err(|_| ImportError::new(format!("Invalid number: {}", value), line))
+}
+
+// 按照 %Y %m %d 格式解析日期, 输出 YYYY-MM-DD
+fn parse_date(value: &str, format: &str) -> Option<String> {
+    let mut chars = value.trim().chars().peekable();
+    let mut format_chars = format.chars();
+    let (mut year, mut month, mut day) = (None, None, None);
+
+    while let Some(ch) = format_chars.next() {
+        if ch == '%' {
+            let (slot, max_len) = match format_chars.next()? {
+                'Y' => (&mut year, 4),
+                'm' => (&mut month, 2),
+                'd' => (&mut day, 2),
+                _ => return None,
+            };
+            let mut digits = String::new();
+            while digits.len() < max_len && chars.peek().is_some_and(|c| c.is_ascii_digit()) {
+                digits.push(chars.next()?);
+            }
+            *slot = Some(digits.parse::<u32>().ok()?);
+        } else if chars.next()? != ch {
+            return None;
+        }
+    }
+
+    if chars.next().is_some() {
+        return None;
+    }
+
+    let (year, month, day) = (year?, month?, day?);
+    if !(1000..=9999).contains(&year) || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    Some(format!("{:04}-{:02}-{:02}", year, month, day))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsl::{Lexer, Parser};
+
+    #[test]
+    fn test_parse_date_formats() {
+        assert_eq!(
+            parse_date("20240105", "%Y%m%d"),
+            Some("2024-01-05".to_string())
+        );
+        assert_eq!(
+            parse_date("1/5/2024", "%m/%d/%Y"),
+            Some("2024-01-05".to_string())
+        );
+        assert_eq!(parse_date("2024-01-05 10:00", "%Y-%m-%d"), None);
+        assert_eq!(parse_date("2024-13-05", "%Y-%m-%d"), None);
+    }
+
+    #[test]
+    fn test_parse_side_column() {
+        let text = "成交日期,证券代码,买卖,成交金额,成交价格,佣金,印花税,备注\n\
+                    20240102,510300,买入,\"5,000.00\",4.56,5,,定投\n\
+                    20240301,510300,卖出,2000,4.65,2,1.2,\n\
+                    20240302,510300,卖出,0,4.65,0,0,撤单\n";
+
+        let mut layout = BrokerLayout::new("成交日期", "证券代码", "成交金额");
+        layout.date_format = "%Y%m%d".to_string();
+        layout.symbol_namespace = Some("ETF".to_string());
+        layout.sign = SignConvention::SideColumn {
+            column: "买卖".to_string(),
+            buy: vec!["买入".to_string()],
+            sell: vec!["卖出".to_string()],
+        };
+        layout.price_column = Some("成交价格".to_string());
+        layout.fee_column = Some("佣金".to_string());
+        layout.tax_column = Some("印花税".to_string());
+        layout.note_column = Some("备注".to_string());
+        layout.account = Some("CASH:BROKER".to_string());
+
+        let records = parse_broker_csv(text, &layout).unwrap();
+        assert_eq!(records.len(), 2);
+
+        let dsl = to_dsl(&records);
+        assert_eq!(
+            dsl,
+            "2024-01-02 TRADE ETF:510300 +5000.00 CNY @ 4.56 VIA CASH:BROKER FEE 5 CNY\n  NOTE \"定投\"\n\
+             2024-03-01 TRADE ETF:510300 -2000 CNY @ 4.65 VIA CASH:BROKER FEE 2 CNY TAX 1.2 CNY\n"
+        );
+
+        let tokens = Lexer::new(&dsl).tokenize().unwrap();
+        let program = Parser::new(tokens).parse().unwrap();
+        assert_eq!(program, to_program(records));
+    }
+
+    #[test]
+    fn test_parse_negative_is_buy() {
+        let text = "date,symbol,amount\n2024-01-02,STOCK:AAPL,-1000\n2024-02-02,STOCK:AAPL,300\n";
+        let mut layout = BrokerLayout::new("date", "symbol", "amount");
+        layout.sign = SignConvention::NegativeIsBuy;
+        layout.unit = "USD".to_string();
+
+        let records = parse_broker_csv(text, &layout).unwrap();
+        match (&records[0].details, &records[1].details) {
+            (Details::Trade(buy), Details::Trade(sell)) => {
+                assert!(buy.buy());
+                assert_eq!(buy.signed_amount.value, 1000.0);
+                assert!(sell.sell());
+                assert_eq!(sell.unit, "USD");
+            }
+            _ => panic!("Expected trade details"),
+        }
+    }
+
+    #[test]
+    fn test_missing_column() {
+        let layout = BrokerLayout::new("date", "symbol", "amount");
+        let err =
+            parse_broker_csv("date,code,amount\n2024-01-02,ETF:510300,1\n", &layout).unwrap_err();
+
+        assert_eq!(err.line, 1);
+    }
+}
@@ -0,0 +1,58 @@
+// 一个够用的 CSV 读取器: 支持双引号包裹的字段和 "" 转义, 不支持字段内换行
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvRow {
+    // 行号, 从 1 开始
+    pub line: usize,
+    pub fields: Vec<String>,
+}
+
+pub fn parse(text: &str, delimiter: char) -> Vec<CsvRow> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| CsvRow {
+            line: index + 1,
+            fields: split_line(line, delimiter),
+        })
+        .collect()
+}
+
+fn split_line(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.trim_end_matches('\r').chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => in_quotes = !in_quotes,
+            ch if ch == delimiter && !in_quotes => {
+                fields.push(field.trim().to_string());
+                field.clear();
+            }
+            ch => field.push(ch),
+        }
+    }
+    fields.push(field.trim().to_string());
+
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_quoted_fields() {
+        let rows = parse("date,name\n\n2024-01-01, \"沪深300, \"\"ETF\"\"\"\n", ',');
+
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1].line, 3);
+        assert_eq!(rows[1].fields, vec!["2024-01-01", "沪深300, \"ETF\""]);
+    }
+}
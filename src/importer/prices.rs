@@ -0,0 +1,153 @@
+use super::{ImportError, csv, normalize_date, parse_symbol};
+use crate::decimal::Decimal;
+use crate::dsl::ast::{Action, Details, Metadata, PriceDetails, Record};
+use std::path::Path;
+
+// CSV 里没有币种列时使用的单位
+pub const DEFAULT_UNIT: &str = "CNY";
+
+// 读取 (date, symbol, close[, unit]) 格式的价格文件, 每一行生成一条 PRICE 记录
+// 第一行如果不是日期就当作表头, 按列名匹配; 否则按照固定的列顺序读取
+pub fn read_price_csv<P: AsRef<Path>>(path: P) -> Result<Vec<Record>, ImportError> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| ImportError::new(format!("Failed to read {}: {}", path.display(), e), 0))?;
+
+    parse_price_csv(&text)
+}
+
+pub fn parse_price_csv(text: &str) -> Result<Vec<Record>, ImportError> {
+    let rows = csv::parse(text, ',');
+    let mut columns = PriceColumns::default();
+
+    let mut rows = rows.iter().peekable();
+    if let Some(first) = rows.peek() {
+        let is_header = first
+            .fields
+            .first()
+            .is_none_or(|field| normalize_date(field).is_none());
+        if is_header {
+            columns = PriceColumns::from_header(first)?;
+            rows.next();
+        }
+    }
+
+    rows.map(|row| columns.read_row(row)).collect()
+}
+
+struct PriceColumns {
+    date: usize,
+    symbol: usize,
+    close: usize,
+    unit: Option<usize>,
+}
+
+impl Default for PriceColumns {
+    fn default() -> Self {
+        Self {
+            date: 0,
+            symbol: 1,
+            close: 2,
+            unit: Some(3),
+        }
+    }
+}
+
+impl PriceColumns {
+    fn from_header(header: &csv::CsvRow) -> Result<Self, ImportError> {
+        let find = |names: &[&str]| {
+            header
+                .fields
+                .iter()
+                .position(|field| names.iter().any(|name| field.eq_ignore_ascii_case(name)))
+        };
+        let require = |names: &[&str]| {
+            find(names).ok_or_else(|| {
+                ImportError::new(format!("Missing column: {}", names[0]), header.line)
+            })
+        };
+
+        Ok(Self {
+            date: require(&["date", "日期"])?,
+            symbol: require(&["symbol", "代码"])?,
+            close: require(&["close", "price", "收盘价"])?,
+            unit: find(&["unit", "currency", "币种"]),
+        })
+    }
+
+    fn read_row(&self, row: &csv::CsvRow) -> Result<Record, ImportError> {
+        let field = |index: usize| {
+            row.fields
+                .get(index)
+                .map(|s| s.as_str())
+                .ok_or_else(|| ImportError::new(format!("Missing column {}", index + 1), row.line))
+        };
+
+        let date = normalize_date(field(self.date)?).ok_or_else(|| {
+            ImportError::new(format!("Invalid date: {}", row.fields[self.date]), row.line)
+        })?;
+        let symbol = parse_symbol(field(self.symbol)?, row.line)?;
+        let price = field(self.close)?.trim().parse::<Decimal>().map_err(|_| {
+            ImportError::new(
+                format!("Invalid price: {}", row.fields[self.close]),
+                row.line,
+            )
+        })?;
+        let unit = self
+            .unit
+            .and_then(|index| row.fields.get(index))
+            .filter(|unit| !unit.is_empty())
+            .cloned()
+            .unwrap_or_else(|| DEFAULT_UNIT.to_string());
+
+        Ok(Record {
+            date,
+            action: Action::Price,
+            details: Details::Price(PriceDetails {
+                symbol,
+                price,
+                unit,
+            }),
+            note: None,
+            metadata: Metadata::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn price_of(record: &Record) -> &PriceDetails {
+        match &record.details {
+            Details::Price(price) => price,
+            _ => panic!("Expected price details"),
+        }
+    }
+
+    #[test]
+    fn test_parse_with_header() {
+        let text = "Symbol,Date,Close\nETF:510300,2024/01/31,4.61\nETF:510300,2024-02-29,4.75\n";
+        let records = parse_price_csv(text).unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].date, "2024-01-31");
+        assert_eq!(price_of(&records[0]).symbol.to_string(), "ETF:510300");
+        assert_eq!(price_of(&records[1]).price, 4.75);
+        assert_eq!(price_of(&records[1]).unit, DEFAULT_UNIT);
+    }
+
+    #[test]
+    fn test_parse_without_header() {
+        let records = parse_price_csv("2024-01-31,STOCK:AAPL,184.4,USD\n").unwrap();
+
+        assert_eq!(price_of(&records[0]).unit, "USD");
+    }
+
+    #[test]
+    fn test_parse_reports_line() {
+        let err = parse_price_csv("date,symbol,close\n2024-01-31,ETF:510300,abc\n").unwrap_err();
+
+        assert_eq!(err.line, 2);
+    }
+}
@@ -1,5 +1,9 @@
+pub mod decimal;
 pub mod dsl;
 pub mod evaluator;
+pub mod exporter;
+pub mod importer;
 
-use dsl::{Parser, Program};
-use evaluator::Engine;
+pub use decimal::Decimal;
+pub use dsl::{Parser, Program};
+pub use evaluator::Engine;